@@ -0,0 +1,397 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    frame::{self, ColorSpace, Frame, SourceFormat},
+    frame_history::FrameHistory,
+    readback, snapshot,
+};
+
+// must match the MODEL_* constants in background.wgsl
+const MODEL_RUNNING_AVERAGE: u32 = 0;
+const MODEL_MEDIAN: u32 = 1;
+
+const WORKGROUP_SIZE: u32 = 16;
+// Rgba32Float
+const BYTES_PER_PIXEL: u32 = 16;
+
+/// How the background is estimated from the incoming frames
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BackgroundModel {
+    /// Exponential running average, where each new frame is given a weight of `alpha`, 0-1
+    RunningAverage { alpha: f32 },
+    /// Per-pixel median of the last `frames` frames, up to `MAX_HISTORY_FRAMES`
+    Median { frames: u32 },
+}
+
+impl Default for BackgroundModel {
+    fn default() -> Self {
+        BackgroundModel::RunningAverage { alpha: 0.05 }
+    }
+}
+
+/// Layout of the params uniform in `background.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BackgroundParams {
+    model: u32,
+    alpha: f32,
+    history_len: u32,
+    _padding: u32,
+}
+
+/// A background image maintained on the GPU that `fs_main` subtracts from each frame.
+///
+/// The background is kept in linear light as `Rgba32Float` so a slow running average doesn't get
+/// stuck on 8 bit rounding.
+pub struct GpuBackground {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    background_texture: wgpu::Texture,
+    background_view: wgpu::TextureView,
+    // the compute pass can't read and write the same texture, so it writes here and the result is
+    // copied back into the background texture
+    output_texture: wgpu::Texture,
+    history: FrameHistory,
+    texture_size: wgpu::Extent3d,
+    workgroups: (u32, u32),
+    /// `None` if background subtraction is off
+    model: Option<BackgroundModel>,
+    /// Stop updating the background from new frames
+    pub frozen: bool,
+    // whether the background holds anything yet. Until it does, the next frame is taken as the
+    // background as-is.
+    initialized: bool,
+}
+
+impl GpuBackground {
+    pub fn new(
+        device: &wgpu::Device,
+        diffuse_texture: &wgpu::Texture,
+        texture_size: wgpu::Extent3d,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("background.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("background.wgsl").into()),
+        });
+
+        let float_texture = |label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                size: texture_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::COPY_DST,
+                label: Some(label),
+                view_formats: &[],
+            })
+        };
+        let background_texture = float_texture("background_texture");
+        let output_texture = float_texture("background_output_texture");
+        let history = FrameHistory::new(
+            device,
+            texture_size,
+            diffuse_texture.format(),
+            "background_history_texture",
+        );
+        let diffuse_texture_view =
+            diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let background_view =
+            background_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let output_view = output_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Background Params Buffer"),
+            contents: bytemuck::bytes_of(&BackgroundParams {
+                model: MODEL_RUNNING_AVERAGE,
+                alpha: 1.0,
+                history_len: 0,
+                _padding: 0,
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                frame::frame_binding_layout_entry(0, wgpu::TextureViewDimension::D2),
+                frame::frame_binding_layout_entry(1, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("background_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&background_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&output_view),
+                },
+            ],
+            label: Some("background_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("background_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout, history.bind_group_layout()],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("background_pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            params_buffer,
+            background_texture,
+            background_view,
+            output_texture,
+            history,
+            texture_size,
+            workgroups: (
+                texture_size.width.div_ceil(WORKGROUP_SIZE),
+                texture_size.height.div_ceil(WORKGROUP_SIZE),
+            ),
+            model: None,
+            frozen: false,
+            initialized: false,
+        }
+    }
+
+    /// View of the background texture for binding in the render pipeline
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.background_view
+    }
+
+    /// Whether the background should be subtracted from frames
+    pub fn enabled(&self) -> bool {
+        self.model.is_some()
+    }
+
+    pub fn model(&self) -> Option<BackgroundModel> {
+        self.model
+    }
+
+    /// Switch to a new model, or turn background subtraction off with `None`. The background is
+    /// rebuilt from scratch unless the model is unchanged or the background is frozen, so a
+    /// loaded or captured background survives switching models. Frames are only kept for the
+    /// median model.
+    pub fn set_model(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        model: Option<BackgroundModel>,
+    ) {
+        if model != self.model {
+            self.model = model;
+            let window = match model {
+                Some(BackgroundModel::Median { frames }) => Some(frames),
+                Some(BackgroundModel::RunningAverage { .. }) | None => None,
+            };
+            self.history.set_window(device, window);
+            if !(self.frozen && self.initialized) {
+                self.reset(queue);
+            }
+        }
+    }
+
+    // a background that is loaded or captured with subtraction off is subtracted with the default
+    // model, frozen so the model doesn't immediately replace it
+    fn ensure_model(&mut self) {
+        if self.model.is_none() {
+            self.model = Some(BackgroundModel::default());
+            self.frozen = true;
+        }
+    }
+
+    /// Throw away the background so it is rebuilt from the upcoming frames
+    pub fn reset(&mut self, queue: &wgpu::Queue) {
+        self.initialized = false;
+        self.history.clear();
+        let zeros = vec![0u8; (self.texture_size.width * self.texture_size.height) as usize * 16];
+        self.write_background(queue, &zeros);
+    }
+
+    /// Replace the background with the frame in the diffuse texture, even if the background is
+    /// frozen. If background subtraction is off it is turned on with the captured background
+    /// frozen.
+    pub fn capture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        diffuse_texture: &wgpu::Texture,
+    ) {
+        self.ensure_model();
+        self.reset(queue);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Background Capture Encoder"),
+        });
+        self.encode_update(queue, &mut encoder, diffuse_texture);
+        queue.submit(Some(encoder.finish()));
+    }
+
+    /// Encode an update of the background with a new frame, which must already have been written
+    /// to the diffuse texture. Does nothing if background subtraction is off or frozen.
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        diffuse_texture: &wgpu::Texture,
+    ) {
+        if self.model.is_none() || self.frozen {
+            return;
+        }
+        self.encode_update(queue, encoder, diffuse_texture);
+    }
+
+    fn encode_update(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        diffuse_texture: &wgpu::Texture,
+    ) {
+        let params = match self.model {
+            None => return,
+            Some(BackgroundModel::RunningAverage { alpha }) => BackgroundParams {
+                model: MODEL_RUNNING_AVERAGE,
+                alpha: if self.initialized {
+                    alpha.clamp(0.0, 1.0)
+                } else {
+                    1.0
+                },
+                history_len: 0,
+                _padding: 0,
+            },
+            Some(BackgroundModel::Median { .. }) => {
+                self.history.push(encoder, diffuse_texture);
+                BackgroundParams {
+                    model: MODEL_MEDIAN,
+                    alpha: 0.0,
+                    history_len: self.history.len(),
+                    _padding: 0,
+                }
+            }
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("background_pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &self.bind_group, &[]);
+            cpass.set_bind_group(1, self.history.bind_group(), &[]);
+            cpass.dispatch_workgroups(self.workgroups.0, self.workgroups.1, 1);
+        }
+        encoder.copy_texture_to_texture(
+            self.output_texture.as_image_copy(),
+            self.background_texture.as_image_copy(),
+            self.texture_size,
+        );
+        self.initialized = true;
+    }
+
+    fn write_background(&self, queue: &wgpu::Queue, data: &[u8]) {
+        queue.write_texture(
+            self.background_texture.as_image_copy(),
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(BYTES_PER_PIXEL * self.texture_size.width),
+                rows_per_image: Some(self.texture_size.height),
+            },
+            self.texture_size,
+        );
+    }
+
+    /// Save the background in the source's format, like an unprocessed snapshot. This waits on
+    /// the GPU.
+    pub fn save(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+        source_format: SourceFormat,
+    ) -> Result<(), String> {
+        let texels = readback::read_texture(device, queue, &self.background_texture)?;
+        let background = Frame::from_raw(
+            self.texture_size.width,
+            self.texture_size.height,
+            bytemuck::pod_collect_to_vec(&texels),
+        )
+        .expect("should be the right size");
+        snapshot::save_image(&frame::to_image(&background, source_format), path)
+    }
+
+    /// Load a background previously saved with `save`, or any image the same size as the frames,
+    /// reading it in the source's `color_space`. The background is frozen afterwards so it isn't
+    /// immediately replaced by new frames, and background subtraction is turned on if it was off.
+    pub fn load(
+        &mut self,
+        queue: &wgpu::Queue,
+        path: &Path,
+        color_space: ColorSpace,
+    ) -> Result<(), String> {
+        let (image, _) = frame::open(path, Some(color_space))?;
+        if image.dimensions() != (self.texture_size.width, self.texture_size.height) {
+            return Err(format!(
+                "background is {}x{} but frames are {}x{}",
+                image.width(),
+                image.height(),
+                self.texture_size.width,
+                self.texture_size.height
+            ));
+        }
+        self.write_background(queue, bytemuck::cast_slice(image.as_raw()));
+        self.ensure_model();
+        self.initialized = true;
+        self.frozen = true;
+        Ok(())
+    }
+}
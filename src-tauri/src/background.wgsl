@@ -0,0 +1,71 @@
+// Background model update
+
+// must match the MODEL_* constants in background.rs
+const MODEL_RUNNING_AVERAGE: u32 = 0u;
+const MODEL_MEDIAN: u32 = 1u;
+// must match MAX_HISTORY_FRAMES in frame_history.rs
+const MAX_HISTORY_FRAMES: u32 = 32u;
+
+// must match BackgroundParams in background.rs
+struct Params {
+    model: u32,
+    alpha: f32,
+    history_len: u32,
+};
+
+@group(0) @binding(0)
+var t_frame: texture_2d<f32>;
+@group(0) @binding(1)
+var t_background: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+@group(0) @binding(3)
+var t_output: texture_storage_2d<rgba32float, write>;
+// the most recent frames, in no particular order, bound by FrameHistory
+@group(1) @binding(0)
+var t_history: texture_2d_array<f32>;
+
+fn median_channel(coords: vec2<u32>, channel: u32) -> f32 {
+    // insertion sort, which is plenty for a few dozen values
+    var values: array<f32, MAX_HISTORY_FRAMES>;
+    for (var i = 0u; i < params.history_len; i++) {
+        let value = textureLoad(t_history, coords, i, 0)[channel];
+        var j = i;
+        while (j > 0u && values[j - 1u] > value) {
+            values[j] = values[j - 1u];
+            j--;
+        }
+        values[j] = value;
+    }
+    let mid = params.history_len / 2u;
+    if (params.history_len % 2u == 0u) {
+        return (values[mid - 1u] + values[mid]) / 2.0;
+    }
+    return values[mid];
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(t_frame);
+    if (global_id.x >= size.x || global_id.y >= size.y) {
+        return;
+    }
+    let coords = global_id.xy;
+
+    var background: vec4<f32>;
+    if (params.model == MODEL_MEDIAN) {
+        background = vec4<f32>(
+            median_channel(coords, 0u),
+            median_channel(coords, 1u),
+            median_channel(coords, 2u),
+            median_channel(coords, 3u),
+        );
+    } else {
+        background = mix(
+            textureLoad(t_background, coords, 0),
+            textureLoad(t_frame, coords, 0),
+            params.alpha,
+        );
+    }
+    textureStore(t_output, coords, background);
+}
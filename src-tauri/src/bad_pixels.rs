@@ -0,0 +1,387 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::frame::{self, ColorSpace, Frame};
+
+// converts a median absolute deviation into a standard deviation for normally distributed noise
+const MAD_TO_SIGMA: f32 = 1.4826;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BadPixelKind {
+    /// Bright in the dark frames
+    Hot,
+    /// Doesn't respond to light, whether stuck dark or at some other level
+    Dead,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BadPixel {
+    pub x: u32,
+    pub y: u32,
+    pub kind: BadPixelKind,
+}
+
+/// Bad pixels of a sensor, saved and loaded as JSON
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BadPixelMap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<BadPixel>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BadPixelDetectionOptions {
+    /// How many standard deviations of the dark frame noise above the typical dark level a pixel
+    /// has to be to count as hot
+    pub hot_sigma: f32,
+    /// Fraction of the typical response from dark to bright frames below which a pixel counts as
+    /// dead
+    pub dead_fraction: f32,
+}
+
+impl Default for BadPixelDetectionOptions {
+    fn default() -> Self {
+        Self {
+            hot_sigma: 6.0,
+            dead_fraction: 0.5,
+        }
+    }
+}
+
+impl BadPixelMap {
+    /// Find hot pixels in the mean of `dark_paths` and dead pixels in the mean of `bright_paths`
+    /// relative to it. Either set of frames can be empty to skip that kind of pixel. Frames are
+    /// read in the source's `color_space`.
+    pub fn detect(
+        dark_paths: &[PathBuf],
+        bright_paths: &[PathBuf],
+        color_space: ColorSpace,
+        options: &BadPixelDetectionOptions,
+    ) -> Result<Self, String> {
+        // the brightest channel catches hot pixels in any channel, and the dimmest catches dead
+        // ones
+        let dark = mean_frame(dark_paths, color_space, |rgb| {
+            rgb[0].max(rgb[1]).max(rgb[2])
+        })?;
+        let bright = mean_frame(bright_paths, color_space, |rgb| {
+            rgb[0].min(rgb[1]).min(rgb[2])
+        })?;
+        let ((width, height), dark, bright) = match (dark, bright) {
+            (None, None) => return Err("no frames to detect bad pixels from".to_string()),
+            (Some((size, dark)), None) => (size, Some(dark), None),
+            (None, Some((size, bright))) => (size, None, Some(bright)),
+            (Some((dark_size, dark)), Some((bright_size, bright))) => {
+                if dark_size != bright_size {
+                    return Err(format!(
+                        "dark frames are {}x{} but bright frames are {}x{}",
+                        dark_size.0, dark_size.1, bright_size.0, bright_size.1
+                    ));
+                }
+                (dark_size, Some(dark), Some(bright))
+            }
+        };
+
+        let mut kinds = vec![None; (width * height) as usize];
+        if let Some(dark) = &dark {
+            let level = median(dark.clone());
+            let mad = median(dark.iter().map(|v| (v - level).abs()).collect());
+            // don't let a perfectly clean dark frame flag every pixel with any noise at all
+            let sigma = (mad * MAD_TO_SIGMA).max(1.0 / 255.0);
+            for (kind, &v) in kinds.iter_mut().zip(dark) {
+                if v > level + options.hot_sigma * sigma {
+                    *kind = Some(BadPixelKind::Hot);
+                }
+            }
+        }
+        if let Some(bright) = &bright {
+            let response: Vec<f32> = match &dark {
+                Some(dark) => bright.iter().zip(dark).map(|(b, d)| b - d).collect(),
+                None => bright.clone(),
+            };
+            let typical_response = median(response.clone());
+            for (kind, &r) in kinds.iter_mut().zip(&response) {
+                if kind.is_none() && r < options.dead_fraction * typical_response {
+                    *kind = Some(BadPixelKind::Dead);
+                }
+            }
+        }
+
+        let pixels = kinds
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, kind)| {
+                Some(BadPixel {
+                    x: i as u32 % width,
+                    y: i as u32 / width,
+                    kind: kind?,
+                })
+            })
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file =
+            File::create(path).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// The bad pixel map in use, if any, along with the neighbours each bad pixel is replaced from
+#[derive(Default)]
+pub struct BadPixels {
+    map: Option<BadPixelMap>,
+    // index of each bad pixel and of its good neighbours
+    replacements: Vec<(usize, Vec<usize>)>,
+}
+
+impl BadPixels {
+    pub fn map(&self) -> Option<&BadPixelMap> {
+        self.map.as_ref()
+    }
+
+    /// Use `map` for frames of the given size, or stop correcting with `None`
+    pub fn set_map(
+        &mut self,
+        map: Option<BadPixelMap>,
+        frame_size: (u32, u32),
+    ) -> Result<(), String> {
+        self.replacements.clear();
+        let Some(map) = map else {
+            self.map = None;
+            return Ok(());
+        };
+        if (map.width, map.height) != frame_size {
+            return Err(format!(
+                "bad pixel map is {}x{} but frames are {}x{}",
+                map.width, map.height, frame_size.0, frame_size.1
+            ));
+        }
+
+        let (width, height) = frame_size;
+        if let Some(pixel) = map
+            .pixels
+            .iter()
+            .find(|pixel| pixel.x >= width || pixel.y >= height)
+        {
+            return Err(format!(
+                "bad pixel ({}, {}) is outside the frame",
+                pixel.x, pixel.y
+            ));
+        }
+        let bad: HashSet<(u32, u32)> = map.pixels.iter().map(|pixel| (pixel.x, pixel.y)).collect();
+        for pixel in &map.pixels {
+            let mut neighbours = Vec::new();
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let x = pixel.x as i64 + dx;
+                    let y = pixel.y as i64 + dy;
+                    if (dx, dy) == (0, 0)
+                        || x < 0
+                        || y < 0
+                        || x >= width as i64
+                        || y >= height as i64
+                        || bad.contains(&(x as u32, y as u32))
+                    {
+                        continue;
+                    }
+                    neighbours.push((y as u32 * width + x as u32) as usize);
+                }
+            }
+            self.replacements
+                .push(((pixel.y * width + pixel.x) as usize, neighbours));
+        }
+        self.map = Some(map);
+        Ok(())
+    }
+
+    /// Replace each bad pixel in `frame` with the per-channel median of its good neighbours
+    pub fn correct(&self, frame: &mut Frame) {
+        let frame: &mut [f32] = frame;
+        let mut values = Vec::with_capacity(8);
+        for (i, neighbours) in &self.replacements {
+            if neighbours.is_empty() {
+                continue;
+            }
+            let mut replacement = [0.0f32; 4];
+            for (channel, replacement) in replacement.iter_mut().enumerate() {
+                values.clear();
+                values.extend(neighbours.iter().map(|&j| frame[4 * j + channel]));
+                values.sort_unstable_by(f32::total_cmp);
+                let mid = values.len() / 2;
+                *replacement = if values.len() % 2 == 0 {
+                    (values[mid - 1] + values[mid]) / 2.0
+                } else {
+                    values[mid]
+                };
+            }
+            let start = 4 * i;
+            frame[start..start + 4].copy_from_slice(&replacement);
+        }
+    }
+}
+
+type FrameSize = (u32, u32);
+
+/// Per-pixel mean of `reduce` applied to the RGB of each frame as thresholds see it, along with
+/// the frame size, or `None` if there are no frames
+fn mean_frame(
+    paths: &[PathBuf],
+    color_space: ColorSpace,
+    reduce: impl Fn([f32; 4]) -> f32,
+) -> Result<Option<(FrameSize, Vec<f32>)>, String> {
+    let mut sum: Option<(FrameSize, Vec<f32>)> = None;
+    for path in paths {
+        let (frame, _) = frame::open(path, Some(color_space))?;
+        let (size, sum) = sum.get_or_insert_with(|| {
+            (
+                frame.dimensions(),
+                vec![0.0; (frame.width() * frame.height()) as usize],
+            )
+        });
+        if frame.dimensions() != *size {
+            return Err(format!(
+                "{} is {}x{} but the frames before it are {}x{}",
+                path.display(),
+                frame.width(),
+                frame.height(),
+                size.0,
+                size.1
+            ));
+        }
+        for (sum, pixel) in sum.iter_mut().zip(frame.pixels()) {
+            *sum += reduce(pixel.0);
+        }
+    }
+    Ok(sum.map(|(size, sum)| {
+        (
+            size,
+            sum.into_iter().map(|v| v / paths.len() as f32).collect(),
+        )
+    }))
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mid = values.len() / 2;
+    *values.select_nth_unstable_by(mid, f32::total_cmp).1
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Luma};
+
+    use super::*;
+
+    const SIZE: u32 = 8;
+    const HOT: (u32, u32) = (2, 3);
+    const DEAD: (u32, u32) = (5, 1);
+    // responds to light, but only weakly
+    const STUCK: (u32, u32) = (6, 6);
+
+    /// Write a stack of 16 bit gray frames with a little noise around `level` and `special`
+    /// pixels at their own levels, returning their paths
+    fn stack(name: &str, level: f32, special: &[((u32, u32), f32)]) -> Vec<PathBuf> {
+        (0..3)
+            .map(|k| {
+                let image = ImageBuffer::from_fn(SIZE, SIZE, |x, y| {
+                    let noise = ((x + 2 * y + k) % 3) as f32 * 0.002 - 0.002;
+                    let v = special
+                        .iter()
+                        .find(|(pixel, _)| *pixel == (x, y))
+                        .map_or(level + noise, |&(_, v)| v);
+                    Luma([(v * u16::MAX as f32).round() as u16])
+                });
+                let path = std::env::temp_dir().join(format!(
+                    "bad-pixels-{}-{}-{}.png",
+                    std::process::id(),
+                    name,
+                    k
+                ));
+                image.save(&path).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn remove(paths: &[PathBuf]) {
+        for path in paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn detected(map: &BadPixelMap) -> Vec<((u32, u32), BadPixelKind)> {
+        map.pixels
+            .iter()
+            .map(|pixel| ((pixel.x, pixel.y), pixel.kind))
+            .collect()
+    }
+
+    #[test]
+    fn finds_hot_and_dead_pixels() {
+        let dark = stack("dark", 0.02, &[(HOT, 0.5)]);
+        let bright = stack("bright", 0.6, &[(HOT, 0.65), (DEAD, 0.03), (STUCK, 0.2)]);
+        let options = BadPixelDetectionOptions::default();
+
+        let map = BadPixelMap::detect(&dark, &bright, ColorSpace::Linear, &options).unwrap();
+        assert_eq!((map.width, map.height), (SIZE, SIZE));
+        assert_eq!(
+            detected(&map),
+            [
+                (DEAD, BadPixelKind::Dead),
+                (HOT, BadPixelKind::Hot),
+                (STUCK, BadPixelKind::Dead),
+            ]
+        );
+
+        let map = BadPixelMap::detect(&dark, &[], ColorSpace::Linear, &options).unwrap();
+        assert_eq!(detected(&map), [(HOT, BadPixelKind::Hot)]);
+        let map = BadPixelMap::detect(&[], &bright, ColorSpace::Linear, &options).unwrap();
+        assert_eq!(
+            detected(&map),
+            [(DEAD, BadPixelKind::Dead), (STUCK, BadPixelKind::Dead)]
+        );
+        remove(&dark);
+        remove(&bright);
+    }
+
+    #[test]
+    fn rejects_missing_and_mismatched_frames() {
+        let options = BadPixelDetectionOptions::default();
+        assert!(BadPixelMap::detect(&[], &[], ColorSpace::Linear, &options).is_err());
+
+        let dark = stack("small-dark", 0.02, &[]);
+        let path = std::env::temp_dir().join(format!("bad-pixels-{}-big.png", std::process::id()));
+        ImageBuffer::from_pixel(SIZE + 1, SIZE, Luma([u16::MAX]))
+            .save(&path)
+            .unwrap();
+        let bright = [path];
+        assert!(BadPixelMap::detect(&dark, &bright, ColorSpace::Linear, &options).is_err());
+        remove(&dark);
+        remove(&bright);
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write as _},
+    path::{Path, PathBuf},
+};
+
+use image::ImageFormat;
+
+use crate::{
+    blobs::Blob,
+    roi::{Roi, RoiStats},
+};
+
+pub const USAGE: &str = "usage: tauri-v2-test --batch <input dir> <output dir> [--config <processing config>] [--calibration <calibration dir>] [--overlays]";
+const STATS_FILE: &str = "stats.csv";
+
+/// Options of batch mode, which processes a sequence of frames without opening a window
+#[derive(Clone, Debug)]
+pub struct BatchArgs {
+    /// Directory of frames, processed in file name order
+    pub input: PathBuf,
+    /// Directory the processed frames and `stats.csv` are written to
+    pub output: PathBuf,
+    /// Processing config saved with `save_processing_config`, or the defaults if `None`
+    pub config: Option<PathBuf>,
+    /// Directory with the dark and flat frames, spatial calibration and colour space, instead of
+    /// the source's
+    pub calibration: Option<PathBuf>,
+    /// Draw the ROIs, blobs and scale bar into the processed frames
+    pub overlays: bool,
+}
+
+impl BatchArgs {
+    /// Parse the arguments after `--batch`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut paths = Vec::new();
+        let mut config = None;
+        let mut calibration = None;
+        let mut overlays = false;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .map(PathBuf::from)
+                    .ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "--config" => config = Some(value()?),
+                "--calibration" => calibration = Some(value()?),
+                "--overlays" => overlays = true,
+                flag if flag.starts_with("--") => {
+                    return Err(format!("unknown option {}\n{}", flag, USAGE))
+                }
+                path => paths.push(PathBuf::from(path)),
+            }
+        }
+        let [input, output] = <[PathBuf; 2]>::try_from(paths)
+            .map_err(|_| format!("expected an input and an output directory\n{}", USAGE))?;
+        Ok(Self {
+            input,
+            output,
+            config,
+            calibration,
+            overlays,
+        })
+    }
+}
+
+/// Image files in `dir`, in file name order
+pub fn sequence_paths(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("failed to read {}: {}", dir.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && ImageFormat::from_path(path).is_ok())
+        .collect();
+    if paths.is_empty() {
+        return Err(format!("no images in {}", dir.display()));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Where the processed version of the frame at `path` is written
+pub fn output_path(output: &Path, path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or(path.as_os_str());
+    output.join(stem).with_extension("png")
+}
+
+/// `stats.csv`, with a row for the whole of each frame followed by one for each ROI
+pub struct StatsWriter {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl StatsWriter {
+    pub fn create(output: &Path) -> Result<Self, String> {
+        let path = output.join(STATS_FILE);
+        let mut writer = BufWriter::new(
+            File::create(&path)
+                .map_err(|e| format!("failed to write {}: {}", path.display(), e))?,
+        );
+        writeln!(
+            writer,
+            "frame_idx,file,min_threshold,max_threshold,roi,area,physical_area,mean,min,max,std_dev,integrated_density,percent_in_band,blobs"
+        )
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        Ok(Self { writer, path })
+    }
+
+    /// Write the rows of one frame, with `None` for the region covering the whole frame.
+    /// `thresholds` and the stats should both be in the source's native units. Blobs are counted
+    /// by centroid, and left empty without blob detection.
+    pub fn write_frame(
+        &mut self,
+        frame_idx: usize,
+        file: &Path,
+        [min_threshold, max_threshold]: [f64; 2],
+        regions: &[(Option<&Roi>, RoiStats)],
+        blobs: Option<&[Blob]>,
+    ) -> Result<(), String> {
+        let file = file
+            .file_name()
+            .unwrap_or(file.as_os_str())
+            .to_string_lossy();
+        // quoted in case of commas
+        let file = format!("\"{}\"", file.replace('"', "\"\""));
+        for (roi, stats) in regions {
+            let label = roi.map_or("frame".to_string(), |roi| roi.id.to_string());
+            let blob_count = blobs.map_or(String::new(), |blobs| {
+                blobs
+                    .iter()
+                    .filter(|blob| {
+                        roi.is_none_or(|roi| roi.shape.contains(blob.centroid[0], blob.centroid[1]))
+                    })
+                    .count()
+                    .to_string()
+            });
+            writeln!(
+                self.writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                frame_idx,
+                file,
+                min_threshold,
+                max_threshold,
+                label,
+                stats.area,
+                // left empty without a spatial calibration
+                stats
+                    .physical_area
+                    .map_or(String::new(), |area| area.to_string()),
+                stats.mean,
+                stats.min,
+                stats.max,
+                stats.std_dev,
+                stats.integrated_density,
+                stats.percent_in_band,
+                blob_count
+            )
+            .map_err(|e| format!("failed to write {}: {}", self.path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .map_err(|e| format!("failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<BatchArgs, String> {
+        BatchArgs::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    fn error(args: &[&str]) -> String {
+        parse(args).unwrap_err()
+    }
+
+    #[test]
+    fn options_need_their_values() {
+        assert!(error(&["in", "out", "--config"]).starts_with("--config needs a value"));
+        assert!(error(&["in", "out", "--calibration"]).starts_with("--calibration needs a value"));
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert!(error(&["in", "out", "--overlay"]).starts_with("unknown option --overlay"));
+    }
+
+    #[test]
+    fn exactly_two_directories_are_needed() {
+        for args in [&["in"][..], &["in", "out", "extra"], &[]] {
+            assert!(error(args).starts_with("expected an input and an output directory"));
+        }
+    }
+
+    #[test]
+    fn options_can_go_anywhere() {
+        for args in [
+            &["--overlays", "in", "--config", "config.json", "out"][..],
+            &["in", "--overlays", "out", "--config", "config.json"],
+            &["in", "out", "--config", "config.json", "--overlays"],
+        ] {
+            let args = parse(args).unwrap();
+            assert_eq!(args.input, PathBuf::from("in"));
+            assert_eq!(args.output, PathBuf::from("out"));
+            assert_eq!(args.config, Some(PathBuf::from("config.json")));
+            assert_eq!(args.calibration, None);
+            assert!(args.overlays);
+        }
+        assert!(!parse(&["in", "out"]).unwrap().overlays);
+    }
+}
@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    frame::Frame,
+    overlay::OverlayLines,
+    spatial_calibration::{PhysicalBlob, SpatialCalibration},
+    threshold::{metric_value, ThresholdBand},
+};
+
+const BLOB_OUTLINE_COLOR: [f32; 4] = [1.0, 0.2, 0.6, 1.0];
+
+// neighbour offsets in clockwise order, starting from east
+const DIRECTIONS: [(i64, i64); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BlobDetectionOptions {
+    /// Smallest blob to keep, in pixels
+    pub min_area: u64,
+    /// Largest blob to keep, in pixels
+    pub max_area: Option<u64>,
+    /// Range of `4π·area/perimeter²` to keep, where 1 is a perfect circle
+    pub min_circularity: f32,
+    pub max_circularity: f32,
+    /// Treat diagonally touching pixels as part of the same blob
+    pub eight_connected: bool,
+    /// Draw the outline of each blob over the video
+    pub draw_outlines: bool,
+}
+
+impl Default for BlobDetectionOptions {
+    fn default() -> Self {
+        Self {
+            min_area: 1,
+            max_area: None,
+            min_circularity: 0.0,
+            max_circularity: 1.0,
+            eight_connected: true,
+            draw_outlines: true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A connected component of the pixels between the min and max thresholds
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    /// Index of the blob within its frame
+    pub label: u32,
+    /// Number of pixels in the blob
+    pub area: u64,
+    /// Center of mass in texture pixel coordinates
+    pub centroid: [f32; 2],
+    pub bounding_box: BoundingBox,
+    /// Mean of the threshold metric over the blob, computed on the 0-100 scale and reported in the
+    /// source's native units like the thresholds
+    pub mean_intensity: f32,
+    /// Length of the traced outer boundary, in pixels
+    pub perimeter: f32,
+    pub circularity: f32,
+    /// Measurements in the units of the spatial calibration, if there is one
+    pub physical: Option<PhysicalBlob>,
+    /// Outer boundary through the centers of the edge pixels
+    #[serde(skip)]
+    pub outline: Vec<[f32; 2]>,
+}
+
+impl Blob {
+    /// The blob with its mean converted from the 0-100 scale by `units_per_percent`
+    pub fn in_units(self, units_per_percent: f64) -> Self {
+        Self {
+            mean_intensity: (self.mean_intensity as f64 * units_per_percent) as f32,
+            ..self
+        }
+    }
+}
+
+/// Label the connected components of the threshold mask of `frame` and keep the ones that pass
+/// the filters in `options`
+pub fn detect_blobs(
+    frame: &Frame,
+    band: ThresholdBand,
+    options: &BlobDetectionOptions,
+    calibration: Option<&SpatialCalibration>,
+) -> Vec<Blob> {
+    let width = frame.width() as i64;
+    let height = frame.height() as i64;
+    let values: Vec<f32> = frame
+        .pixels()
+        .map(|pixel| metric_value(pixel.0, band.metric))
+        .collect();
+
+    // 0 is background or not yet labelled
+    let mut labels = vec![0u32; values.len()];
+    let neighbours: &[(i64, i64)] = if options.eight_connected {
+        &DIRECTIONS
+    } else {
+        &[(1, 0), (0, 1), (-1, 0), (0, -1)]
+    };
+
+    let mut blobs = Vec::new();
+    let mut next_label = 1;
+    let mut stack = Vec::new();
+    for start in 0..values.len() {
+        if labels[start] != 0 || !band.contains(values[start]) {
+            continue;
+        }
+        let label = next_label;
+        next_label += 1;
+
+        // flood fill the component, accumulating its moments as we go
+        let mut area = 0u64;
+        let mut sum_x = 0.0f64;
+        let mut sum_y = 0.0f64;
+        let mut sum_value = 0.0f64;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (i64::MAX, i64::MAX, 0, 0);
+        labels[start] = label;
+        stack.push(start);
+        while let Some(idx) = stack.pop() {
+            let x = idx as i64 % width;
+            let y = idx as i64 / width;
+            area += 1;
+            sum_x += x as f64;
+            sum_y += y as f64;
+            sum_value += values[idx] as f64;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+            for &(dx, dy) in neighbours {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                    continue;
+                }
+                let neighbour = (ny * width + nx) as usize;
+                if labels[neighbour] == 0 && band.contains(values[neighbour]) {
+                    labels[neighbour] = label;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        if area < options.min_area || options.max_area.is_some_and(|max_area| area > max_area) {
+            continue;
+        }
+        let start = (start as i64 % width, start as i64 / width);
+        let (outline, perimeter) = trace_outline(&labels, width, height, label, start);
+        let circularity = if perimeter > 0.0 {
+            (4.0 * std::f32::consts::PI * area as f32 / (perimeter * perimeter)).min(1.0)
+        } else {
+            1.0
+        };
+        if circularity < options.min_circularity || circularity > options.max_circularity {
+            continue;
+        }
+
+        let mut blob = Blob {
+            label: blobs.len() as u32,
+            area,
+            // +0.5 to get to pixel centers
+            centroid: [
+                (sum_x / area as f64) as f32 + 0.5,
+                (sum_y / area as f64) as f32 + 0.5,
+            ],
+            bounding_box: BoundingBox {
+                x: min_x as u32,
+                y: min_y as u32,
+                width: (max_x - min_x + 1) as u32,
+                height: (max_y - min_y + 1) as u32,
+            },
+            mean_intensity: (sum_value / area as f64) as f32,
+            perimeter,
+            circularity,
+            physical: None,
+            outline,
+        };
+        blob.physical = calibration.map(|calibration| calibration.blob(&blob));
+        blobs.push(blob);
+    }
+    blobs
+}
+
+/// Moore neighbour tracing of the outer boundary of the component containing `start`, which must
+/// be its first pixel in raster order. Returns the boundary points and the perimeter, counting
+/// diagonal steps as √2.
+fn trace_outline(
+    labels: &[u32],
+    width: i64,
+    height: i64,
+    label: u32,
+    start: (i64, i64),
+) -> (Vec<[f32; 2]>, f32) {
+    let inside = |(x, y): (i64, i64)| {
+        x >= 0 && y >= 0 && x < width && y < height && labels[(y * width + x) as usize] == label
+    };
+    let direction_of = |from: (i64, i64), to: (i64, i64)| {
+        DIRECTIONS
+            .iter()
+            .position(|&d| d == (to.0 - from.0, to.1 - from.1))
+            .unwrap()
+    };
+
+    let mut points = vec![start];
+    let mut perimeter = 0.0f32;
+    let mut current = start;
+    // start is the first pixel in raster order, so everything from west round to north east of
+    // it is background
+    let mut search_from = 4;
+    let mut first_step = None;
+    // a single pixel has no neighbours to find
+    while let Some(k) = (0..8).find(|k| {
+        let (dx, dy) = DIRECTIONS[(search_from + k) % 8];
+        inside((current.0 + dx, current.1 + dy))
+    }) {
+        let direction = (search_from + k) % 8;
+        let next = (
+            current.0 + DIRECTIONS[direction].0,
+            current.1 + DIRECTIONS[direction].1,
+        );
+        // stop once we're about to retrace the first step
+        if first_step == Some((current, next)) {
+            break;
+        }
+        if first_step.is_none() {
+            first_step = Some((current, next));
+        }
+
+        perimeter += if direction % 2 == 0 {
+            1.0
+        } else {
+            std::f32::consts::SQRT_2
+        };
+        // resume the search around the next pixel from the last background pixel checked
+        let (bx, by) = DIRECTIONS[(search_from + k + 7) % 8];
+        search_from = direction_of(next, (current.0 + bx, current.1 + by));
+        current = next;
+        if current != start {
+            points.push(current);
+        }
+    }
+
+    let outline = points
+        .into_iter()
+        .map(|(x, y)| [x as f32 + 0.5, y as f32 + 0.5])
+        .collect();
+    (outline, perimeter)
+}
+
+pub fn draw_blobs(blobs: &[Blob], lines: &mut OverlayLines) {
+    for blob in blobs {
+        if blob.outline.len() == 1 {
+            // mark single pixel blobs with a short tick so they still show up
+            let [x, y] = blob.outline[0];
+            lines.line([x - 0.5, y], [x + 0.5, y], BLOB_OUTLINE_COLOR);
+        } else {
+            lines.outline(&blob.outline, BLOB_OUTLINE_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::threshold::ThresholdMetric;
+
+    const BAND: ThresholdBand = ThresholdBand {
+        min: 50.0,
+        max: 101.0,
+        metric: ThresholdMetric::Value,
+    };
+
+    /// A frame that is white wherever `rows` has a `#`
+    fn mask(rows: &[&str]) -> Frame {
+        Frame::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            let v = if rows[y as usize].as_bytes()[x as usize] == b'#' {
+                1.0
+            } else {
+                0.0
+            };
+            Rgba([v, v, v, 1.0])
+        })
+    }
+
+    fn areas(frame: &Frame, options: &BlobDetectionOptions) -> Vec<u64> {
+        detect_blobs(frame, BAND, options, None)
+            .iter()
+            .map(|blob| blob.area)
+            .collect()
+    }
+
+    #[test]
+    fn diagonal_pixels_only_join_when_eight_connected() {
+        let frame = mask(&["#..", ".#.", "..#"]);
+        let mut options = BlobDetectionOptions::default();
+        let blobs = detect_blobs(&frame, BAND, &options, None);
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[0].area, 3);
+        assert_eq!(blobs[0].centroid, [1.5, 1.5]);
+        // there and back along the diagonal
+        assert!((blobs[0].perimeter - 4.0 * std::f32::consts::SQRT_2).abs() < 1e-5);
+
+        options.eight_connected = false;
+        assert_eq!(areas(&frame, &options), [1, 1, 1]);
+    }
+
+    #[test]
+    fn area_filters_are_inclusive() {
+        let frame = mask(&["#.##.###", "..##.###", ".....###"]);
+        let options = |min_area, max_area| BlobDetectionOptions {
+            min_area,
+            max_area,
+            ..Default::default()
+        };
+        assert_eq!(areas(&frame, &options(1, None)), [1, 4, 9]);
+        assert_eq!(areas(&frame, &options(4, None)), [4, 9]);
+        assert_eq!(areas(&frame, &options(1, Some(4))), [1, 4]);
+        assert_eq!(areas(&frame, &options(2, Some(8))), [4]);
+    }
+
+    #[test]
+    fn squares_are_circular_and_lines_are_not() {
+        let frame = mask(&[
+            "###.................",
+            "###.................",
+            "###.................",
+            "....................",
+            "####################",
+        ]);
+        let blobs = detect_blobs(&frame, BAND, &BlobDetectionOptions::default(), None);
+        assert_eq!(blobs.len(), 2);
+        let square = &blobs[0];
+        assert_eq!(square.area, 9);
+        assert_eq!(square.perimeter, 8.0);
+        assert_eq!(square.circularity, 1.0);
+        assert_eq!(
+            (square.bounding_box.width, square.bounding_box.height),
+            (3, 3)
+        );
+        let line = &blobs[1];
+        assert_eq!(line.area, 20);
+        assert_eq!(line.perimeter, 38.0);
+        assert!(
+            (line.circularity - 4.0 * std::f32::consts::PI * 20.0 / 38.0f32.powi(2)).abs() < 1e-6
+        );
+
+        let options = BlobDetectionOptions {
+            min_circularity: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(areas(&frame, &options), [9]);
+        let options = BlobDetectionOptions {
+            max_circularity: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(areas(&frame, &options), [20]);
+    }
+}
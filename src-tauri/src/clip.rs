@@ -0,0 +1,235 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use color_quant::NeuQuant;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+// Floyd–Steinberg error diffusion to the right and the row below
+const DITHER_WEIGHTS: [(i64, i64, f32); 4] = [
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0),
+    (0, 1, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ClipFormat {
+    Gif,
+    Apng,
+}
+
+/// Source frames to export, inclusive
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// How GIF frames are reduced to a palette. APNGs keep full colour.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClipOptions {
+    /// Palette size, from 64 to 256
+    pub colors: u32,
+    /// How many pixels NeuQuant skips while learning the palette, from 1 (best) to 30 (fastest)
+    pub quantizer_sample_factor: u32,
+    /// Diffuse the quantisation error to hide banding in gradients
+    pub dither: bool,
+    /// Learn one palette from every frame rather than one per frame, which avoids colours
+    /// flickering between frames
+    pub shared_palette: bool,
+}
+
+impl Default for ClipOptions {
+    fn default() -> Self {
+        Self {
+            colors: 256,
+            quantizer_sample_factor: 10,
+            dither: true,
+            shared_palette: false,
+        }
+    }
+}
+
+impl ClipOptions {
+    pub fn validated(self) -> Result<Self, String> {
+        if !(64..=256).contains(&self.colors) {
+            return Err("palette must have from 64 to 256 colors".to_string());
+        }
+        if !(1..=30).contains(&self.quantizer_sample_factor) {
+            return Err("quantizer sample factor must be from 1 to 30".to_string());
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ClipStage {
+    Rendering,
+    Encoding,
+}
+
+/// Payload of the `clip-progress` events `export_clip` emits
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipProgress {
+    pub stage: ClipStage,
+    pub done: u32,
+    pub total: u32,
+}
+
+/// Encode `frames` as an animation that loops forever, showing each frame for `frame_period`.
+/// `progress` is called with the number of frames encoded so far.
+pub fn encode_clip(
+    frames: &[RgbaImage],
+    path: &Path,
+    format: ClipFormat,
+    options: &ClipOptions,
+    frame_period: Duration,
+    progress: impl FnMut(u32),
+) -> Result<(), String> {
+    let Some(first) = frames.first() else {
+        return Err("no frames to export".to_string());
+    };
+    let file =
+        File::create(path).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    let writer = BufWriter::new(file);
+    match format {
+        ClipFormat::Gif => encode_gif(
+            frames,
+            first.dimensions(),
+            writer,
+            options,
+            frame_period,
+            progress,
+        ),
+        ClipFormat::Apng => encode_apng(frames, first.dimensions(), writer, frame_period, progress),
+    }
+    .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+fn encode_gif(
+    frames: &[RgbaImage],
+    (width, height): (u32, u32),
+    writer: BufWriter<File>,
+    options: &ClipOptions,
+    frame_period: Duration,
+    mut progress: impl FnMut(u32),
+) -> Result<(), String> {
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(format!("{}x{} is too big for a GIF", width, height));
+    };
+    let learn = |pixels: &[u8]| {
+        NeuQuant::new(
+            options.quantizer_sample_factor as i32,
+            options.colors as usize,
+            pixels,
+        )
+    };
+    let shared_palette = options.shared_palette.then(|| {
+        let pixels: Vec<u8> = frames
+            .iter()
+            .flat_map(|frame| frame.as_raw().iter().copied())
+            .collect();
+        learn(&pixels)
+    });
+
+    let global_palette = shared_palette
+        .as_ref()
+        .map(NeuQuant::color_map_rgb)
+        .unwrap_or_default();
+    let mut encoder =
+        gif::Encoder::new(writer, width, height, &global_palette).map_err(|e| e.to_string())?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|e| e.to_string())?;
+    // GIF delays are in hundredths of a second
+    let delay = (frame_period.as_millis() / 10).clamp(1, u16::MAX as u128) as u16;
+    for (i, frame) in frames.iter().enumerate() {
+        let own_palette = shared_palette.is_none().then(|| learn(frame.as_raw()));
+        let quantizer = shared_palette
+            .as_ref()
+            .or(own_palette.as_ref())
+            .expect("should have a palette");
+        encoder
+            .write_frame(&gif::Frame {
+                width,
+                height,
+                delay,
+                palette: own_palette.as_ref().map(NeuQuant::color_map_rgb),
+                buffer: quantize(frame, quantizer, options.dither).into(),
+                ..Default::default()
+            })
+            .map_err(|e| e.to_string())?;
+        progress(i as u32 + 1);
+    }
+    Ok(())
+}
+
+fn encode_apng(
+    frames: &[RgbaImage],
+    (width, height): (u32, u32),
+    writer: BufWriter<File>,
+    frame_period: Duration,
+    mut progress: impl FnMut(u32),
+) -> Result<(), String> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(|e| e.to_string())?;
+    let delay_ms = frame_period.as_millis().min(u16::MAX as u128) as u16;
+    encoder
+        .set_frame_delay(delay_ms, 1000)
+        .map_err(|e| e.to_string())?;
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    for (i, frame) in frames.iter().enumerate() {
+        writer
+            .write_image_data(frame.as_raw())
+            .map_err(|e| e.to_string())?;
+        progress(i as u32 + 1);
+    }
+    writer.finish().map_err(|e| e.to_string())
+}
+
+/// Palette index of each pixel of `frame`, ignoring alpha since GIF frames are opaque
+fn quantize(frame: &RgbaImage, quantizer: &NeuQuant, dither: bool) -> Vec<u8> {
+    if !dither {
+        return frame
+            .pixels()
+            .map(|pixel| quantizer.index_of(&[pixel[0], pixel[1], pixel[2], 255]) as u8)
+            .collect();
+    }
+
+    let (width, height) = (frame.width() as i64, frame.height() as i64);
+    let mut pixels: Vec<[f32; 3]> = frame
+        .pixels()
+        .map(|pixel| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])
+        .collect();
+    let mut indices = Vec::with_capacity(pixels.len());
+    for y in 0..height {
+        for x in 0..width {
+            let wanted = pixels[(y * width + x) as usize];
+            let [r, g, b] = wanted.map(|c| c.round().clamp(0.0, 255.0) as u8);
+            let index = quantizer.index_of(&[r, g, b, 255]);
+            let color = quantizer.lookup(index).expect("should be in the palette");
+            indices.push(index as u8);
+            for (dx, dy, weight) in DITHER_WEIGHTS {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || nx >= width || ny >= height {
+                    continue;
+                }
+                let neighbour = &mut pixels[(ny * width + nx) as usize];
+                for c in 0..3 {
+                    neighbour[c] += (wanted[c] - color[c] as f32) * weight;
+                }
+            }
+        }
+    }
+    indices
+}
@@ -0,0 +1,420 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+// Rg32Float
+const BYTES_PER_PIXEL: u32 = 8;
+
+/// Brown–Conrady lens model, with the camera matrix in pixels
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LensDistortion {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    /// Radial coefficients
+    pub k1: f64,
+    pub k2: f64,
+    #[serde(default)]
+    pub k3: f64,
+    /// Tangential coefficients
+    pub p1: f64,
+    pub p2: f64,
+    /// Size of the images the camera was calibrated with, if different from the frames. The
+    /// camera matrix is scaled to fit the frames.
+    #[serde(default)]
+    pub image_size: Option<[u32; 2]>,
+}
+
+impl LensDistortion {
+    /// Load an OpenCV calibration, as written by `cv::FileStorage` in YAML or JSON, with
+    /// `camera_matrix` and `distortion_coefficients` matrices and optionally `image_width` and
+    /// `image_height`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let fields = if is_json {
+            json_fields(&contents)
+        } else {
+            yaml_fields(&contents)
+        }
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::from_fields(fields).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn from_fields(fields: CalibrationFields) -> Result<Self, String> {
+        let camera_matrix = fields
+            .camera_matrix
+            .ok_or_else(|| "missing camera_matrix".to_string())?;
+        if camera_matrix.len() != 9 {
+            return Err(format!(
+                "camera_matrix has {} values instead of 9",
+                camera_matrix.len()
+            ));
+        }
+        let coefficients = fields
+            .distortion_coefficients
+            .ok_or_else(|| "missing distortion_coefficients".to_string())?;
+        if coefficients.len() < 4 {
+            return Err(format!(
+                "distortion_coefficients has {} values but needs at least 4",
+                coefficients.len()
+            ));
+        }
+        Self {
+            fx: camera_matrix[0],
+            fy: camera_matrix[4],
+            cx: camera_matrix[2],
+            cy: camera_matrix[5],
+            // OpenCV orders them k1, k2, p1, p2[, k3, ...]
+            k1: coefficients[0],
+            k2: coefficients[1],
+            p1: coefficients[2],
+            p2: coefficients[3],
+            k3: coefficients.get(4).copied().unwrap_or(0.0),
+            image_size: fields.image_width.zip(fields.image_height).map(Into::into),
+        }
+        .validated()
+    }
+
+    pub fn validated(self) -> Result<Self, String> {
+        if !(self.fx.is_finite() && self.fy.is_finite() && self.fx != 0.0 && self.fy != 0.0) {
+            return Err("focal lengths must be non-zero".to_string());
+        }
+        if self
+            .image_size
+            .is_some_and(|[width, height]| width == 0 || height == 0)
+        {
+            return Err("image size must be non-zero".to_string());
+        }
+        Ok(self)
+    }
+
+    /// Where the undistorted pixel at `(u, v)` comes from in the distorted frame, in pixels with
+    /// integer pixel centers like OpenCV
+    fn distort(&self, u: f64, v: f64) -> (f64, f64) {
+        let x = (u - self.cx) / self.fx;
+        let y = (v - self.cy) / self.fy;
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        let xd = x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x);
+        let yd = y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y;
+        (xd * self.fx + self.cx, yd * self.fy + self.cy)
+    }
+
+    /// The camera matrix scaled from the calibration image size to the frames
+    fn scaled_to(&self, width: u32, height: u32) -> Self {
+        let Some([image_width, image_height]) = self.image_size else {
+            return *self;
+        };
+        let sx = width as f64 / image_width as f64;
+        let sy = height as f64 / image_height as f64;
+        Self {
+            fx: self.fx * sx,
+            fy: self.fy * sy,
+            // pixel centers are at integers, so their edges move with the scale rather than them
+            cx: (self.cx + 0.5) * sx - 0.5,
+            cy: (self.cy + 0.5) * sy - 0.5,
+            ..*self
+        }
+    }
+}
+
+/// Remap texture `fs_main` samples to undistort each frame. Each texel holds the texture
+/// coordinates in the distorted frame that the same texel of the undistorted frame comes from.
+pub struct LensUndistortion {
+    remap_texture: wgpu::Texture,
+    remap_view: wgpu::TextureView,
+    texture_size: wgpu::Extent3d,
+    distortion: Option<LensDistortion>,
+}
+
+impl LensUndistortion {
+    pub fn new(device: &wgpu::Device, texture_size: wgpu::Extent3d) -> Self {
+        let remap_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rg32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("remap_texture"),
+            view_formats: &[],
+        });
+        let remap_view = remap_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            remap_texture,
+            remap_view,
+            texture_size,
+            distortion: None,
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.remap_view
+    }
+
+    pub fn distortion(&self) -> Option<LensDistortion> {
+        self.distortion
+    }
+
+    /// Use a new lens model, or stop undistorting with `None`
+    pub fn set_distortion(&mut self, queue: &wgpu::Queue, distortion: Option<LensDistortion>) {
+        self.distortion = distortion;
+        let Some(distortion) = distortion else {
+            return;
+        };
+
+        let width = self.texture_size.width;
+        let height = self.texture_size.height;
+        let distortion = distortion.scaled_to(width, height);
+        let mut remap = Vec::with_capacity((width * height * 2) as usize);
+        for v in 0..height {
+            for u in 0..width {
+                let (x, y) = distortion.distort(u as f64, v as f64);
+                remap.push(((x + 0.5) / width as f64) as f32);
+                remap.push(((y + 0.5) / height as f64) as f32);
+            }
+        }
+        queue.write_texture(
+            self.remap_texture.as_image_copy(),
+            bytemuck::cast_slice(&remap),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(BYTES_PER_PIXEL * width),
+                rows_per_image: Some(height),
+            },
+            self.texture_size,
+        );
+    }
+}
+
+#[derive(Default)]
+struct CalibrationFields {
+    camera_matrix: Option<Vec<f64>>,
+    distortion_coefficients: Option<Vec<f64>>,
+    image_width: Option<u32>,
+    image_height: Option<u32>,
+}
+
+fn json_fields(contents: &str) -> Result<CalibrationFields, String> {
+    let value: serde_json::Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    // matrices are either `{"rows": .., "cols": .., "data": [..]}` or plain (nested) arrays
+    let matrix = |key: &str| -> Option<Vec<f64>> {
+        let matrix = value.get(key)?;
+        let data = matrix.get("data").unwrap_or(matrix);
+        let mut values = Vec::new();
+        let mut stack = vec![data];
+        while let Some(value) = stack.pop() {
+            match value {
+                serde_json::Value::Array(items) => stack.extend(items.iter().rev()),
+                value => values.push(value.as_f64()?),
+            }
+        }
+        Some(values)
+    };
+    let size = |key: &str| value.get(key)?.as_u64().map(|size| size as u32);
+    Ok(CalibrationFields {
+        camera_matrix: matrix("camera_matrix"),
+        distortion_coefficients: matrix("distortion_coefficients"),
+        image_width: size("image_width"),
+        image_height: size("image_height"),
+    })
+}
+
+/// Only the parts of OpenCV's YAML dialect that calibrations use, since its `%YAML:1.0` header
+/// and `!!opencv-matrix` tags aren't standard YAML
+fn yaml_fields(contents: &str) -> Result<CalibrationFields, String> {
+    let mut fields = CalibrationFields::default();
+    let lines: Vec<&str> = contents.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        // top level keys aren't indented
+        if line.starts_with([' ', '\t', '%', '-', '#']) {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "camera_matrix" => fields.camera_matrix = Some(yaml_matrix_data(&lines[i + 1..])?),
+            "distortion_coefficients" => {
+                fields.distortion_coefficients = Some(yaml_matrix_data(&lines[i + 1..])?)
+            }
+            "image_width" => fields.image_width = value.parse().ok(),
+            "image_height" => fields.image_height = value.parse().ok(),
+            _ => (),
+        }
+    }
+    Ok(fields)
+}
+
+/// Values of the `data: [...]` of a matrix, which can span several lines
+fn yaml_matrix_data(lines: &[&str]) -> Result<Vec<f64>, String> {
+    let matrix = lines
+        .iter()
+        .take_while(|line| line.starts_with([' ', '\t']))
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let data = matrix
+        .split_once("data:")
+        .and_then(|(_, data)| data.split_once('['))
+        .and_then(|(_, data)| data.split_once(']'))
+        .map(|(data, _)| data)
+        .ok_or_else(|| "matrix is missing its data".to_string())?;
+    data.split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid matrix value {:?}", value.trim()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = "%YAML:1.0
+---
+image_width: 640
+image_height: 480
+camera_matrix: !!opencv-matrix
+   rows: 3
+   cols: 3
+   dt: d
+   data: [ 500., 0., 320., 0., 510., 240.,
+       0., 0., 1. ]
+distortion_coefficients: !!opencv-matrix
+   rows: 1
+   cols: 5
+   dt: d
+   data: [ -0.1, 0.01, 0.001, -0.002, 0.0005 ]
+avg_reprojection_error: 0.2
+";
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9
+    }
+
+    fn lens(k1: f64, p1: f64) -> LensDistortion {
+        LensDistortion {
+            fx: 100.0,
+            fy: 100.0,
+            cx: 50.0,
+            cy: 40.0,
+            k1,
+            k2: 0.0,
+            k3: 0.0,
+            p1,
+            p2: 0.0,
+            image_size: None,
+        }
+    }
+
+    #[test]
+    fn reads_opencv_yaml() {
+        let distortion = LensDistortion::from_fields(yaml_fields(YAML).unwrap()).unwrap();
+        assert_eq!(
+            distortion,
+            LensDistortion {
+                fx: 500.0,
+                fy: 510.0,
+                cx: 320.0,
+                cy: 240.0,
+                k1: -0.1,
+                k2: 0.01,
+                k3: 0.0005,
+                p1: 0.001,
+                p2: -0.002,
+                image_size: Some([640, 480]),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_opencv_json_with_either_matrix_layout() {
+        let json = r#"{
+            "camera_matrix": {"rows": 3, "cols": 3, "dt": "d",
+                "data": [500.0, 0.0, 320.0, 0.0, 510.0, 240.0, 0.0, 0.0, 1.0]},
+            "distortion_coefficients": [[-0.1, 0.01, 0.001, -0.002]]
+        }"#;
+        let distortion = LensDistortion::from_fields(json_fields(json).unwrap()).unwrap();
+        assert_eq!((distortion.fx, distortion.fy), (500.0, 510.0));
+        assert_eq!((distortion.cx, distortion.cy), (320.0, 240.0));
+        assert_eq!(
+            [distortion.k1, distortion.k2, distortion.p1, distortion.p2],
+            [-0.1, 0.01, 0.001, -0.002]
+        );
+        // k3 is optional
+        assert_eq!(distortion.k3, 0.0);
+        assert_eq!(distortion.image_size, None);
+    }
+
+    #[test]
+    fn rejects_incomplete_calibrations() {
+        let fields =
+            |camera_matrix: Option<Vec<f64>>, coefficients: Option<Vec<f64>>| CalibrationFields {
+                camera_matrix,
+                distortion_coefficients: coefficients,
+                ..Default::default()
+            };
+        let matrix = vec![500.0, 0.0, 320.0, 0.0, 510.0, 240.0, 0.0, 0.0, 1.0];
+        let coefficients = vec![-0.1, 0.01, 0.0, 0.0];
+        assert!(LensDistortion::from_fields(fields(None, Some(coefficients.clone()))).is_err());
+        assert!(LensDistortion::from_fields(fields(Some(matrix.clone()), None)).is_err());
+        assert!(LensDistortion::from_fields(fields(
+            Some(matrix[..6].to_vec()),
+            Some(coefficients)
+        ))
+        .is_err());
+        assert!(LensDistortion::from_fields(fields(Some(matrix), Some(vec![0.0; 3]))).is_err());
+        assert!(yaml_fields("camera_matrix: !!opencv-matrix\n   rows: 3\n").is_err());
+        assert!(yaml_fields("camera_matrix: !!opencv-matrix\n   data: [ 1., x ]\n").is_err());
+    }
+
+    #[test]
+    fn distort_is_the_identity_without_coefficients() {
+        let distortion = lens(0.0, 0.0);
+        for (u, v) in [(50.0, 40.0), (0.0, 0.0), (99.0, 79.0)] {
+            assert!(close(distortion.distort(u, v), (u, v)));
+        }
+    }
+
+    #[test]
+    fn distort_applies_radial_and_tangential_terms() {
+        // x = 1, y = 0.5 in normalised coordinates, so r² = 1.25
+        let (u, v) = (150.0, 90.0);
+        let radial = 1.0 + 1.25 * 0.1;
+        assert!(close(
+            lens(0.1, 0.0).distort(u, v),
+            (50.0 + 100.0 * radial, 40.0 + 50.0 * radial)
+        ));
+        // p1 adds 2·p1·x·y to x and p1·(r² + 2y²) to y
+        assert!(close(
+            lens(0.0, 0.01).distort(u, v),
+            (150.0 + 100.0 * 0.01, 90.0 + 100.0 * 0.01 * 1.75)
+        ));
+        // the center never moves
+        assert!(close(lens(0.3, 0.01).distort(50.0, 40.0), (50.0, 40.0)));
+    }
+
+    #[test]
+    fn camera_matrix_scales_with_the_frames() {
+        let distortion = LensDistortion {
+            image_size: Some([200, 160]),
+            ..lens(0.1, 0.0)
+        };
+        let scaled = distortion.scaled_to(100, 80);
+        assert_eq!((scaled.fx, scaled.fy), (50.0, 50.0));
+        assert_eq!((scaled.cx, scaled.cy), (24.75, 19.75));
+        assert_eq!(scaled.k1, 0.1);
+        assert_eq!(lens(0.1, 0.0).scaled_to(100, 80), lens(0.1, 0.0));
+    }
+}
@@ -0,0 +1,374 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{frame, threshold::ThresholdMetric};
+
+// must match the OP_* constants in filters.wgsl
+const OP_GAUSSIAN: u32 = 0;
+const OP_UNSHARP: u32 = 1;
+const OP_MEDIAN: u32 = 2;
+const OP_SOBEL: u32 = 3;
+const OP_LAPLACIAN: u32 = 4;
+const OP_ERODE: u32 = 5;
+const OP_DILATE: u32 = 6;
+
+const WORKGROUP_SIZE: u32 = 16;
+// kernels are cut off at 3 sigma, up to this many pixels either side
+const MAX_KERNEL_RADIUS: u32 = 15;
+// the unsharp mask blur isn't separable, so it gets a smaller window
+const MAX_UNSHARP_RADIUS: u32 = 7;
+const MAX_MORPHOLOGY_RADIUS: u32 = 7;
+// the median window is sorted in a fixed size array in filters.wgsl
+const MAX_MEDIAN_SIZE: u32 = 5;
+
+/// One stage of the spatial filter chain, applied in linear light before thresholding
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum FilterStage {
+    GaussianBlur {
+        sigma: f32,
+    },
+    /// Add `amount` times the difference from a Gaussian blur with the given sigma
+    UnsharpMask {
+        sigma: f32,
+        amount: f32,
+    },
+    /// Per-channel median over a `size`×`size` window, where `size` is 3 or 5
+    Median {
+        size: u32,
+    },
+    /// Per-channel Sobel gradient magnitude
+    Sobel,
+    /// Per-channel magnitude of the 4-neighbour Laplacian
+    Laplacian,
+    /// Remove specks from the threshold mask smaller than a `2·radius + 1` square. This is done
+    /// on the threshold metric, so it opens the region above the min threshold.
+    Open {
+        radius: u32,
+    },
+    /// Fill holes in the threshold mask smaller than a `2·radius + 1` square
+    Close {
+        radius: u32,
+    },
+}
+
+impl FilterStage {
+    fn validate(&self) -> Result<(), String> {
+        match *self {
+            FilterStage::GaussianBlur { sigma } | FilterStage::UnsharpMask { sigma, .. }
+                if sigma.is_nan() || sigma <= 0.0 =>
+            {
+                Err(format!("sigma must be positive, got {}", sigma))
+            }
+            FilterStage::Median { size } if size != 3 && size != MAX_MEDIAN_SIZE => Err(format!(
+                "median size must be 3 or {}, got {}",
+                MAX_MEDIAN_SIZE, size
+            )),
+            FilterStage::Open { radius } | FilterStage::Close { radius }
+                if radius == 0 || radius > MAX_MORPHOLOGY_RADIUS =>
+            {
+                Err(format!(
+                    "radius must be between 1 and {}, got {}",
+                    MAX_MORPHOLOGY_RADIUS, radius
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The compute passes that make up this stage
+    fn passes(&self) -> Vec<FilterParams> {
+        let kernel_radius = |sigma: f32, max_radius| ((3.0 * sigma).ceil() as u32).min(max_radius);
+        match *self {
+            FilterStage::GaussianBlur { sigma } => {
+                // separable, so blur rows then columns
+                let radius = kernel_radius(sigma, MAX_KERNEL_RADIUS);
+                vec![
+                    FilterParams::new(OP_GAUSSIAN, radius, sigma, 0.0, [1, 0]),
+                    FilterParams::new(OP_GAUSSIAN, radius, sigma, 0.0, [0, 1]),
+                ]
+            }
+            FilterStage::UnsharpMask { sigma, amount } => {
+                let radius = kernel_radius(sigma, MAX_UNSHARP_RADIUS);
+                vec![FilterParams::new(OP_UNSHARP, radius, sigma, amount, [0, 0])]
+            }
+            FilterStage::Median { size } => {
+                vec![FilterParams::new(OP_MEDIAN, size / 2, 0.0, 0.0, [0, 0])]
+            }
+            FilterStage::Sobel => vec![FilterParams::new(OP_SOBEL, 1, 0.0, 0.0, [0, 0])],
+            FilterStage::Laplacian => vec![FilterParams::new(OP_LAPLACIAN, 1, 0.0, 0.0, [0, 0])],
+            FilterStage::Open { radius } => vec![
+                FilterParams::new(OP_ERODE, radius, 0.0, 0.0, [0, 0]),
+                FilterParams::new(OP_DILATE, radius, 0.0, 0.0, [0, 0]),
+            ],
+            FilterStage::Close { radius } => vec![
+                FilterParams::new(OP_DILATE, radius, 0.0, 0.0, [0, 0]),
+                FilterParams::new(OP_ERODE, radius, 0.0, 0.0, [0, 0]),
+            ],
+        }
+    }
+}
+
+/// Layout of the params uniform in `filters.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterParams {
+    op: u32,
+    radius: i32,
+    sigma: f32,
+    amount: f32,
+    direction: [i32; 2],
+    metric: u32,
+    _padding: u32,
+}
+
+impl FilterParams {
+    fn new(op: u32, radius: u32, sigma: f32, amount: f32, direction: [i32; 2]) -> Self {
+        Self {
+            op,
+            radius: radius as i32,
+            sigma,
+            amount,
+            direction,
+            metric: ThresholdMetric::default() as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// Where the filter chain takes its frames from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FilterSource {
+    Diffuse = 0,
+    Temporal = 1,
+}
+
+struct FilterPass {
+    params: FilterParams,
+    params_buffer: wgpu::Buffer,
+    // the first pass reads from whichever source is in use, so it has one bind group for each,
+    // indexed by `FilterSource`. The rest read the output of the pass before.
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+/// An ordered chain of spatial filters run as compute passes that ping-pong between two textures
+/// in the `frame::intermediate_format` of the frames. Each pass has its own params uniform buffer.
+pub struct GpuFilterChain {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    source_views: [wgpu::TextureView; 2],
+    textures: [wgpu::Texture; 2],
+    views: [wgpu::TextureView; 2],
+    texture_size: wgpu::Extent3d,
+    workgroups: (u32, u32),
+    stages: Vec<FilterStage>,
+    passes: Vec<FilterPass>,
+}
+
+impl GpuFilterChain {
+    pub fn new(
+        device: &wgpu::Device,
+        diffuse_texture: &wgpu::Texture,
+        temporal_texture: &wgpu::Texture,
+        texture_size: wgpu::Extent3d,
+    ) -> Self {
+        let format = frame::intermediate_format(diffuse_texture.format());
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("filters.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                frame::with_output_format(
+                    concat!(include_str!("metric.wgsl"), include_str!("filters.wgsl")),
+                    format,
+                )
+                .into(),
+            ),
+        });
+
+        let textures = ["filter_texture_0", "filter_texture_1"].map(|label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                size: texture_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                label: Some(label),
+                view_formats: &[],
+            })
+        });
+        let views = textures
+            .each_ref()
+            .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        let source_views = [diffuse_texture, temporal_texture]
+            .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()));
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                frame::frame_binding_layout_entry(0, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("filter_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("filter_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("filter_pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            source_views,
+            textures,
+            views,
+            texture_size,
+            workgroups: (
+                texture_size.width.div_ceil(WORKGROUP_SIZE),
+                texture_size.height.div_ceil(WORKGROUP_SIZE),
+            ),
+            stages: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn stages(&self) -> &[FilterStage] {
+        &self.stages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Replace the whole chain. Nothing changes if any stage is invalid.
+    pub fn set_stages(
+        &mut self,
+        device: &wgpu::Device,
+        stages: Vec<FilterStage>,
+    ) -> Result<(), String> {
+        for stage in &stages {
+            stage.validate()?;
+        }
+
+        let all_params: Vec<FilterParams> = stages.iter().flat_map(FilterStage::passes).collect();
+        self.passes = all_params
+            .into_iter()
+            .enumerate()
+            .map(|(i, params)| {
+                let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Filter Params Buffer"),
+                    size: std::mem::size_of::<FilterParams>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let inputs: Vec<&wgpu::TextureView> = if i == 0 {
+                    self.source_views.iter().collect()
+                } else {
+                    vec![&self.views[(i - 1) % 2]]
+                };
+                let bind_groups = inputs
+                    .into_iter()
+                    .map(|input| {
+                        device.create_bind_group(&wgpu::BindGroupDescriptor {
+                            layout: &self.bind_group_layout,
+                            entries: &[
+                                wgpu::BindGroupEntry {
+                                    binding: 0,
+                                    resource: wgpu::BindingResource::TextureView(input),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 1,
+                                    resource: params_buffer.as_entire_binding(),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 2,
+                                    resource: wgpu::BindingResource::TextureView(
+                                        &self.views[i % 2],
+                                    ),
+                                },
+                            ],
+                            label: Some("filter_bind_group"),
+                        })
+                    })
+                    .collect();
+                FilterPass {
+                    params,
+                    params_buffer,
+                    bind_groups,
+                }
+            })
+            .collect();
+        self.stages = stages;
+        Ok(())
+    }
+
+    /// Encode the chain over the frame from `source`, copying the result into `output`. Returns
+    /// false without encoding anything if the chain is empty.
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        source: FilterSource,
+        metric: ThresholdMetric,
+        output: &wgpu::Texture,
+    ) -> bool {
+        if self.passes.is_empty() {
+            return false;
+        }
+        for (i, pass) in self.passes.iter().enumerate() {
+            // the metric can change at any time, so refresh it every frame
+            let params = FilterParams {
+                metric: metric as u32,
+                ..pass.params
+            };
+            queue.write_buffer(&pass.params_buffer, 0, bytemuck::bytes_of(&params));
+
+            let bind_group = if i == 0 {
+                &pass.bind_groups[source as usize]
+            } else {
+                &pass.bind_groups[0]
+            };
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("filter_pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, bind_group, &[]);
+            cpass.dispatch_workgroups(self.workgroups.0, self.workgroups.1, 1);
+        }
+        encoder.copy_texture_to_texture(
+            self.textures[(self.passes.len() - 1) % 2].as_image_copy(),
+            output.as_image_copy(),
+            self.texture_size,
+        );
+        true
+    }
+}
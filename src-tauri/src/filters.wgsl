@@ -0,0 +1,169 @@
+// Spatial filter pass, appended to metric.wgsl
+
+// must match the OP_* constants in filters.rs
+const OP_GAUSSIAN: u32 = 0u;
+const OP_UNSHARP: u32 = 1u;
+const OP_MEDIAN: u32 = 2u;
+const OP_SOBEL: u32 = 3u;
+const OP_LAPLACIAN: u32 = 4u;
+const OP_ERODE: u32 = 5u;
+const OP_DILATE: u32 = 6u;
+// MAX_MEDIAN_SIZE in filters.rs squared, 5×5
+const MAX_MEDIAN_VALUES: u32 = 25u;
+
+// must match FilterParams in filters.rs
+struct Params {
+    op: u32,
+    radius: i32,
+    sigma: f32,
+    amount: f32,
+    // step between taps of a separable pass
+    direction: vec2<i32>,
+    metric: u32,
+};
+
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> params: Params;
+// OUTPUT_FORMAT is filled in with the filter texture format by filters.rs
+@group(0) @binding(2)
+var t_output: texture_storage_2d<OUTPUT_FORMAT, write>;
+
+// texel at an offset from coords, clamped to the edges of the texture
+fn load(coords: vec2<i32>, offset: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(t_input));
+    return textureLoad(t_input, clamp(coords + offset, vec2<i32>(0), size - 1), 0);
+}
+
+fn gaussian_weight(distance_squared: f32) -> f32 {
+    return exp(-distance_squared / (2.0 * params.sigma * params.sigma));
+}
+
+fn gaussian_1d(coords: vec2<i32>) -> vec4<f32> {
+    var total = vec4<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = -params.radius; i <= params.radius; i++) {
+        let weight = gaussian_weight(f32(i * i));
+        total += weight * load(coords, i * params.direction);
+        total_weight += weight;
+    }
+    return total / total_weight;
+}
+
+fn gaussian_2d(coords: vec2<i32>) -> vec4<f32> {
+    var total = vec4<f32>(0.0);
+    var total_weight = 0.0;
+    for (var y = -params.radius; y <= params.radius; y++) {
+        for (var x = -params.radius; x <= params.radius; x++) {
+            let weight = gaussian_weight(f32(x * x + y * y));
+            total += weight * load(coords, vec2<i32>(x, y));
+            total_weight += weight;
+        }
+    }
+    return total / total_weight;
+}
+
+fn median(coords: vec2<i32>) -> vec4<f32> {
+    var result: vec4<f32>;
+    for (var channel = 0u; channel < 4u; channel++) {
+        // insertion sort of the window
+        var values: array<f32, MAX_MEDIAN_VALUES>;
+        var n = 0u;
+        for (var y = -params.radius; y <= params.radius; y++) {
+            for (var x = -params.radius; x <= params.radius; x++) {
+                let value = load(coords, vec2<i32>(x, y))[channel];
+                var j = n;
+                while (j > 0u && values[j - 1u] > value) {
+                    values[j] = values[j - 1u];
+                    j--;
+                }
+                values[j] = value;
+                n++;
+            }
+        }
+        result[channel] = values[n / 2u];
+    }
+    return result;
+}
+
+fn sobel(coords: vec2<i32>) -> vec4<f32> {
+    let nw = load(coords, vec2<i32>(-1, -1));
+    let n = load(coords, vec2<i32>(0, -1));
+    let ne = load(coords, vec2<i32>(1, -1));
+    let w = load(coords, vec2<i32>(-1, 0));
+    let e = load(coords, vec2<i32>(1, 0));
+    let sw = load(coords, vec2<i32>(-1, 1));
+    let s = load(coords, vec2<i32>(0, 1));
+    let se = load(coords, vec2<i32>(1, 1));
+    let gx = (ne + 2.0 * e + se) - (nw + 2.0 * w + sw);
+    let gy = (sw + 2.0 * s + se) - (nw + 2.0 * n + ne);
+    // scaled so a full step edge has a magnitude of 1
+    let magnitude = sqrt(gx.rgb * gx.rgb + gy.rgb * gy.rgb) / 4.0;
+    return vec4<f32>(min(magnitude, vec3<f32>(1.0)), load(coords, vec2<i32>(0)).a);
+}
+
+fn laplacian(coords: vec2<i32>) -> vec4<f32> {
+    let c = load(coords, vec2<i32>(0));
+    let sum = load(coords, vec2<i32>(0, -1)) + load(coords, vec2<i32>(-1, 0))
+        + load(coords, vec2<i32>(1, 0)) + load(coords, vec2<i32>(0, 1));
+    return vec4<f32>(min(abs(4.0 * c.rgb - sum.rgb), vec3<f32>(1.0)), c.a);
+}
+
+// the texel in a square window with the lowest (erode) or highest (dilate) metric value, which
+// shrinks or grows the regions above the min threshold
+fn morphology(coords: vec2<i32>, dilate: bool) -> vec4<f32> {
+    var best = load(coords, vec2<i32>(0));
+    var best_value = metric_value(best, params.metric);
+    for (var y = -params.radius; y <= params.radius; y++) {
+        for (var x = -params.radius; x <= params.radius; x++) {
+            let texel = load(coords, vec2<i32>(x, y));
+            let value = metric_value(texel, params.metric);
+            if ((dilate && value > best_value) || (!dilate && value < best_value)) {
+                best = texel;
+                best_value = value;
+            }
+        }
+    }
+    return best;
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(t_input);
+    if (global_id.x >= size.x || global_id.y >= size.y) {
+        return;
+    }
+    let coords = vec2<i32>(global_id.xy);
+
+    var filtered: vec4<f32>;
+    switch params.op {
+        case OP_GAUSSIAN: {
+            filtered = gaussian_1d(coords);
+        }
+        case OP_UNSHARP: {
+            let original = load(coords, vec2<i32>(0));
+            let sharpened = original.rgb + params.amount * (original.rgb - gaussian_2d(coords).rgb);
+            filtered = vec4<f32>(clamp(sharpened, vec3<f32>(0.0), vec3<f32>(1.0)), original.a);
+        }
+        case OP_MEDIAN: {
+            filtered = median(coords);
+        }
+        case OP_SOBEL: {
+            filtered = sobel(coords);
+        }
+        case OP_LAPLACIAN: {
+            filtered = laplacian(coords);
+        }
+        case OP_ERODE: {
+            filtered = morphology(coords, false);
+        }
+        case OP_DILATE: {
+            filtered = morphology(coords, true);
+        }
+        default: {
+            filtered = load(coords, vec2<i32>(0));
+        }
+    }
+    textureStore(t_output, global_id.xy, filtered);
+}
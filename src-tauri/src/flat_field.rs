@@ -0,0 +1,301 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    frame::{self, Frame, SampleFormat, SourceFormat},
+    snapshot,
+};
+
+// Rgba32Float
+const BYTES_PER_PIXEL: u32 = 16;
+// keeps pixels where the flat is no brighter than the dark from blowing up
+const MIN_GAIN: f32 = 1e-4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CalibrationFrame {
+    /// Taken with the sensor covered, giving the offset to remove from every pixel
+    Dark,
+    /// Taken of an evenly lit target, giving the relative gain of every pixel
+    Flat,
+}
+
+impl CalibrationFrame {
+    fn file_stem(self) -> &'static str {
+        match self {
+            CalibrationFrame::Dark => "dark",
+            CalibrationFrame::Flat => "flat",
+        }
+    }
+}
+
+/// Payload of `get_flat_field`
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlatFieldStatus {
+    pub enabled: bool,
+    pub dark: bool,
+    pub flat: bool,
+}
+
+/// Dark and flat frames `fs_main` uses to correct each frame as `(raw - dark) / (flat - dark) *
+/// mean`, where `mean` is the mean of `flat - dark` so the overall brightness is kept.
+///
+/// Without a flat frame the dark frame is just subtracted, and without a dark frame the frames
+/// are only divided by the flat. Like the background, the frames are kept in linear light as
+/// `Rgba32Float`.
+///
+/// The frames are saved in the source's calibration directory whenever they are captured, loaded
+/// or cleared, in the source's own format and colour space.
+pub struct FlatField {
+    dark_texture: wgpu::Texture,
+    flat_texture: wgpu::Texture,
+    dark_view: wgpu::TextureView,
+    flat_view: wgpu::TextureView,
+    // linear RGBA, kept on the CPU to work out the mean and fill in for a missing frame
+    dark: Option<Vec<f32>>,
+    flat: Option<Vec<f32>>,
+    mean: [f32; 4],
+    texture_size: wgpu::Extent3d,
+    calibration_dir: PathBuf,
+    source_format: SourceFormat,
+    /// Apply the correction when there is a dark or flat frame
+    pub enabled: bool,
+}
+
+impl FlatField {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_size: wgpu::Extent3d,
+        calibration_dir: PathBuf,
+        source_format: SourceFormat,
+    ) -> Self {
+        let float_texture = |label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                size: texture_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                label: Some(label),
+                view_formats: &[],
+            })
+        };
+        let dark_texture = float_texture("dark_texture");
+        let flat_texture = float_texture("flat_texture");
+        let dark_view = dark_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let flat_view = flat_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut flat_field = Self {
+            dark_texture,
+            flat_texture,
+            dark_view,
+            flat_view,
+            dark: None,
+            flat: None,
+            mean: [1.0; 4],
+            texture_size,
+            calibration_dir,
+            source_format,
+            enabled: true,
+        };
+        flat_field.upload(queue);
+        flat_field
+    }
+
+    pub fn dark_view(&self) -> &wgpu::TextureView {
+        &self.dark_view
+    }
+
+    pub fn flat_view(&self) -> &wgpu::TextureView {
+        &self.flat_view
+    }
+
+    /// The per-channel mean of `flat - dark` to scale corrected frames by, or `None` if there is
+    /// nothing to correct with
+    pub fn mean(&self) -> Option<[f32; 4]> {
+        (self.enabled && (self.dark.is_some() || self.flat.is_some())).then_some(self.mean)
+    }
+
+    pub fn status(&self) -> FlatFieldStatus {
+        FlatFieldStatus {
+            enabled: self.enabled,
+            dark: self.dark.is_some(),
+            flat: self.flat.is_some(),
+        }
+    }
+
+    /// Load whichever calibration frames the source has saved
+    pub fn load_saved(&mut self, queue: &wgpu::Queue) -> Result<(), String> {
+        for frame in [CalibrationFrame::Dark, CalibrationFrame::Flat] {
+            if let Some(data) = self.read_saved(frame)? {
+                self.set(frame, Some(data));
+            }
+        }
+        self.upload(queue);
+        Ok(())
+    }
+
+    /// Read the saved calibration frames again in `source_format`, for when the source's colour
+    /// space changes. Nothing changes if either fails to read.
+    pub fn set_source_format(
+        &mut self,
+        queue: &wgpu::Queue,
+        source_format: SourceFormat,
+    ) -> Result<(), String> {
+        let previous_format = std::mem::replace(&mut self.source_format, source_format);
+        let saved = self
+            .read_saved(CalibrationFrame::Dark)
+            .and_then(|dark| Ok((dark, self.read_saved(CalibrationFrame::Flat)?)));
+        match saved {
+            Ok((dark, flat)) => {
+                self.dark = dark;
+                self.flat = flat;
+                self.upload(queue);
+                Ok(())
+            }
+            Err(e) => {
+                self.source_format = previous_format;
+                Err(e)
+            }
+        }
+    }
+
+    /// Use `frame_image`, the current raw frame, as a calibration frame
+    pub fn capture(
+        &mut self,
+        queue: &wgpu::Queue,
+        frame: CalibrationFrame,
+        frame_image: &Frame,
+    ) -> Result<(), String> {
+        let data = frame_image.as_raw().clone();
+        self.persist(frame, &data)?;
+        self.set(frame, Some(data));
+        self.upload(queue);
+        Ok(())
+    }
+
+    /// Load a calibration frame from an image the same size as the frames
+    pub fn load(
+        &mut self,
+        queue: &wgpu::Queue,
+        frame: CalibrationFrame,
+        path: &Path,
+    ) -> Result<(), String> {
+        let data = self.read(path)?;
+        self.persist(frame, &data)?;
+        self.set(frame, Some(data));
+        self.upload(queue);
+        Ok(())
+    }
+
+    pub fn clear(&mut self, queue: &wgpu::Queue, frame: CalibrationFrame) -> Result<(), String> {
+        let path = self.path(frame);
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(format!("failed to remove {}: {}", path.display(), e)),
+        }
+        self.set(frame, None);
+        self.upload(queue);
+        Ok(())
+    }
+
+    fn set(&mut self, frame: CalibrationFrame, data: Option<Vec<f32>>) {
+        match frame {
+            CalibrationFrame::Dark => self.dark = data,
+            CalibrationFrame::Flat => self.flat = data,
+        }
+    }
+
+    // write both frames to their textures, standing in for any that are missing so the shader
+    // doesn't need to know which ones there are
+    fn upload(&mut self, queue: &wgpu::Queue) {
+        let len = (self.texture_size.width * self.texture_size.height * 4) as usize;
+        let dark = self.dark.clone().unwrap_or_else(|| vec![0.0; len]);
+        let flat = self
+            .flat
+            .clone()
+            .unwrap_or_else(|| dark.iter().map(|d| d + 1.0).collect());
+        self.write(queue, &self.dark_texture, &dark);
+        self.write(queue, &self.flat_texture, &flat);
+
+        let mut sums = [0.0f64; 4];
+        for (i, (f, d)) in flat.iter().zip(&dark).enumerate() {
+            sums[i % 4] += (f - d).max(MIN_GAIN) as f64;
+        }
+        let pixels = (len / 4).max(1) as f64;
+        self.mean = sums.map(|sum| (sum / pixels) as f32);
+    }
+
+    fn write(&self, queue: &wgpu::Queue, texture: &wgpu::Texture, data: &[f32]) {
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(data),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(BYTES_PER_PIXEL * self.texture_size.width),
+                rows_per_image: Some(self.texture_size.height),
+            },
+            self.texture_size,
+        );
+    }
+
+    // where `frame` is saved, as a TIFF for float sources since PNGs can't hold them
+    fn path(&self, frame: CalibrationFrame) -> PathBuf {
+        let extension = match self.source_format.sample_format {
+            SampleFormat::U8 | SampleFormat::U16 => "png",
+            SampleFormat::F32 => "tiff",
+        };
+        self.calibration_dir
+            .join(format!("{}.{}", frame.file_stem(), extension))
+    }
+
+    // the saved `frame`, if there is one
+    fn read_saved(&self, frame: CalibrationFrame) -> Result<Option<Vec<f32>>, String> {
+        let path = self.path(frame);
+        if path.exists() {
+            self.read(&path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // read in the source's colour space, the same way its frames are
+    fn read(&self, path: &Path) -> Result<Vec<f32>, String> {
+        let (image, _) = frame::open(path, Some(self.source_format.color_space))?;
+        if image.dimensions() != (self.texture_size.width, self.texture_size.height) {
+            return Err(format!(
+                "calibration frame is {}x{} but frames are {}x{}",
+                image.width(),
+                image.height(),
+                self.texture_size.width,
+                self.texture_size.height
+            ));
+        }
+        Ok(image.into_raw())
+    }
+
+    // save to the source's calibration directory in the source's format, like unprocessed
+    // snapshots
+    fn persist(&self, frame: CalibrationFrame, data: &[f32]) -> Result<(), String> {
+        fs::create_dir_all(&self.calibration_dir)
+            .map_err(|e| format!("failed to create {}: {}", self.calibration_dir.display(), e))?;
+        let image = Frame::from_raw(
+            self.texture_size.width,
+            self.texture_size.height,
+            data.to_vec(),
+        )
+        .expect("should be the right size");
+        snapshot::save_image(
+            &frame::to_image(&image, self.source_format),
+            &self.path(frame),
+        )
+    }
+}
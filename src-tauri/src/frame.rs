@@ -0,0 +1,542 @@
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::LazyLock,
+};
+
+use image::{DynamicImage, ImageFormat, Rgba, Rgba32FImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::threshold::{linear_to_srgb, srgb_to_linear, ThresholdMetric};
+
+/// A source frame as the values thresholds compare, scaled so the largest value its sample format
+/// can hold is 1. That is linear light for sRGB sources and the samples as stored otherwise (see
+/// `ColorSpace`). These are the values the shaders sample, so work done on the CPU sees the same
+/// thing.
+pub type Frame = Rgba32FImage;
+
+/// Linear value of each 8 bit sRGB code
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> =
+    LazyLock::new(|| std::array::from_fn(|c| srgb_to_linear(c as f32 / 255.0)));
+
+/// Linear values halfway between consecutive 8 bit sRGB codes, for encoding back to the nearest
+/// code
+static SRGB_MIDPOINTS: LazyLock<[f32; 255]> =
+    LazyLock::new(|| std::array::from_fn(|c| (SRGB_TO_LINEAR[c] + SRGB_TO_LINEAR[c + 1]) / 2.0));
+
+/// How the source's samples are stored
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SampleFormat {
+    #[default]
+    U8,
+    /// Includes 12 and 14 bit camera data stored in 16 bits
+    U16,
+    /// Floating point, with 1 as full scale
+    F32,
+}
+
+impl SampleFormat {
+    /// Full scale in native units
+    pub fn max(self) -> f64 {
+        match self {
+            SampleFormat::U8 => u8::MAX as f64,
+            SampleFormat::U16 => u16::MAX as f64,
+            SampleFormat::F32 => 1.0,
+        }
+    }
+}
+
+/// What the source's samples mean, which decides the values thresholds compare. Set per source and
+/// saved with its calibration.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ColorSpace {
+    /// Encoded with the sRGB transfer function, like ordinary photos. Samples are decoded to
+    /// linear light, so thresholds compare light levels rather than the stored values and are
+    /// given on the 0-100 scale.
+    Srgb,
+    /// Proportional to light. Thresholds compare the stored values, in the source's native units.
+    Linear,
+    /// Sensor data, compared like linear samples but shown as stored rather than as light, the
+    /// way scientific image viewers show it
+    Raw,
+}
+
+impl ColorSpace {
+    /// What a source is taken to be without a saved colour space: 8 bit images are normally
+    /// photos and anything deeper normally comes from a camera
+    pub fn default_for(sample_format: SampleFormat) -> Self {
+        match sample_format {
+            SampleFormat::U8 => ColorSpace::Srgb,
+            SampleFormat::U16 | SampleFormat::F32 => ColorSpace::Linear,
+        }
+    }
+
+    /// The value thresholds compare for a stored 0-1 sample
+    pub fn decode(self, stored: f32) -> f32 {
+        match self {
+            ColorSpace::Srgb => srgb_to_linear(stored),
+            ColorSpace::Linear | ColorSpace::Raw => stored,
+        }
+    }
+
+    /// Inverse of `decode`
+    pub fn encode(self, value: f32) -> f32 {
+        match self {
+            ColorSpace::Srgb => linear_to_srgb(value),
+            ColorSpace::Linear | ColorSpace::Raw => value,
+        }
+    }
+
+    /// Convert a value on the 0-100 scale decoded in this colour space to the one decoding the
+    /// same stored value in `to`, so a threshold keeps picking out the same pixels when a source's
+    /// colour space is changed
+    pub fn convert_percent(self, to: ColorSpace, percent: f32) -> f32 {
+        to.decode(self.encode(percent / 100.0)) * 100.0
+    }
+
+    /// The saved colour space of a source, if there is one
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file =
+            File::open(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map(Some)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))
+    }
+
+    pub fn save(self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        }
+        let file =
+            File::create(path).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// How a source's frames are stored and what they mean, which decides the textures frames are
+/// uploaded to and the units thresholds are given in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SourceFormat {
+    pub sample_format: SampleFormat,
+    pub color_space: ColorSpace,
+}
+
+impl SourceFormat {
+    /// Format of the frame textures. 8 bit frames keep their own encoding, with the GPU decoding
+    /// sRGB ones as they are sampled. Deeper frames are kept as 32 bit floats so nothing is lost
+    /// before thresholding, which means they can only be filtered if the device supports it.
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match (self.sample_format, self.color_space) {
+            (SampleFormat::U8, ColorSpace::Srgb) => wgpu::TextureFormat::Rgba8UnormSrgb,
+            (SampleFormat::U8, ColorSpace::Linear | ColorSpace::Raw) => {
+                wgpu::TextureFormat::Rgba8Unorm
+            }
+            (SampleFormat::U16 | SampleFormat::F32, _) => wgpu::TextureFormat::Rgba32Float,
+        }
+    }
+
+    /// Full scale in native units, for values proportional to the samples. Decoded sRGB values
+    /// aren't proportional to the stored codes, so sRGB sources keep the 0-100 scale.
+    pub fn full_scale(self) -> f64 {
+        match self.color_space {
+            ColorSpace::Srgb => 100.0,
+            ColorSpace::Linear | ColorSpace::Raw => self.sample_format.max(),
+        }
+    }
+
+    /// Native units per percent of full scale
+    pub fn units_per_percent(self) -> f64 {
+        self.full_scale() / 100.0
+    }
+
+    /// Native units per percent of `metric`. Hue and saturation are ratios, so they stay on the
+    /// 0-100 scale whatever the source.
+    pub fn metric_units_per_percent(self, metric: ThresholdMetric) -> f64 {
+        if metric.is_intensity() {
+            self.units_per_percent()
+        } else {
+            1.0
+        }
+    }
+
+    /// Convert a threshold on `metric` given in native units to the 0-100 metric scale
+    pub fn threshold_to_percent(self, metric: ThresholdMetric, native: f64) -> f32 {
+        (native / self.metric_units_per_percent(metric)) as f32
+    }
+
+    /// Inverse of `threshold_to_percent`
+    pub fn threshold_from_percent(self, metric: ThresholdMetric, percent: f32) -> f64 {
+        percent as f64 * self.metric_units_per_percent(metric)
+    }
+}
+
+/// Payload of `get_sample_range`
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleRange {
+    pub format: SampleFormat,
+    pub color_space: ColorSpace,
+    /// Full scale in native units, which is also the top of the threshold and display ranges
+    pub max: f64,
+}
+
+impl From<SourceFormat> for SampleRange {
+    fn from(format: SourceFormat) -> Self {
+        Self {
+            format: format.sample_format,
+            color_space: format.color_space,
+            max: format.full_scale(),
+        }
+    }
+}
+
+/// Read a frame at its full depth in `color_space`, or the default for its sample format if
+/// `None`
+pub fn open(path: &Path, color_space: Option<ColorSpace>) -> Result<(Frame, SourceFormat), String> {
+    match image::open(path) {
+        Ok(image) => Ok(from_image(image, color_space)),
+        Err(e) => open_gray_float_tiff(path)
+            .map(|stored| {
+                let color_space = color_space.unwrap_or(ColorSpace::default_for(SampleFormat::F32));
+                let format = SourceFormat {
+                    sample_format: SampleFormat::F32,
+                    color_space,
+                };
+                (decode_frame(stored, color_space), format)
+            })
+            .ok_or_else(|| format!("failed to read {}: {}", path.display(), e)),
+    }
+}
+
+pub fn from_image(image: DynamicImage, color_space: Option<ColorSpace>) -> (Frame, SourceFormat) {
+    let sample_format = match image {
+        DynamicImage::ImageLuma8(_)
+        | DynamicImage::ImageLumaA8(_)
+        | DynamicImage::ImageRgb8(_)
+        | DynamicImage::ImageRgba8(_) => SampleFormat::U8,
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => SampleFormat::U16,
+        _ => SampleFormat::F32,
+    };
+    let color_space = color_space.unwrap_or(ColorSpace::default_for(sample_format));
+    let format = SourceFormat {
+        sample_format,
+        color_space,
+    };
+    let frame = match (sample_format, color_space) {
+        // the sRGB curve is slow enough to be worth a table for each code
+        (SampleFormat::U8, ColorSpace::Srgb) => {
+            let rgba = image.to_rgba8();
+            Frame::from_fn(rgba.width(), rgba.height(), |x, y| {
+                let [r, g, b, a] = rgba.get_pixel(x, y).0;
+                Rgba([
+                    SRGB_TO_LINEAR[r as usize],
+                    SRGB_TO_LINEAR[g as usize],
+                    SRGB_TO_LINEAR[b as usize],
+                    a as f32 / 255.0,
+                ])
+            })
+        }
+        (SampleFormat::U8, _) => image.to_rgba32f(),
+        (SampleFormat::U16, _) => {
+            let rgba = image.to_rgba16();
+            let (width, height) = rgba.dimensions();
+            let samples = rgba
+                .into_raw()
+                .into_iter()
+                .map(|c| c as f32 / u16::MAX as f32)
+                .collect();
+            let stored = Frame::from_raw(width, height, samples).expect("should be the right size");
+            decode_frame(stored, color_space)
+        }
+        (SampleFormat::F32, _) => decode_frame(image.to_rgba32f(), color_space),
+    };
+    (frame, format)
+}
+
+/// Decode the colour channels of stored 0-1 samples, leaving alpha as it is
+fn decode_frame(mut frame: Frame, color_space: ColorSpace) -> Frame {
+    if color_space != ColorSpace::Srgb {
+        return frame;
+    }
+    for pixel in frame.pixels_mut() {
+        for c in &mut pixel.0[..3] {
+            *c = color_space.decode(*c);
+        }
+    }
+    frame
+}
+
+/// Single channel 32 bit float TIFFs, which `image` can't decode
+fn open_gray_float_tiff(path: &Path) -> Option<Frame> {
+    if ImageFormat::from_path(path).ok()? != ImageFormat::Tiff {
+        return None;
+    }
+    let mut decoder = tiff::decoder::Decoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+    if decoder.colortype().ok()? != tiff::ColorType::Gray(32) {
+        return None;
+    }
+    let (width, height) = decoder.dimensions().ok()?;
+    let tiff::decoder::DecodingResult::F32(values) = decoder.read_image().ok()? else {
+        return None;
+    };
+    let samples = values.into_iter().flat_map(|v| [v, v, v, 1.0]).collect();
+    Frame::from_raw(width, height, samples)
+}
+
+/// The 8 bit sRGB code nearest to a linear value
+pub fn encode_srgb8(c: f32) -> u8 {
+    SRGB_MIDPOINTS.partition_point(|&midpoint| midpoint < c) as u8
+}
+
+/// `frame` as an 8 bit sRGB image, clipping anything over full scale
+pub fn to_srgb8(frame: &Frame) -> RgbaImage {
+    RgbaImage::from_fn(frame.width(), frame.height(), |x, y| {
+        let [r, g, b, a] = frame.get_pixel(x, y).0;
+        Rgba([
+            encode_srgb8(r),
+            encode_srgb8(g),
+            encode_srgb8(b),
+            (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+    })
+}
+
+/// `frame` encoded as stored, without quantising
+fn encode_frame(frame: &Frame, color_space: ColorSpace) -> Frame {
+    let mut stored = frame.clone();
+    if color_space == ColorSpace::Srgb {
+        for pixel in stored.pixels_mut() {
+            for c in &mut pixel.0[..3] {
+                *c = color_space.encode(*c);
+            }
+        }
+    }
+    stored
+}
+
+/// `frame` quantised to 8 bits without encoding, clipping anything over full scale
+fn to_unorm8(frame: &Frame) -> RgbaImage {
+    let samples = frame
+        .as_raw()
+        .iter()
+        .map(|&c| (c.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+        .collect();
+    RgbaImage::from_raw(frame.width(), frame.height(), samples).expect("should be the right size")
+}
+
+/// `frame` as 8 bit sRGB the way it is shown, which is as light except for raw sources
+pub fn to_display8(frame: &Frame, color_space: ColorSpace) -> RgbaImage {
+    match color_space {
+        ColorSpace::Srgb | ColorSpace::Linear => to_srgb8(frame),
+        ColorSpace::Raw => to_unorm8(frame),
+    }
+}
+
+/// `frame` back in the format it was read from
+pub fn to_image(frame: &Frame, format: SourceFormat) -> DynamicImage {
+    match (format.sample_format, format.color_space) {
+        (SampleFormat::U8, ColorSpace::Srgb) => DynamicImage::ImageRgba8(to_srgb8(frame)),
+        (SampleFormat::U8, _) => DynamicImage::ImageRgba8(to_unorm8(frame)),
+        (SampleFormat::U16, color_space) => {
+            let samples = encode_frame(frame, color_space)
+                .as_raw()
+                .iter()
+                .map(|&c| (c.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+                .collect();
+            DynamicImage::ImageRgba16(
+                image::ImageBuffer::from_raw(frame.width(), frame.height(), samples)
+                    .expect("should be the right size"),
+            )
+        }
+        (SampleFormat::F32, color_space) => {
+            DynamicImage::ImageRgba32F(encode_frame(frame, color_space))
+        }
+    }
+}
+
+/// Upload `frame` to a texture in one of the formats `SourceFormat::texture_format` picks
+pub fn write_texture(queue: &wgpu::Queue, texture: &wgpu::Texture, frame: &Frame) {
+    let data: Cow<[u8]> = match texture.format() {
+        wgpu::TextureFormat::Rgba8UnormSrgb => Cow::Owned(to_srgb8(frame).into_raw()),
+        wgpu::TextureFormat::Rgba8Unorm => Cow::Owned(to_unorm8(frame).into_raw()),
+        wgpu::TextureFormat::Rgba32Float => Cow::Borrowed(bytemuck::cast_slice(frame.as_raw())),
+        format => panic!("frames can't be written to {:?} textures", format),
+    };
+    let bytes_per_pixel = texture
+        .format()
+        .block_copy_size(None)
+        .expect("should be a color format");
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        &data,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(bytes_per_pixel * frame.width()),
+            rows_per_image: Some(frame.height()),
+        },
+        texture.size(),
+    );
+}
+
+/// Layout entry for a frame texture read by a compute pass. Compute passes load frames rather
+/// than sampling them, so they don't need to be filterable.
+pub fn frame_binding_layout_entry(
+    binding: u32,
+    view_dimension: wgpu::TextureViewDimension,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    }
+}
+
+/// Format the filters write their results in for frames stored as `frame_format`. Deep frames
+/// are kept as 32 bit floats so filtering doesn't lose their precision before thresholding, while
+/// 16 bit floats are plenty for 8 bit frames.
+pub fn intermediate_format(frame_format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+    match frame_format {
+        wgpu::TextureFormat::Rgba32Float => wgpu::TextureFormat::Rgba32Float,
+        _ => wgpu::TextureFormat::Rgba16Float,
+    }
+}
+
+/// `source` with `OUTPUT_FORMAT` replaced by the WGSL texel format of `format`, for compute
+/// shaders that write to one of the `intermediate_format`s
+pub fn with_output_format(source: &str, format: wgpu::TextureFormat) -> String {
+    let texel_format = match format {
+        wgpu::TextureFormat::Rgba16Float => "rgba16float",
+        wgpu::TextureFormat::Rgba32Float => "rgba32float",
+        format => panic!("filters can't write to {:?} textures", format),
+    };
+    source.replace("OUTPUT_FORMAT", texel_format)
+}
+
+/// Whether textures of `format` can be sampled with linear filtering on `device`
+pub fn filterable(device: &wgpu::Device, format: wgpu::TextureFormat) -> bool {
+    format
+        .guaranteed_format_features(device.features())
+        .flags
+        .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, ImageBuffer, Luma};
+
+    use super::*;
+    use crate::threshold::metric_value;
+
+    fn gray8(code: u8) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_pixel(1, 1, Luma([code])))
+    }
+
+    fn luminance(frame: &Frame) -> f32 {
+        metric_value(frame.get_pixel(0, 0).0, ThresholdMetric::Luminance709)
+    }
+
+    #[test]
+    fn srgb_sources_threshold_linear_light() {
+        let (frame, format) = from_image(gray8(128), Some(ColorSpace::Srgb));
+        assert_eq!(format.texture_format(), wgpu::TextureFormat::Rgba8UnormSrgb);
+        // code 128 is about 21.6% of the light of code 255
+        assert!((luminance(&frame) - 21.586).abs() < 1e-3);
+        // and thresholds stay on the 0-100 scale
+        assert_eq!(
+            format.threshold_to_percent(ThresholdMetric::Luminance709, 21.586),
+            21.586
+        );
+    }
+
+    #[test]
+    fn linear_sources_threshold_stored_values() {
+        for color_space in [ColorSpace::Linear, ColorSpace::Raw] {
+            let (frame, format) = from_image(gray8(128), Some(color_space));
+            assert_eq!(format.texture_format(), wgpu::TextureFormat::Rgba8Unorm);
+            assert!((luminance(&frame) - 128.0 / 255.0 * 100.0).abs() < 1e-3);
+            // thresholds are given as codes, matching the pixel values other tools show
+            let percent = format.threshold_to_percent(ThresholdMetric::Luminance709, 128.0);
+            assert!((percent - luminance(&frame)).abs() < 1e-3);
+            assert_eq!(SampleRange::from(format).max, 255.0);
+        }
+    }
+
+    #[test]
+    fn measurements_convert_to_native_units_except_for_ratios() {
+        let format = SourceFormat {
+            sample_format: SampleFormat::U16,
+            color_space: ColorSpace::Linear,
+        };
+        assert_eq!(
+            format.metric_units_per_percent(ThresholdMetric::Luminance709),
+            655.35
+        );
+        assert_eq!(format.metric_units_per_percent(ThresholdMetric::Hue), 1.0);
+        assert_eq!(
+            format.threshold_from_percent(ThresholdMetric::Saturation, 40.0),
+            40.0
+        );
+    }
+
+    #[test]
+    fn converted_percentages_decode_the_same_stored_value() {
+        let (srgb, _) = from_image(gray8(128), Some(ColorSpace::Srgb));
+        let (linear, _) = from_image(gray8(128), Some(ColorSpace::Linear));
+        let converted = ColorSpace::Srgb.convert_percent(ColorSpace::Linear, luminance(&srgb));
+        assert!((converted - luminance(&linear)).abs() < 1e-3);
+        let back = ColorSpace::Linear.convert_percent(ColorSpace::Srgb, converted);
+        assert!((back - luminance(&srgb)).abs() < 1e-3);
+        assert_eq!(
+            ColorSpace::Linear.convert_percent(ColorSpace::Raw, 40.0),
+            40.0
+        );
+    }
+
+    #[test]
+    fn color_space_defaults_to_srgb_for_8_bit_and_linear_for_deeper() {
+        let (_, format) = from_image(gray8(128), None);
+        assert_eq!(format.color_space, ColorSpace::Srgb);
+        let gray16 = ImageBuffer::from_pixel(1, 1, Luma([32768u16]));
+        let (frame, format) = from_image(DynamicImage::ImageLuma16(gray16), None);
+        assert_eq!(format.color_space, ColorSpace::Linear);
+        assert!((luminance(&frame) - 32768.0 / 65535.0 * 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn frames_convert_back_to_their_stored_samples() {
+        let codes8: Vec<u8> = (0..=255).collect();
+        let image8 = DynamicImage::ImageLuma8(GrayImage::from_raw(256, 1, codes8).unwrap());
+        let codes16: Vec<u16> = (0..=u16::MAX).step_by(257).chain([1, 4095]).collect();
+        let image16 = DynamicImage::ImageLuma16(
+            ImageBuffer::from_raw(codes16.len() as u32, 1, codes16).unwrap(),
+        );
+        for color_space in [ColorSpace::Srgb, ColorSpace::Linear, ColorSpace::Raw] {
+            let (frame, format) = from_image(image8.clone(), Some(color_space));
+            assert_eq!(to_image(&frame, format).to_rgba8(), image8.to_rgba8());
+            let (frame, format) = from_image(image16.clone(), Some(color_space));
+            assert_eq!(to_image(&frame, format).to_rgba16(), image16.to_rgba16());
+        }
+    }
+}
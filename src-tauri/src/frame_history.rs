@@ -0,0 +1,185 @@
+use crate::frame;
+
+/// Most frames a `FrameHistory` can hold
+pub const MAX_HISTORY_FRAMES: u32 = 32;
+
+/// The most recent frames, kept in the layers of a texture array for compute passes that work
+/// over a window of frames. Layers are reused round robin, so they are in no particular order.
+///
+/// The array is only allocated once a window is set, and sized to it, since deep frames make it
+/// large. It is bound on its own as `@group(1) @binding(0)`, so passes don't have to rebuild their other
+/// bindings when it is reallocated.
+pub struct FrameHistory {
+    // `None` without a window, with a single placeholder layer bound instead
+    texture: Option<wgpu::Texture>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    texture_size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+    label: &'static str,
+    len: u32,
+    next_layer: u32,
+}
+
+impl FrameHistory {
+    /// `format` should be the diffuse texture's, so frames can be copied in as they are
+    pub fn new(
+        device: &wgpu::Device,
+        texture_size: wgpu::Extent3d,
+        format: wgpu::TextureFormat,
+        label: &'static str,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[frame::frame_binding_layout_entry(
+                0,
+                wgpu::TextureViewDimension::D2Array,
+            )],
+            label: Some("frame_history_bind_group_layout"),
+        });
+        let placeholder = Self::create_placeholder(device, format, label);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &placeholder);
+        Self {
+            texture: None,
+            bind_group_layout,
+            bind_group,
+            texture_size,
+            format,
+            label,
+            len: 0,
+            next_layer: 0,
+        }
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        size: wgpu::Extent3d,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some(label),
+            view_formats: &[],
+        })
+    }
+
+    // bound when there is no window, so the passes always have something to bind
+    fn create_placeholder(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> wgpu::Texture {
+        let size = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        Self::create_texture(device, size, format, label)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &wgpu::Texture,
+    ) -> wgpu::BindGroup {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+            label: Some("frame_history_bind_group"),
+        })
+    }
+
+    /// Layout of `bind_group`, for the pipeline layouts of the passes that read the frames
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// The array for binding as a `texture_2d_array<f32>`
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Number of layers holding frames
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next_layer = 0;
+    }
+
+    /// Make room for the last `window` frames, clamped to `1..=MAX_HISTORY_FRAMES`, or free them
+    /// all with `None`. The frames held so far are dropped if the window changes.
+    pub fn set_window(&mut self, device: &wgpu::Device, window: Option<u32>) {
+        let layers = window.map(|window| window.clamp(1, MAX_HISTORY_FRAMES));
+        let current_layers = self
+            .texture
+            .as_ref()
+            .map(|texture| texture.depth_or_array_layers());
+        if layers == current_layers {
+            return;
+        }
+        self.clear();
+        self.texture = layers.map(|layers| {
+            Self::create_texture(
+                device,
+                wgpu::Extent3d {
+                    depth_or_array_layers: layers,
+                    ..self.texture_size
+                },
+                self.format,
+                self.label,
+            )
+        });
+        let placeholder;
+        let texture = match &self.texture {
+            Some(texture) => texture,
+            None => {
+                placeholder = Self::create_placeholder(device, self.format, self.label);
+                &placeholder
+            }
+        };
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, texture);
+    }
+
+    /// Encode adding the frame in `frame_texture`, overwriting the oldest one once the window is
+    /// full. Does nothing without a window.
+    pub fn push(&mut self, encoder: &mut wgpu::CommandEncoder, frame_texture: &wgpu::Texture) {
+        let Some(texture) = &self.texture else {
+            return;
+        };
+        let window = texture.depth_or_array_layers();
+        if self.next_layer >= window {
+            self.next_layer = 0;
+        }
+        encoder.copy_texture_to_texture(
+            frame_texture.as_image_copy(),
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: self.next_layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            self.texture_size,
+        );
+        self.next_layer += 1;
+        self.len = (self.len + 1).min(window);
+    }
+}
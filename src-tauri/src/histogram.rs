@@ -0,0 +1,293 @@
+use serde::Serialize;
+use tokio::time::{Duration, Instant};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    frame::{self, Frame, SourceFormat},
+    readback::Readbacks,
+    threshold::{metric_value, ThresholdMetric},
+};
+
+pub const HISTOGRAM_BINS: usize = 256;
+
+/// Histogram of a threshold metric over a frame, with the 0-100 metric range split evenly across
+/// `HISTOGRAM_BINS` bins.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bins: Vec<u32>,
+}
+
+impl Histogram {
+    pub fn from_image(image: &Frame, metric: ThresholdMetric) -> Self {
+        let mut bins = vec![0; HISTOGRAM_BINS];
+        for pixel in image.pixels() {
+            let value = metric_value(pixel.0, metric);
+            bins[Self::bin_of(value)] += 1;
+        }
+        Self { bins }
+    }
+
+    pub fn bins(&self) -> &[u32] {
+        &self.bins
+    }
+
+    pub fn total(&self) -> u64 {
+        self.bins.iter().map(|&count| count as u64).sum()
+    }
+
+    pub fn mean_bin(&self) -> f64 {
+        let weighted: f64 = self
+            .bins
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| i as f64 * count as f64)
+            .sum();
+        weighted / self.total() as f64
+    }
+
+    pub fn bin_of(value: f32) -> usize {
+        ((value / 100.0 * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1)
+    }
+
+    /// The metric value at the top of the given bin
+    pub fn bin_upper_edge(bin: usize) -> f32 {
+        (bin + 1) as f32 * 100.0 / HISTOGRAM_BINS as f32
+    }
+}
+
+/// Payload of the `histogram` event
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramPayload {
+    /// The metric the `values` bins were computed from, i.e. the current threshold metric
+    pub metric: ThresholdMetric,
+    /// Top of the last bin in the source's native units, like the thresholds. The bins split
+    /// 0 to `max` evenly.
+    pub max: f64,
+    pub values: Vec<u32>,
+    pub red: Option<Vec<u32>>,
+    pub green: Option<Vec<u32>>,
+    pub blue: Option<Vec<u32>>,
+}
+
+/// Layout of the params uniform in `histogram.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct HistogramParams {
+    metric: u32,
+    per_channel: u32,
+}
+
+const WORKGROUP_SIZE: u32 = 16;
+// the metric bins plus one set per RGB channel
+const MAX_BINS: usize = 4 * HISTOGRAM_BINS;
+const BINS_BUFFER_SIZE: wgpu::BufferAddress = (MAX_BINS * std::mem::size_of::<u32>()) as u64;
+// number of histograms that can be waiting on a readback at once. If they are all in use when the
+// next histogram is due, it is skipped rather than waiting on the GPU.
+const NUM_READBACK_BUFFERS: usize = 3;
+
+/// Bins the processed frame with a compute pass and reads the results back asynchronously so the
+/// render loop never has to wait on the GPU.
+pub struct GpuHistogram {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    bins_buffer: wgpu::Buffer,
+    // the metric and whether the channels were binned for each readback
+    readbacks: Readbacks<(ThresholdMetric, bool)>,
+    workgroups: (u32, u32),
+    /// Time between histograms, `None` if they are turned off
+    pub interval: Option<Duration>,
+    /// Also bin the red, green and blue channels
+    pub per_channel: bool,
+    last_dispatch: Option<Instant>,
+}
+
+impl GpuHistogram {
+    pub fn new(
+        device: &wgpu::Device,
+        texture_view: &wgpu::TextureView,
+        texture_size: wgpu::Extent3d,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("histogram.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("metric.wgsl"), include_str!("histogram.wgsl")).into(),
+            ),
+        });
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Histogram Params Buffer"),
+            contents: bytemuck::bytes_of(&HistogramParams {
+                metric: ThresholdMetric::default() as u32,
+                per_channel: 0,
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bins_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Histogram Bins Buffer"),
+            size: BINS_BUFFER_SIZE,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                frame::frame_binding_layout_entry(0, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("histogram_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: bins_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("histogram_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("histogram_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("histogram_pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            params_buffer,
+            bins_buffer,
+            readbacks: Readbacks::new(
+                device,
+                "Histogram Readback Buffer",
+                NUM_READBACK_BUFFERS,
+                BINS_BUFFER_SIZE,
+            ),
+            workgroups: (
+                texture_size.width.div_ceil(WORKGROUP_SIZE),
+                texture_size.height.div_ceil(WORKGROUP_SIZE),
+            ),
+            interval: None,
+            per_channel: false,
+            last_dispatch: None,
+        }
+    }
+
+    /// Encode a histogram pass into `encoder` if one is due and there is a free readback buffer
+    /// to copy the results into. Call `after_submit` once the encoder has been submitted.
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        metric: ThresholdMetric,
+    ) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let now = Instant::now();
+        if self
+            .last_dispatch
+            .is_some_and(|last_dispatch| now.duration_since(last_dispatch) < interval)
+        {
+            return;
+        }
+        let per_channel = self.per_channel;
+        let Some(readback_buffer) = self.readbacks.start((metric, per_channel)) else {
+            return;
+        };
+        self.last_dispatch = Some(now);
+
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::bytes_of(&HistogramParams {
+                metric: metric as u32,
+                per_channel: per_channel as u32,
+            }),
+        );
+        encoder.clear_buffer(&self.bins_buffer, 0, None);
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("histogram_pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &self.bind_group, &[]);
+            cpass.dispatch_workgroups(self.workgroups.0, self.workgroups.1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.bins_buffer, 0, readback_buffer, 0, BINS_BUFFER_SIZE);
+    }
+
+    /// Request mapping of any readback buffers whose copies were just submitted
+    pub fn after_submit(&mut self) {
+        self.readbacks.after_submit();
+    }
+
+    /// Collect a histogram that has finished reading back, if there is one, without blocking.
+    /// `source_format` gives the native units of the bins.
+    pub fn poll(
+        &mut self,
+        device: &wgpu::Device,
+        source_format: SourceFormat,
+    ) -> Option<Result<HistogramPayload, String>> {
+        let ((metric, per_channel), bins) = self.readbacks.poll(device, |_, data| {
+            bytemuck::pod_collect_to_vec::<u8, u32>(data)
+        })?;
+        let bins = match bins {
+            Ok(bins) => bins,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let channel = |i: usize| {
+            per_channel.then(|| bins[i * HISTOGRAM_BINS..(i + 1) * HISTOGRAM_BINS].to_vec())
+        };
+        Some(Ok(HistogramPayload {
+            metric,
+            max: source_format.threshold_from_percent(metric, 100.0),
+            values: bins[..HISTOGRAM_BINS].to_vec(),
+            red: channel(1),
+            green: channel(2),
+            blue: channel(3),
+        }))
+    }
+}
@@ -0,0 +1,55 @@
+// Histogram compute shader, appended to metric.wgsl
+
+const BINS: u32 = 256u;
+const WORKGROUP_SIZE: u32 = 16u;
+
+// must match HistogramParams in histogram.rs
+struct Params {
+    metric: u32,
+    per_channel: u32,
+};
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> params: Params;
+// metric bins, followed by the red, green and blue bins when binning per channel
+@group(0) @binding(2)
+var<storage, read_write> bins: array<atomic<u32>>;
+
+var<workgroup> local_bins: array<atomic<u32>, 1024>;
+
+fn bin_of(value: f32) -> u32 {
+    return min(u32(max(value, 0.0) * f32(BINS)), BINS - 1u);
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    let num_bins = select(BINS, 4u * BINS, params.per_channel != 0u);
+    for (var i = local_index; i < num_bins; i += WORKGROUP_SIZE * WORKGROUP_SIZE) {
+        atomicStore(&local_bins[i], 0u);
+    }
+    workgroupBarrier();
+
+    let size = textureDimensions(t_diffuse);
+    if (global_id.x < size.x && global_id.y < size.y) {
+        let texel = textureLoad(t_diffuse, global_id.xy, 0);
+        atomicAdd(&local_bins[bin_of(metric_value(texel, params.metric) / 100.0)], 1u);
+        if (params.per_channel != 0u) {
+            atomicAdd(&local_bins[BINS + bin_of(texel.r)], 1u);
+            atomicAdd(&local_bins[2u * BINS + bin_of(texel.g)], 1u);
+            atomicAdd(&local_bins[3u * BINS + bin_of(texel.b)], 1u);
+        }
+    }
+    workgroupBarrier();
+
+    for (var i = local_index; i < num_bins; i += WORKGROUP_SIZE * WORKGROUP_SIZE) {
+        let count = atomicLoad(&local_bins[i]);
+        if (count > 0u) {
+            atomicAdd(&bins[i], count);
+        }
+    }
+}
@@ -1,9 +1,71 @@
-use std::{fs, sync::Mutex};
+use std::{
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    task::{Context, Poll, Waker},
+};
 
-use image::GenericImageView;
-use tauri::{async_runtime::block_on, AppHandle, Manager, PhysicalSize, RunEvent, WindowEvent};
+use tauri::{
+    async_runtime::block_on, AppHandle, Emitter, Manager, PhysicalSize, RunEvent, WindowEvent,
+};
 use tokio::time::{sleep_until, Duration, Instant};
-use wgpu::{include_wgsl, util::DeviceExt as _, BufferBindingType};
+use wgpu::{util::DeviceExt as _, BufferBindingType};
+
+mod background;
+mod bad_pixels;
+mod batch;
+mod blobs;
+mod clip;
+mod distortion;
+mod filters;
+mod flat_field;
+mod frame;
+mod frame_history;
+mod histogram;
+mod overlay;
+mod pre_trigger;
+mod processing;
+mod processing_config;
+mod profile;
+mod readback;
+mod recording;
+mod roi;
+mod shader_params;
+mod shaders;
+mod snapshot;
+mod spatial_calibration;
+mod temporal;
+mod threshold;
+mod tracking;
+
+use background::{BackgroundModel, GpuBackground};
+use bad_pixels::{BadPixelDetectionOptions, BadPixelMap, BadPixels};
+use batch::{BatchArgs, StatsWriter};
+use blobs::{detect_blobs, draw_blobs, Blob, BlobDetectionOptions};
+use clip::{encode_clip, ClipFormat, ClipOptions, ClipProgress, ClipRange, ClipStage};
+use distortion::{LensDistortion, LensUndistortion};
+use filters::{FilterSource, FilterStage, GpuFilterChain};
+use flat_field::{CalibrationFrame, FlatField, FlatFieldStatus};
+use frame::{ColorSpace, Frame, SampleRange, SourceFormat};
+use histogram::{GpuHistogram, Histogram};
+use overlay::{Overlay, OverlayLines, OverlayPlacement};
+use pre_trigger::{PreTrigger, PreTriggerOptions, TriggerReason};
+use processing::{motion_score, DifferenceMode, MotionScore, ProcessingUniform};
+use processing_config::ProcessingConfig;
+use profile::{LineProfile, ProfileLine};
+use readback::{Readbacks, TextureLayout};
+use recording::{
+    BufferedFrame, RecordedFrame, Recording, RecordingOptions, RecordingSource, RecordingSummary,
+};
+use roi::{Roi, RoiShape, RoiStats, RoiTracePoint, Rois};
+use shader_params::{ShaderParam, ShaderParamValue, ShaderParams};
+use shaders::{CustomShader, ShaderError};
+use snapshot::SnapshotOptions;
+use spatial_calibration::{LengthUnit, SpatialCalibration};
+use temporal::{GpuTemporalFilter, TemporalFilter};
+use threshold::{AutoThresholdMethod, ThresholdBand, ThresholdMetric, ThresholdUniform};
+use tracking::{Track, TrackExportFormat, Tracker, TrackingOptions};
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
@@ -37,199 +99,2863 @@ const VERTICES: &[Vertex] = &[
     },
 ];
 
-const INDICES: &[u16] = &[0, 1, 2, 0, 2, 3];
+/// `VERTICES` stretched over the whole render target, for rendering frames offscreen
+const FRAME_VERTICES: &[Vertex] = &[
+    Vertex {
+        position: [-1.0, 1.0, 0.0],
+        tex_coords: [0.0, 0.0],
+    },
+    Vertex {
+        position: [-1.0, -1.0, 0.0],
+        tex_coords: [0.0, 1.0],
+    },
+    Vertex {
+        position: [1.0, -1.0, 0.0],
+        tex_coords: [1.0, 1.0],
+    },
+    Vertex {
+        position: [1.0, 1.0, 0.0],
+        tex_coords: [1.0, 0.0],
+    },
+];
+
+const INDICES: &[u16] = &[0, 1, 2, 0, 2, 3];
+
+/// Where frames are read from. Calibration for the source is kept alongside them.
+const SOURCE_DIR: &str = "./video-imgs";
+const SPATIAL_CALIBRATION_FILE: &str = "spatial.json";
+const COLOR_SPACE_FILE: &str = "color_space.json";
+const NUM_FRAMES: u32 = 11;
+const BUILTIN_SHADER: &str = concat!(
+    include_str!("metric.wgsl"),
+    include_str!("prelude.wgsl"),
+    include_str!("shader.wgsl")
+);
+const PROCESSED_SHADER: &str = concat!(
+    include_str!("metric.wgsl"),
+    include_str!("prelude.wgsl"),
+    include_str!("processed.wgsl")
+);
+// processed frames that can be waiting on a readback at once during live view. If they are all in
+// use, the analyses skip a frame rather than waiting on the GPU.
+const NUM_PROCESSED_READBACKS: usize = 2;
+// rendered frames that can be waiting on a readback at once while recording. If they are all in
+// use, the frame is dropped from the recording rather than waiting on the GPU.
+const NUM_CAPTURE_READBACKS: usize = 3;
+// current limit seems to be ~10ms
+const FRAME_RATE: Duration = Duration::from_millis(100);
+
+/// How a recording captures frames, so the recording and the pre-trigger buffer can share them
+/// when they capture the same way
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum CaptureKind {
+    Source,
+    Rendered { overlays: bool },
+}
+
+impl CaptureKind {
+    fn of(options: &RecordingOptions) -> Self {
+        match options.source {
+            RecordingSource::Source => CaptureKind::Source,
+            RecordingSource::Rendered => CaptureKind::Rendered {
+                overlays: options.overlays,
+            },
+        }
+    }
+}
+
+/// A captured frame and what it is for
+struct CaptureTag {
+    frame: RecordedFrame,
+    captured_at: Instant,
+    for_recording: bool,
+    for_pre_trigger: bool,
+}
+
+/// Which frame of live view a processed frame being read back was rendered from
+struct ProcessedFrameTag {
+    frame_idx: Option<u32>,
+    /// Whether live view had just moved to the frame
+    new_frame: bool,
+    /// Time since live view started
+    elapsed_ms: Option<f64>,
+}
+
+struct GpuState<'a> {
+    // `None` in batch mode, which only renders offscreen
+    surface: Option<wgpu::Surface<'a>>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    render_pipeline: wgpu::RenderPipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    /// `None` when using the built-in `shader.wgsl`
+    custom_shader: Option<CustomShader>,
+    shader_params: ShaderParams,
+    shader_params_bind_group_layout: wgpu::BindGroupLayout,
+    shader_params_buffer: wgpu::Buffer,
+    shader_params_bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    frame_vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    diffuse_sampler: wgpu::Sampler,
+    diffuse_bind_group: wgpu::BindGroup,
+    diffuse_texture: wgpu::Texture,
+    previous_texture: wgpu::Texture,
+    current_frame: Frame,
+    previous_frame: Frame,
+    source_format: SourceFormat,
+    bad_pixels: BadPixels,
+    frame_idx: Option<u32>,
+    start_time: Option<Instant>,
+    // on the 0-100 metric scale, converted from and to the source's units at the commands
+    min_threshold: f32,
+    max_threshold: f32,
+    threshold_metric: ThresholdMetric,
+    auto_threshold_method: Option<AutoThresholdMethod>,
+    threshold_buffer: wgpu::Buffer,
+    threshold_bind_group: wgpu::BindGroup,
+    difference_mode: DifferenceMode,
+    motion_level: Option<f32>,
+    // the part of the 0-100 range shown from black to white
+    display_range: [f32; 2],
+    processing_buffer: wgpu::Buffer,
+    processing_bind_group_layout: wgpu::BindGroupLayout,
+    processing_bind_group: wgpu::BindGroup,
+    background: GpuBackground,
+    flat_field: FlatField,
+    lens_undistortion: LensUndistortion,
+    temporal_filter: GpuTemporalFilter,
+    filter_chain: GpuFilterChain,
+    filtered_texture: wgpu::Texture,
+    /// Renders the frame the thresholds are applied to, which the histogram and the other
+    /// analyses run on
+    processed_pipeline: wgpu::RenderPipeline,
+    processed_texture: wgpu::Texture,
+    processed_readbacks: Readbacks<ProcessedFrameTag>,
+    /// Frames are rendered into this at their own resolution for recordings, then read back
+    /// without waiting on the GPU
+    capture_texture: wgpu::Texture,
+    capture_readbacks: Readbacks<CaptureTag>,
+    histogram: GpuHistogram,
+    overlay: Overlay,
+    rois: Rois,
+    profile_line: Option<ProfileLine>,
+    spatial_calibration: Option<SpatialCalibration>,
+    scale_bar: bool,
+    recording: Option<Recording>,
+    pre_trigger: Option<PreTrigger>,
+    blob_detection: Option<BlobDetectionOptions>,
+    tracking: Option<TrackingOptions>,
+    tracker: Tracker,
+    /// Blobs from the last processed frame read back during live view
+    blobs: Vec<Blob>,
+}
+
+impl<'a> GpuState<'a> {
+    /// Set up the renderer for frames the size and format of `first_frame`, drawing to `surface`
+    /// if there is one or only offscreen otherwise. `config.format` is what the pipelines render
+    /// to.
+    fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        surface: Option<wgpu::Surface<'a>>,
+        config: wgpu::SurfaceConfiguration,
+        (first_frame, source_format): (Frame, SourceFormat),
+        calibration_dir: PathBuf,
+    ) -> Self {
+        // vertex buffer
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let frame_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Frame Vertex Buffer"),
+            contents: bytemuck::cast_slice(FRAME_VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        // index buffer
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        // texture
+        let frame_format = source_format.texture_format();
+        // deep frames can only be sampled without filtering on some devices
+        let filterable = frame::filterable(&device, frame_format);
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        // This should match the filterable field of the
+                        // corresponding Texture entry above.
+                        ty: wgpu::BindingType::Sampler(if filterable {
+                            wgpu::SamplerBindingType::Filtering
+                        } else {
+                            wgpu::SamplerBindingType::NonFiltering
+                        }),
+                        count: None,
+                    },
+                    // previous frame
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable },
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            });
+
+        let dimensions = first_frame.dimensions();
+
+        let texture_size = wgpu::Extent3d {
+            width: dimensions.0,
+            height: dimensions.1,
+            depth_or_array_layers: 1,
+        };
+        let (diffuse_texture, previous_texture) =
+            create_frame_textures(&device, texture_size, frame_format);
+        let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: if filterable {
+                wgpu::FilterMode::Linear
+            } else {
+                wgpu::FilterMode::Nearest
+            },
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let diffuse_bind_group = create_diffuse_bind_group(
+            &device,
+            &texture_bind_group_layout,
+            &diffuse_sampler,
+            &diffuse_texture,
+            &previous_texture,
+        );
+
+        frame::write_texture(&queue, &diffuse_texture, &first_frame);
+        frame::write_texture(&queue, &previous_texture, &first_frame);
+
+        let processed_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            label: Some("processed_texture"),
+            view_formats: &[],
+        });
+        let processed_readbacks = Readbacks::new(
+            &device,
+            "Processed Frame Readback Buffer",
+            NUM_PROCESSED_READBACKS,
+            TextureLayout::of(&processed_texture).buffer_size(),
+        );
+        let capture_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // the pipelines are built for the surface's format
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: Some("capture_texture"),
+            view_formats: &[],
+        });
+        let capture_readbacks = Readbacks::new(
+            &device,
+            "Capture Readback Buffer",
+            NUM_CAPTURE_READBACKS,
+            TextureLayout::of(&capture_texture).buffer_size(),
+        );
+        let histogram = GpuHistogram::new(
+            &device,
+            &processed_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture_size,
+        );
+        let background = GpuBackground::new(&device, &diffuse_texture, texture_size);
+        let temporal_filter = GpuTemporalFilter::new(&device, &diffuse_texture, texture_size);
+        let filter_chain = GpuFilterChain::new(
+            &device,
+            &diffuse_texture,
+            temporal_filter.output(),
+            texture_size,
+        );
+        let filtered_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: frame::intermediate_format(frame_format),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("filtered_texture"),
+            view_formats: &[],
+        });
+        let flat_field = FlatField::new(
+            &device,
+            &queue,
+            texture_size,
+            calibration_dir,
+            source_format,
+        );
+        let lens_undistortion = LensUndistortion::new(&device, texture_size);
+
+        // thresholds
+        let min_threshold = 0.0;
+        let max_threshold = 100.0;
+        let threshold_metric = ThresholdMetric::default();
+
+        let threshold_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Threshold Buffer"),
+            contents: bytemuck::bytes_of(&ThresholdUniform::new(
+                min_threshold,
+                max_threshold,
+                threshold_metric,
+            )),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let threshold_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("threshold_bind_group_layout"),
+            });
+
+        let threshold_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &threshold_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: threshold_buffer.as_entire_binding(),
+            }],
+            label: Some("camera_bind_group"),
+        });
+
+        // processing
+        let difference_mode = DifferenceMode::default();
+
+        let processing_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Processing Buffer"),
+            contents: bytemuck::bytes_of(&ProcessingUniform::new(
+                difference_mode,
+                false,
+                false,
+                None,
+                false,
+                display_as_stored(source_format.color_space, config.format),
+                [0.0, 100.0],
+            )),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let processing_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // background
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    // filtered, sampled with the same sampler as the frames
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable },
+                        },
+                        count: None,
+                    },
+                    // flat field dark frame
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    // flat field flat frame
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    // lens distortion remap
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("processing_bind_group_layout"),
+            });
+
+        let processing_bind_group = create_processing_bind_group(
+            &device,
+            &processing_bind_group_layout,
+            &processing_buffer,
+            &background,
+            &filtered_texture,
+            &flat_field,
+            &lens_undistortion,
+        );
+
+        // params for user shaders
+        let shader_params = ShaderParams::default();
+        let shader_params_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("shader_params_bind_group_layout"),
+            });
+        let (shader_params_buffer, shader_params_bind_group) =
+            create_shader_params_binding(&device, &shader_params_bind_group_layout, &shader_params);
+
+        // etc.
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &threshold_bind_group_layout,
+                &processing_bind_group_layout,
+                &shader_params_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let render_pipeline = create_render_pipeline(
+            &device,
+            &pipeline_layout,
+            config.format,
+            "shader.wgsl",
+            BUILTIN_SHADER,
+        )
+        .expect("built-in shader should be valid");
+        let processed_pipeline = create_render_pipeline(
+            &device,
+            &pipeline_layout,
+            processed_texture.format(),
+            "processed.wgsl",
+            PROCESSED_SHADER,
+        )
+        .expect("processed shader should be valid");
+
+        let overlay = Overlay::new(
+            &device,
+            config.format,
+            overlay_placement(VERTICES, texture_size),
+        );
+
+        GpuState {
+            surface,
+            device,
+            queue,
+            config,
+            render_pipeline,
+            render_pipeline_layout: pipeline_layout,
+            custom_shader: None,
+            shader_params,
+            shader_params_bind_group_layout,
+            shader_params_buffer,
+            shader_params_bind_group,
+            vertex_buffer,
+            frame_vertex_buffer,
+            index_buffer,
+            texture_bind_group_layout,
+            diffuse_sampler,
+            diffuse_bind_group,
+            diffuse_texture,
+            previous_texture,
+            previous_frame: first_frame.clone(),
+            current_frame: first_frame,
+            source_format,
+            bad_pixels: BadPixels::default(),
+            frame_idx: None,
+            start_time: None,
+            min_threshold,
+            max_threshold,
+            threshold_metric,
+            auto_threshold_method: None,
+            threshold_buffer,
+            threshold_bind_group,
+            difference_mode,
+            motion_level: None,
+            display_range: [0.0, 100.0],
+            processing_buffer,
+            processing_bind_group_layout,
+            processing_bind_group,
+            background,
+            flat_field,
+            lens_undistortion,
+            temporal_filter,
+            filter_chain,
+            filtered_texture,
+            processed_pipeline,
+            processed_texture,
+            processed_readbacks,
+            capture_texture,
+            capture_readbacks,
+            histogram,
+            overlay,
+            rois: Rois::default(),
+            profile_line: None,
+            spatial_calibration: None,
+            scale_bar: true,
+            recording: None,
+            pre_trigger: None,
+            blob_detection: None,
+            tracking: None,
+            tracker: Tracker::default(),
+            blobs: Vec::new(),
+        }
+    }
+
+    /// Load the flat field and spatial calibrations saved in `calibration_dir`. Both are tried
+    /// even if one fails to load, with the first error returned.
+    fn load_calibration(&mut self, calibration_dir: &Path) -> Result<(), String> {
+        let flat_field = self
+            .flat_field
+            .load_saved(&self.queue)
+            .map_err(|e| format!("failed to load flat field calibration: {}", e));
+        let spatial_calibration =
+            SpatialCalibration::load(&calibration_dir.join(SPATIAL_CALIBRATION_FILE))
+                .map(|calibration| self.spatial_calibration = calibration)
+                .map_err(|e| format!("failed to load spatial calibration: {}", e));
+        flat_field.and(spatial_calibration)
+    }
+
+    fn threshold_band(&self) -> ThresholdBand {
+        ThresholdBand {
+            min: self.min_threshold,
+            max: self.max_threshold,
+            metric: self.threshold_metric,
+        }
+    }
+
+    /// Native units per percent of the threshold metric, to report measurements made on the 0-100
+    /// metric scale in the same units as `native_thresholds`
+    fn metric_units_per_percent(&self) -> f64 {
+        self.source_format
+            .metric_units_per_percent(self.threshold_metric)
+    }
+
+    /// The min and max thresholds in the source's native units
+    fn native_thresholds(&self) -> [f64; 2] {
+        [self.min_threshold, self.max_threshold].map(|threshold| {
+            self.source_format
+                .threshold_from_percent(self.threshold_metric, threshold)
+        })
+    }
+
+    /// Switch to a shader and its params uniform buffer, keeping the current one if the pipeline
+    /// fails validation
+    fn use_shader(
+        &mut self,
+        label: &str,
+        source: &str,
+        params: ShaderParams,
+    ) -> Result<(), String> {
+        self.render_pipeline = create_render_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            self.config.format,
+            label,
+            source,
+        )?;
+        if self.shader_params_buffer.size() != shader_params_buffer_size(&params) {
+            (self.shader_params_buffer, self.shader_params_bind_group) =
+                create_shader_params_binding(
+                    &self.device,
+                    &self.shader_params_bind_group_layout,
+                    &params,
+                );
+        }
+        self.queue
+            .write_buffer(&self.shader_params_buffer, 0, params.data());
+        self.shader_params = params;
+        Ok(())
+    }
+
+    /// Set up a GPU and the renderer without a window, for batch mode
+    fn new_headless(
+        first_frame: (Frame, SourceFormat),
+        calibration_dir: PathBuf,
+    ) -> Result<Self, String> {
+        let instance = wgpu::Instance::default();
+        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }))
+        .ok_or_else(|| "failed to find a GPU adapter".to_string())?;
+        let (device, queue) = block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                // lets deep frames be filtered where the device can
+                required_features: adapter.features() & wgpu::Features::FLOAT32_FILTERABLE,
+                memory_hints: wgpu::MemoryHints::Performance,
+                required_limits: wgpu::Limits::default().using_resolution(adapter.limits()),
+            },
+            None,
+        ))
+        .map_err(|e| format!("failed to create device: {}", e))?;
+        // never used to configure a surface, but it is where the offscreen renders get their format
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: first_frame.0.width(),
+            height: first_frame.0.height(),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        Ok(Self::new(
+            device,
+            queue,
+            None,
+            config,
+            first_frame,
+            calibration_dir,
+        ))
+    }
+
+    /// Make `frame` the current frame after correcting its bad pixels, with the current frame
+    /// becoming the previous one for differencing
+    fn set_frame(&mut self, mut frame: Frame) {
+        self.bad_pixels.correct(&mut frame);
+        // move the current frame over on the GPU rather than uploading it again, submitting before
+        // the write below so the copy sees the current frame
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Previous Frame Encoder"),
+            });
+        encoder.copy_texture_to_texture(
+            self.diffuse_texture.as_image_copy(),
+            self.previous_texture.as_image_copy(),
+            self.diffuse_texture.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+        frame::write_texture(&self.queue, &self.diffuse_texture, &frame);
+        self.previous_frame = std::mem::replace(&mut self.current_frame, frame);
+    }
+
+    fn write_threshold_uniform(&self) {
+        self.queue.write_buffer(
+            &self.threshold_buffer,
+            0,
+            bytemuck::bytes_of(&ThresholdUniform::new(
+                self.min_threshold,
+                self.max_threshold,
+                self.threshold_metric,
+            )),
+        );
+    }
+
+    /// Update the processing uniform, taking frames from the temporal filter output if `temporal`
+    /// is set
+    fn write_processing_uniform(&self, temporal: bool) {
+        self.queue.write_buffer(
+            &self.processing_buffer,
+            0,
+            bytemuck::bytes_of(&ProcessingUniform::new(
+                self.difference_mode,
+                self.background.enabled(),
+                temporal || !self.filter_chain.is_empty(),
+                self.flat_field.mean(),
+                self.lens_undistortion.distortion().is_some(),
+                display_as_stored(self.source_format.color_space, self.config.format),
+                self.display_range,
+            )),
+        );
+    }
+
+    /// Run the spatial filter chain into the filtered texture, starting from the temporal filter
+    /// output if `temporal` is set
+    fn encode_filters(&self, encoder: &mut wgpu::CommandEncoder, temporal: bool) {
+        let filter_source = if temporal {
+            FilterSource::Temporal
+        } else {
+            FilterSource::Diffuse
+        };
+        let spatially_filtered = self.filter_chain.encode(
+            &self.queue,
+            encoder,
+            filter_source,
+            self.threshold_metric,
+            &self.filtered_texture,
+        );
+        if !spatially_filtered && filter_source == FilterSource::Temporal {
+            encoder.copy_texture_to_texture(
+                self.temporal_filter.output().as_image_copy(),
+                self.filtered_texture.as_image_copy(),
+                self.filtered_texture.size(),
+            );
+        }
+    }
+
+    /// Render the frame the thresholds are applied to into the processed texture, after the
+    /// filters have run
+    fn encode_processed(&self, encoder: &mut wgpu::CommandEncoder) {
+        let view = self
+            .processed_texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("processed_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.processed_pipeline);
+        rpass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+        rpass.set_bind_group(1, &self.threshold_bind_group, &[]);
+        rpass.set_bind_group(2, &self.processing_bind_group, &[]);
+        rpass.set_bind_group(3, &self.shader_params_bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.frame_vertex_buffer.slice(..));
+        rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        rpass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+    }
+
+    /// The current frame after lens undistortion, the filters, flat field correction, background
+    /// subtraction and differencing, which is what the thresholds are applied to. Pixels that
+    /// undistortion pulls in from beyond the edges of the frame are 0. This waits on the GPU.
+    fn render_processed(&self) -> Result<Frame, String> {
+        self.write_threshold_uniform();
+        let temporal = self.temporal_filter.enabled();
+        self.write_processing_uniform(temporal);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Processed Frame Encoder"),
+            });
+        self.encode_filters(&mut encoder, temporal);
+        self.encode_processed(&mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+        let texels = readback::read_texture(&self.device, &self.queue, &self.processed_texture)?;
+        Ok(self.processed_frame(&texels))
+    }
+
+    /// A processed frame from the unpadded texels of the processed texture
+    fn processed_frame(&self, texels: &[u8]) -> Frame {
+        let size = self.processed_texture.size();
+        Frame::from_raw(
+            size.width,
+            size.height,
+            bytemuck::pod_collect_to_vec(texels),
+        )
+        .expect("should be the right size")
+    }
+
+    /// Whether anything in live view needs the processed frames read back
+    fn analyses_processed_frames(&self) -> bool {
+        !self.rois.is_empty()
+            || self.blob_detection.is_some()
+            || self.tracking.is_some()
+            || self.auto_threshold_method.is_some()
+            || self
+                .pre_trigger
+                .as_ref()
+                .is_some_and(|pre_trigger| pre_trigger.options().area_percent.is_some())
+    }
+
+    /// Run the live view analyses on a processed frame that has been read back: the auto
+    /// threshold, ROI stats and blobs, with the ROI traces and tracks advancing on new frames
+    fn analyze_processed_frame(
+        &mut self,
+        app_handle: &AppHandle,
+        processed_frame: &Frame,
+        tag: ProcessedFrameTag,
+    ) {
+        // keep the auto threshold tracking new frames
+        if let (true, Some(method)) = (tag.new_frame, self.auto_threshold_method) {
+            let histogram = Histogram::from_image(processed_frame, self.threshold_metric);
+            self.min_threshold = method.compute(&histogram);
+            app_handle
+                .emit("auto-threshold", self.native_thresholds()[0])
+                .expect("should emit");
+        }
+
+        if !self.rois.is_empty() {
+            let units_per_percent = self.metric_units_per_percent();
+            let roi_stats: Vec<RoiStats> = self
+                .rois
+                .stats(
+                    processed_frame,
+                    self.threshold_band(),
+                    self.spatial_calibration.as_ref(),
+                )
+                .into_iter()
+                .map(|stats| stats.in_units(units_per_percent))
+                .collect();
+            app_handle
+                .emit("roi-stats", &roi_stats)
+                .expect("should emit");
+            if let (true, Some(frame_idx), Some(elapsed_ms)) =
+                (tag.new_frame, tag.frame_idx, tag.elapsed_ms)
+            {
+                let trace_points = self.rois.record(frame_idx, elapsed_ms, roi_stats);
+                app_handle
+                    .emit("roi-trace", trace_points)
+                    .expect("should emit");
+            }
+        }
+
+        // blobs, which tracking also needs
+        if self.blob_detection.is_none() && self.tracking.is_none() {
+            self.blobs.clear();
+            return;
+        }
+        let units_per_percent = self.metric_units_per_percent();
+        self.blobs = detect_blobs(
+            processed_frame,
+            self.threshold_band(),
+            &self.blob_detection.clone().unwrap_or_default(),
+            self.spatial_calibration.as_ref(),
+        )
+        .into_iter()
+        .map(|blob| blob.in_units(units_per_percent))
+        .collect();
+        if self.blob_detection.is_some() {
+            app_handle.emit("blobs", &self.blobs).expect("should emit");
+        }
+        if let (true, Some(options), Some(frame_idx), Some(elapsed_ms)) =
+            (tag.new_frame, &self.tracking, tag.frame_idx, tag.elapsed_ms)
+        {
+            let tracks = self
+                .tracker
+                .update(options, frame_idx, elapsed_ms, &self.blobs);
+            app_handle.emit("tracks", tracks).expect("should emit");
+        }
+    }
+
+    /// Render source frames offscreen the same way as the current frame, without overlays.
+    /// The temporal filter runs on its own accumulator over the clip, starting on the frames
+    /// before it that live view would have filtered. The background model doesn't advance.
+    /// `progress` is called with the number of frames rendered so far.
+    fn render_clip(
+        &self,
+        range: ClipRange,
+        mut progress: impl FnMut(u32),
+    ) -> Result<Vec<image::RgbaImage>, String> {
+        let load_corrected = |frame_idx| -> Result<Frame, String> {
+            let (mut frame, _) = load_frame(Some(frame_idx), Some(self.source_format.color_space))?;
+            self.bad_pixels.correct(&mut frame);
+            Ok(frame)
+        };
+        // live view's accumulator is left alone so it carries on where it was afterwards
+        let texture_size = self.diffuse_texture.size();
+        let mut temporal_filter = self.temporal_filter.filter().map(|filter| {
+            let mut temporal_filter =
+                GpuTemporalFilter::new(&self.device, &self.diffuse_texture, texture_size);
+            temporal_filter.set_filter(&self.device, Some(filter));
+            temporal_filter
+        });
+        let mut render = || -> Result<Vec<image::RgbaImage>, String> {
+            if let Some(temporal_filter) = &mut temporal_filter {
+                let warm_up_frames = temporal_filter
+                    .filter()
+                    .map_or(0, |filter| filter.warm_up_frames());
+                // the frames before the clip as live view loops through them, oldest first
+                for frames_before in (1..=warm_up_frames).rev() {
+                    let frame_idx =
+                        (range.start as i64 - frames_before as i64).rem_euclid(NUM_FRAMES as i64);
+                    frame::write_texture(
+                        &self.queue,
+                        &self.diffuse_texture,
+                        &load_corrected(frame_idx as u32)?,
+                    );
+                    let mut encoder =
+                        self.device
+                            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                                label: Some("Clip Warm Up Encoder"),
+                            });
+                    temporal_filter.encode(&self.queue, &mut encoder, &self.diffuse_texture);
+                    self.queue.submit(Some(encoder.finish()));
+                }
+            }
+            let mut frames = Vec::new();
+            let mut previous = load_corrected((range.start + NUM_FRAMES - 1) % NUM_FRAMES)?;
+            let temporal = temporal_filter.is_some();
+            self.write_processing_uniform(temporal);
+            for frame_idx in range.start..=range.end {
+                let current = load_corrected(frame_idx)?;
+                frame::write_texture(&self.queue, &self.diffuse_texture, &current);
+                frame::write_texture(&self.queue, &self.previous_texture, &previous);
+                let mut encoder =
+                    self.device
+                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("Clip Filter Encoder"),
+                        });
+                if let Some(temporal_filter) = &mut temporal_filter {
+                    // the filter chain reads the live filter's output
+                    temporal_filter.encode(&self.queue, &mut encoder, &self.diffuse_texture);
+                    temporal_filter.encode_copy_output(&mut encoder, self.temporal_filter.output());
+                }
+                self.encode_filters(&mut encoder, temporal);
+                self.queue.submit(Some(encoder.finish()));
+                frames.push(self.render_offscreen(false)?);
+                progress(frames.len() as u32);
+                previous = current;
+            }
+            Ok(frames)
+        };
+        let frames = render();
+
+        // put the current frame back
+        frame::write_texture(&self.queue, &self.diffuse_texture, &self.current_frame);
+        frame::write_texture(&self.queue, &self.previous_texture, &self.previous_frame);
+        let temporal = self.temporal_filter.enabled();
+        self.write_processing_uniform(temporal);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Clip Filter Encoder"),
+            });
+        if temporal_filter.is_some() {
+            self.temporal_filter
+                .encode_copy_output(&mut encoder, self.temporal_filter.output());
+        }
+        self.encode_filters(&mut encoder, temporal);
+        self.queue.submit(Some(encoder.finish()));
+        frames
+    }
+
+    /// Capture the current frame for the recording and the pre-trigger buffer, as `tag` says.
+    /// Source frames are handed over straight away and rendered ones once they have been read
+    /// back.
+    fn capture(&mut self, app_handle: &AppHandle, kind: CaptureKind, tag: CaptureTag) {
+        match kind {
+            CaptureKind::Source => {
+                let image = frame::to_display8(&self.current_frame, self.source_format.color_space);
+                self.deliver_capture(app_handle, tag, Ok(image));
+            }
+            CaptureKind::Rendered { overlays } => {
+                let for_recording = tag.for_recording;
+                if !self.start_capture(overlays, tag) && for_recording {
+                    if let Some(recording) = &mut self.recording {
+                        recording.note_dropped_frame();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hand a captured frame to the recording and the pre-trigger buffer. If it couldn't be
+    /// captured, whichever it was for stops and `recording-error` is emitted.
+    fn deliver_capture(
+        &mut self,
+        app_handle: &AppHandle,
+        tag: CaptureTag,
+        image: Result<image::RgbaImage, String>,
+    ) {
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => {
+                if let (true, Some(recording)) = (tag.for_recording, self.recording.take()) {
+                    abort_recording(
+                        app_handle,
+                        recording,
+                        format!("failed to record frame: {}", e),
+                    );
+                }
+                if let (true, Some(pre_trigger)) = (tag.for_pre_trigger, self.pre_trigger.take()) {
+                    app_handle
+                        .emit("recording-error", format!("stopped buffering: {}", e))
+                        .expect("should emit");
+                    if let Some(recording) = pre_trigger.finish() {
+                        finish_triggered_recording(app_handle, recording);
+                    }
+                }
+                return;
+            }
+        };
+        let (recorded_image, buffered_image) = if tag.for_recording && tag.for_pre_trigger {
+            (Some(image.clone()), Some(image))
+        } else if tag.for_recording {
+            (Some(image), None)
+        } else {
+            (None, Some(image))
+        };
+        if let (Some(image), Some(recording)) = (recorded_image, &mut self.recording) {
+            // anything captured before the recording started was for an earlier one
+            if tag.captured_at >= recording.start_time() {
+                recording.push(image, tag.frame, tag.captured_at);
+            }
+        }
+        if let (Some(image), Some(pre_trigger)) = (buffered_image, &mut self.pre_trigger) {
+            let finished = pre_trigger.push(BufferedFrame {
+                image,
+                frame: tag.frame,
+                captured_at: tag.captured_at,
+            });
+            if let Some(recording) = finished {
+                finish_triggered_recording(app_handle, recording);
+            }
+        }
+    }
+
+    /// Render the current frame the same way as on screen, but at the frame's own resolution, and
+    /// read it back. This waits on the GPU.
+    fn render_offscreen(&self, overlays: bool) -> Result<image::RgbaImage, String> {
+        let texture_size = self.diffuse_texture.size();
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // the pipelines are built for the surface's format
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: Some("offscreen_texture"),
+            view_formats: &[],
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen Encoder"),
+            });
+        self.encode_offscreen(&mut encoder, &texture, overlays);
+        self.submit_offscreen(encoder, overlays);
+        snapshot::read_texture(&self.device, &self.queue, &texture)
+    }
+
+    /// Render the current frame into `texture`, which is the frame's size, the same way as on
+    /// screen. Submit with `submit_offscreen` so the overlays land on the frame.
+    fn encode_offscreen(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        overlays: bool,
+    ) {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("offscreen_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+        rpass.set_bind_group(1, &self.threshold_bind_group, &[]);
+        rpass.set_bind_group(2, &self.processing_bind_group, &[]);
+        rpass.set_bind_group(3, &self.shader_params_bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.frame_vertex_buffer.slice(..));
+        rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        rpass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+        if overlays {
+            self.overlay.draw(&mut rpass);
+        }
+    }
+
+    /// Submit offscreen renders, with the overlays moved onto the frame for this submission only
+    fn submit_offscreen(&self, encoder: wgpu::CommandEncoder, overlays: bool) {
+        let texture_size = self.diffuse_texture.size();
+        if overlays {
+            self.overlay
+                .set_placement(&self.queue, overlay_placement(FRAME_VERTICES, texture_size));
+        }
+        self.queue.submit(Some(encoder.finish()));
+        if overlays {
+            // back to where the video is on screen for the next frame
+            self.overlay
+                .set_placement(&self.queue, overlay_placement(VERTICES, texture_size));
+        }
+    }
+
+    /// Render the current frame for the recording or the pre-trigger buffer and start reading it
+    /// back, without waiting on the GPU. Returns `false` if every capture buffer is still in use,
+    /// so the frame is dropped.
+    fn start_capture(&mut self, overlays: bool, tag: CaptureTag) -> bool {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+        self.encode_offscreen(&mut encoder, &self.capture_texture, overlays);
+        let Some(readback_buffer) = self.capture_readbacks.start(tag) else {
+            return false;
+        };
+        TextureLayout::of(&self.capture_texture).encode_copy(
+            &mut encoder,
+            &self.capture_texture,
+            readback_buffer,
+        );
+        self.submit_offscreen(encoder, overlays);
+        self.capture_readbacks.after_submit();
+        true
+    }
+
+    /// A rendered frame that has finished reading back, if there is one,
+    /// without blocking
+    fn poll_capture(&mut self) -> Option<(CaptureTag, Result<image::RgbaImage, String>)> {
+        let layout = TextureLayout::of(&self.capture_texture);
+        let (format, size) = (self.capture_texture.format(), self.capture_texture.size());
+        let (tag, texels) = self
+            .capture_readbacks
+            .poll(&self.device, |_, data| layout.unpad(data))?;
+        Some((
+            tag,
+            texels.and_then(|texels| snapshot::rgba_image(format, size, texels)),
+        ))
+    }
+
+    /// How frames are currently processed and measured, as saved by `save_processing_config`
+    fn processing_config(&self) -> ProcessingConfig {
+        let [min_threshold, max_threshold] = self.native_thresholds();
+        ProcessingConfig {
+            min_threshold,
+            max_threshold: Some(max_threshold),
+            threshold_metric: self.threshold_metric,
+            auto_threshold_method: self.auto_threshold_method,
+            difference_mode: self.difference_mode,
+            background_model: self.background.model(),
+            temporal_filter: self.temporal_filter.filter(),
+            filter_chain: self.filter_chain.stages().to_vec(),
+            flat_field: self.flat_field.enabled,
+            bad_pixel_map: self.bad_pixels.map().cloned(),
+            lens_distortion: self.lens_undistortion.distortion(),
+            spatial_calibration: self.spatial_calibration,
+            rois: self
+                .rois
+                .all()
+                .iter()
+                .map(|roi| roi.shape.clone())
+                .collect(),
+            blob_detection: self.blob_detection.clone(),
+        }
+    }
+
+    /// Process frames the way `config` says. Nothing changes if any of it is invalid.
+    fn apply_processing_config(&mut self, config: ProcessingConfig) -> Result<(), String> {
+        let lens_distortion = config
+            .lens_distortion
+            .map(LensDistortion::validated)
+            .transpose()?;
+        let spatial_calibration = config
+            .spatial_calibration
+            .map(SpatialCalibration::validated)
+            .transpose()?;
+        let previous_stages = self.filter_chain.stages().to_vec();
+        self.filter_chain
+            .set_stages(&self.device, config.filter_chain)?;
+        if let Err(e) = self.set_bad_pixel_map(config.bad_pixel_map) {
+            self.filter_chain
+                .set_stages(&self.device, previous_stages)
+                .expect("previous filter chain should be valid");
+            return Err(e);
+        }
+
+        let (source_format, metric) = (self.source_format, config.threshold_metric);
+        self.min_threshold = source_format.threshold_to_percent(metric, config.min_threshold);
+        self.max_threshold = config.max_threshold.map_or(100.0, |max_threshold| {
+            source_format.threshold_to_percent(metric, max_threshold)
+        });
+        self.threshold_metric = config.threshold_metric;
+        self.auto_threshold_method = config.auto_threshold_method;
+        self.difference_mode = config.difference_mode;
+        self.background
+            .set_model(&self.device, &self.queue, config.background_model);
+        self.temporal_filter
+            .set_filter(&self.device, config.temporal_filter);
+        self.flat_field.enabled = config.flat_field;
+        self.lens_undistortion
+            .set_distortion(&self.queue, lens_distortion);
+        if spatial_calibration.is_some() {
+            self.spatial_calibration = spatial_calibration;
+        }
+        self.rois = Rois::default();
+        for shape in config.rois {
+            self.rois.add(shape);
+        }
+        self.blob_detection = config.blob_detection;
+        Ok(())
+    }
+
+    /// Switch bad pixel maps, correcting the frames already on screen with the new one
+    fn set_bad_pixel_map(&mut self, map: Option<BadPixelMap>) -> Result<(), String> {
+        self.bad_pixels
+            .set_map(map, self.current_frame.dimensions())?;
+        self.bad_pixels.correct(&mut self.current_frame);
+        self.bad_pixels.correct(&mut self.previous_frame);
+        frame::write_texture(&self.queue, &self.diffuse_texture, &self.current_frame);
+        frame::write_texture(&self.queue, &self.previous_texture, &self.previous_frame);
+        Ok(())
+    }
+
+    /// Take the source's samples to be in `color_space`, reading the current frame and the
+    /// calibration frames again in it and rebuilding everything bound to the frame textures, whose
+    /// format can change with it. Intensity thresholds and the display range are converted so
+    /// they keep applying to the same stored values. The background and the temporal filter
+    /// start learning again.
+    fn set_color_space(&mut self, color_space: ColorSpace) -> Result<(), String> {
+        let previous_color_space = self.source_format.color_space;
+        if color_space == previous_color_space {
+            return Ok(());
+        }
+        let (mut frame, _) = load_frame(self.frame_idx, Some(color_space))?;
+        let source_format = SourceFormat {
+            color_space,
+            ..self.source_format
+        };
+        self.flat_field
+            .set_source_format(&self.queue, source_format)?;
+        self.source_format = source_format;
+
+        // the sample format doesn't change, so neither does whether the frames can be filtered and
+        // the layouts, pipelines and filtered texture all still fit
+        let texture_size = self.diffuse_texture.size();
+        (self.diffuse_texture, self.previous_texture) =
+            create_frame_textures(&self.device, texture_size, source_format.texture_format());
+        self.diffuse_bind_group = create_diffuse_bind_group(
+            &self.device,
+            &self.texture_bind_group_layout,
+            &self.diffuse_sampler,
+            &self.diffuse_texture,
+            &self.previous_texture,
+        );
+        self.bad_pixels.correct(&mut frame);
+        frame::write_texture(&self.queue, &self.diffuse_texture, &frame);
+        frame::write_texture(&self.queue, &self.previous_texture, &frame);
+        self.previous_frame = frame.clone();
+        self.current_frame = frame;
+
+        let background_model = self.background.model();
+        self.background = GpuBackground::new(&self.device, &self.diffuse_texture, texture_size);
+        self.background
+            .set_model(&self.device, &self.queue, background_model);
+        let temporal_filter = self.temporal_filter.filter();
+        self.temporal_filter =
+            GpuTemporalFilter::new(&self.device, &self.diffuse_texture, texture_size);
+        self.temporal_filter
+            .set_filter(&self.device, temporal_filter);
+        let filter_stages = self.filter_chain.stages().to_vec();
+        self.filter_chain = GpuFilterChain::new(
+            &self.device,
+            &self.diffuse_texture,
+            self.temporal_filter.output(),
+            texture_size,
+        );
+        self.filter_chain
+            .set_stages(&self.device, filter_stages)
+            .expect("current filter chain should be valid");
+        self.processing_bind_group = create_processing_bind_group(
+            &self.device,
+            &self.processing_bind_group_layout,
+            &self.processing_buffer,
+            &self.background,
+            &self.filtered_texture,
+            &self.flat_field,
+            &self.lens_undistortion,
+        );
+
+        let convert = |percent| previous_color_space.convert_percent(color_space, percent);
+        // hue and saturation are ratios, which don't depend on the encoding
+        if self.threshold_metric.is_intensity() {
+            self.min_threshold = convert(self.min_threshold);
+            self.max_threshold = convert(self.max_threshold);
+        }
+        self.display_range = self.display_range.map(convert);
+        Ok(())
+    }
+}
+
+/// Whether raw samples need decoding before being written to `format` so they are shown as they
+/// are stored. Only sRGB targets encode what is written to them as light.
+fn display_as_stored(color_space: ColorSpace, format: wgpu::TextureFormat) -> bool {
+    color_space == ColorSpace::Raw && format.is_srgb()
+}
+
+/// The current and previous frame textures for frames of `format`
+fn create_frame_textures(
+    device: &wgpu::Device,
+    texture_size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+) -> (wgpu::Texture, wgpu::Texture) {
+    let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
+        size: texture_size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        // copied into the previous texture and the frame histories
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        label: Some("diffuse_texture"),
+        view_formats: &[],
+    });
+    let previous_texture = device.create_texture(&wgpu::TextureDescriptor {
+        size: texture_size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some("previous_texture"),
+        view_formats: &[],
+    });
+    (diffuse_texture, previous_texture)
+}
+
+fn create_diffuse_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    diffuse_texture: &wgpu::Texture,
+    previous_texture: &wgpu::Texture,
+) -> wgpu::BindGroup {
+    let diffuse_texture_view = diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let previous_texture_view =
+        previous_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&previous_texture_view),
+            },
+        ],
+        label: Some("diffuse_bind_group"),
+    })
+}
+
+fn create_processing_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    processing_buffer: &wgpu::Buffer,
+    background: &GpuBackground,
+    filtered_texture: &wgpu::Texture,
+    flat_field: &FlatField,
+    lens_undistortion: &LensUndistortion,
+) -> wgpu::BindGroup {
+    let filtered_texture_view =
+        filtered_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: processing_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(background.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&filtered_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(flat_field.dark_view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(flat_field.flat_view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(lens_undistortion.view()),
+            },
+        ],
+        label: Some("processing_bind_group"),
+    })
+}
+
+fn shader_params_buffer_size(params: &ShaderParams) -> wgpu::BufferAddress {
+    // the built-in shader doesn't have params, but something still needs to be bound
+    params.data().len().max(16) as wgpu::BufferAddress
+}
+
+fn create_shader_params_binding(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    params: &ShaderParams,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Shader Params Buffer"),
+        size: shader_params_buffer_size(params),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+        label: Some("shader_params_bind_group"),
+    });
+    (buffer, bind_group)
+}
+
+// TODO
+// 1) don't use a new webview (Is this already done?)
+//  - create components around the video player that do not have transparent backgrounds
+//
+// IDEAS:
+//  - instead of looping inside the command, create another task that just sends the next frame idx
+//    periodically according to the frame rate
+//  ? make some resizable component in the FE, send the size and position down to rust, have that
+//    control where the video is rendered in the shader
+
+/// Where overlays go for video drawn with the given vertices
+fn overlay_placement(vertices: &[Vertex], texture_size: wgpu::Extent3d) -> OverlayPlacement {
+    OverlayPlacement::new(
+        [vertices[0].position[0], vertices[0].position[1]],
+        [vertices[2].position[0], vertices[2].position[1]],
+        texture_size,
+    )
+}
+
+/// Build the render pipeline for a shader, returning any validation error rather than letting it
+/// reach the device's uncaptured error handler, which would panic. naga has already checked user
+/// shaders by now, so this catches what only the pipeline can, like bindings that don't match the
+/// layout.
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    label: &str,
+    source: &str,
+) -> Result<wgpu::RenderPipeline, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+
+    let vertex_buffer_layout = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            wgpu::VertexAttribute {
+                offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+        ],
+    };
+
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            buffers: &[vertex_buffer_layout],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            targets: &[Some(format.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+    // wgpu settles error scopes as they are popped, and this can run inside a command where
+    // blocking on the async runtime would panic, so poll the future once rather than waiting on it
+    let error = std::pin::pin!(device.pop_error_scope());
+    match Future::poll(error, &mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(Some(error)) => Err(error.to_string()),
+        Poll::Ready(None) | Poll::Pending => Ok(pipeline),
+    }
+}
+
+/// Where the flat field frames, spatial calibration and colour space of the source are saved
+fn calibration_dir() -> PathBuf {
+    Path::new(SOURCE_DIR).join("calibration")
+}
+
+fn next_triangle(app_handle: &AppHandle, new_size: Option<PhysicalSize<u32>>) -> bool {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+
+    // TODO try removing these inner brackets
+    {
+        let mut gpu_state = gpu_state_mutex.lock().unwrap();
+        let gpu_state = &mut *gpu_state;
+
+        // check and see if reconfig is needed
+        if let Some(new_size) = new_size {
+            gpu_state.config.width = if new_size.width > 0 {
+                new_size.width
+            } else {
+                1
+            };
+            gpu_state.config.height = if new_size.height > 0 {
+                new_size.height
+            } else {
+                1
+            };
+            if let Some(surface) = &gpu_state.surface {
+                surface.configure(&gpu_state.device, &gpu_state.config);
+            }
+        }
+
+        // update frame idx if necessary
+        let next_frame_idx = if let Some(start_time) = gpu_state.start_time {
+            let whole_periods_elapsed =
+                Instant::now().duration_since(start_time).as_millis() / FRAME_RATE.as_millis();
+            let next_frame_idx = (whole_periods_elapsed % (NUM_FRAMES as u128)) as u32;
+            // TODO remove debug
+            if let Some(frame_idx) = gpu_state.frame_idx {
+                if next_frame_idx != frame_idx && next_frame_idx > (frame_idx + 1) % NUM_FRAMES {
+                    println!("\n\n------------- FRAME(S) DROPPED -------------\n\n")
+                }
+            }
+            Some(next_frame_idx)
+        } else {
+            None
+        };
+        // if on a new frame idx, update the image
+        let new_frame = next_frame_idx != gpu_state.frame_idx;
+        let mut trigger_reason = None;
+        if new_frame {
+            gpu_state.frame_idx = next_frame_idx;
+            let (frame, _) = load_frame(next_frame_idx, Some(gpu_state.source_format.color_space))
+                .expect("should read");
+            gpu_state.set_frame(frame);
+
+            if gpu_state.difference_mode != DifferenceMode::Off || gpu_state.motion_level.is_some()
+            {
+                let motion = MotionScore {
+                    frame_idx: gpu_state.frame_idx,
+                    score: motion_score(
+                        &gpu_state.current_frame,
+                        &gpu_state.previous_frame,
+                        gpu_state.threshold_metric,
+                    ) * gpu_state.metric_units_per_percent() as f32,
+                };
+                if gpu_state
+                    .motion_level
+                    .is_some_and(|motion_level| motion.score > motion_level)
+                {
+                    if gpu_state
+                        .pre_trigger
+                        .as_ref()
+                        .is_some_and(|pre_trigger| pre_trigger.options().on_motion)
+                    {
+                        trigger_reason = Some(TriggerReason::Motion);
+                    }
+                    app_handle
+                        .emit("motion-detected", &motion)
+                        .expect("should emit");
+                }
+                app_handle
+                    .emit("motion-score", motion)
+                    .expect("should emit");
+            }
+        }
+
+        // analyses of the processed frames that have finished reading back, which are a frame or
+        // two behind what is on screen
+        let processed = gpu_state
+            .processed_readbacks
+            .poll(&gpu_state.device, |_, data| {
+                TextureLayout::of(&gpu_state.processed_texture).unpad(data)
+            });
+        match processed {
+            Some((tag, Ok(texels))) => {
+                let processed_frame = gpu_state.processed_frame(&texels);
+                let new_frame = tag.new_frame;
+                gpu_state.analyze_processed_frame(app_handle, &processed_frame, tag);
+                if new_frame
+                    && gpu_state.pre_trigger.as_ref().is_some_and(|pre_trigger| {
+                        pre_trigger.area_exceeded(&processed_frame, gpu_state.threshold_band())
+                    })
+                {
+                    trigger_reason = trigger_reason.or(Some(TriggerReason::ThresholdArea));
+                }
+            }
+            Some((_, Err(e))) => eprintln!("failed to read back processed frame: {}", e),
+            None => (),
+        }
+
+        // handle thresholding
+        gpu_state.write_threshold_uniform();
+
+        gpu_state.write_processing_uniform(gpu_state.temporal_filter.enabled());
+
+        // send out any histograms that have finished reading back
+        match gpu_state
+            .histogram
+            .poll(&gpu_state.device, gpu_state.source_format)
+        {
+            Some(Ok(histogram)) => app_handle
+                .emit("histogram", histogram)
+                .expect("should emit"),
+            Some(Err(e)) => eprintln!("failed to read back histogram: {}", e),
+            None => (),
+        }
+
+        // overlays
+        let mut overlay_lines = OverlayLines::default();
+        if let Some(options) = &gpu_state.blob_detection {
+            if options.draw_outlines {
+                draw_blobs(&gpu_state.blobs, &mut overlay_lines);
+            }
+        }
+        gpu_state.rois.draw(&mut overlay_lines);
+        if let Some(profile_line) = &gpu_state.profile_line {
+            profile_line.draw(&mut overlay_lines);
+        }
+        if let Some(options) = &gpu_state.tracking {
+            gpu_state.tracker.draw(options, &mut overlay_lines);
+        }
+        if let (true, Some(calibration)) = (gpu_state.scale_bar, &gpu_state.spatial_calibration) {
+            calibration.draw_scale_bar(gpu_state.current_frame.dimensions(), &mut overlay_lines);
+        }
+        gpu_state
+            .overlay
+            .set_lines(&gpu_state.device, &gpu_state.queue, &overlay_lines);
+
+        // render
+        let frame = gpu_state
+            .surface
+            .as_ref()
+            .expect("live view should have a window")
+            .get_current_texture()
+            .expect("Failed to acquire next swap chain texture");
+        let view = frame
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = gpu_state
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        if new_frame {
+            gpu_state.temporal_filter.encode(
+                &gpu_state.queue,
+                &mut encoder,
+                &gpu_state.diffuse_texture,
+            );
+            gpu_state
+                .background
+                .encode(&gpu_state.queue, &mut encoder, &gpu_state.diffuse_texture);
+        }
+        // the spatial filters run every frame so changes to them show up even when paused
+        gpu_state.encode_filters(&mut encoder, gpu_state.temporal_filter.enabled());
+        gpu_state.encode_processed(&mut encoder);
+        // read back every new frame, and otherwise whenever the last one is in, so the analyses
+        // keep up with setting changes while paused
+        if gpu_state.analyses_processed_frames()
+            && (new_frame || !gpu_state.processed_readbacks.is_busy())
+        {
+            let tag = ProcessedFrameTag {
+                frame_idx: gpu_state.frame_idx,
+                new_frame,
+                elapsed_ms: gpu_state.start_time.map(|start_time| {
+                    Instant::now().duration_since(start_time).as_secs_f64() * 1000.0
+                }),
+            };
+            let layout = TextureLayout::of(&gpu_state.processed_texture);
+            if let Some(readback_buffer) = gpu_state.processed_readbacks.start(tag) {
+                layout.encode_copy(&mut encoder, &gpu_state.processed_texture, readback_buffer);
+            }
+        }
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(&gpu_state.render_pipeline);
+            rpass.set_bind_group(0, &gpu_state.diffuse_bind_group, &[]);
+            rpass.set_bind_group(1, &gpu_state.threshold_bind_group, &[]);
+            rpass.set_bind_group(2, &gpu_state.processing_bind_group, &[]);
+            rpass.set_bind_group(3, &gpu_state.shader_params_bind_group, &[]);
+            rpass.set_vertex_buffer(0, gpu_state.vertex_buffer.slice(..));
+            rpass.set_index_buffer(gpu_state.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            rpass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+            gpu_state.overlay.draw(&mut rpass);
+        }
+        gpu_state
+            .histogram
+            .encode(&gpu_state.queue, &mut encoder, gpu_state.threshold_metric);
+
+        gpu_state.queue.submit(Some(encoder.finish()));
+        gpu_state.histogram.after_submit();
+        gpu_state.processed_readbacks.after_submit();
+        frame.present();
+
+        // recording and the pre-trigger buffer, with the overlays still drawn from this frame
+        let captured_at = Instant::now();
+        let [min_threshold, max_threshold] = gpu_state.native_thresholds();
+        let recorded_frame = RecordedFrame {
+            frame_idx: gpu_state.frame_idx,
+            min_threshold,
+            max_threshold,
+            threshold_metric: gpu_state.threshold_metric,
+        };
+        if let (true, Some(frame_idx)) = (new_frame, gpu_state.frame_idx) {
+            if let Some(recording) = &mut gpu_state.recording {
+                recording.note_source_frame(frame_idx);
+            }
+            if let Some(pre_trigger) = &mut gpu_state.pre_trigger {
+                pre_trigger.note_source_frame(frame_idx);
+            }
+        }
+        // rendered frames are handed over once they have been read back, a frame or two later
+        if let Some((tag, image)) = gpu_state.poll_capture() {
+            gpu_state.deliver_capture(app_handle, tag, image);
+        }
+
+        if let (Some(reason), Some(pre_trigger)) = (trigger_reason, &mut gpu_state.pre_trigger) {
+            match pre_trigger.trigger(
+                reason,
+                gpu_state.current_frame.dimensions(),
+                FRAME_RATE,
+                NUM_FRAMES,
+            ) {
+                Ok(true) => app_handle
+                    .emit("recording-triggered", reason)
+                    .expect("should emit"),
+                Ok(false) => (),
+                Err(e) => app_handle
+                    .emit(
+                        "recording-error",
+                        format!("failed to start triggered recording: {}", e),
+                    )
+                    .expect("should emit"),
+            }
+        }
+
+        let recording_kind = gpu_state
+            .recording
+            .as_ref()
+            .filter(|recording| recording.options().wants_frame(new_frame))
+            .map(|recording| CaptureKind::of(recording.options()));
+        let pre_trigger_kind = gpu_state
+            .pre_trigger
+            .as_ref()
+            .filter(|pre_trigger| pre_trigger.recording_options().wants_frame(new_frame))
+            .map(|pre_trigger| CaptureKind::of(pre_trigger.recording_options()));
+        let tag = |for_recording, for_pre_trigger| CaptureTag {
+            frame: recorded_frame,
+            captured_at,
+            for_recording,
+            for_pre_trigger,
+        };
+        match (recording_kind, pre_trigger_kind) {
+            // one capture does for both
+            (Some(recording_kind), Some(pre_trigger_kind))
+                if recording_kind == pre_trigger_kind =>
+            {
+                gpu_state.capture(app_handle, recording_kind, tag(true, true));
+            }
+            (recording_kind, pre_trigger_kind) => {
+                if let Some(kind) = recording_kind {
+                    gpu_state.capture(app_handle, kind, tag(true, false));
+                }
+                if let Some(kind) = pre_trigger_kind {
+                    gpu_state.capture(app_handle, kind, tag(false, true));
+                }
+            }
+        }
+
+        next_frame_idx.is_some()
+    }
+}
+
+#[tauri::command]
+async fn start_live_view(app_handle: AppHandle) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let now: Instant;
+    {
+        let mut gpu_state = gpu_state_mutex.lock().unwrap();
+        now = Instant::now();
+        gpu_state.start_time = Some(now);
+        gpu_state.rois.clear_traces();
+        gpu_state.tracker.reset();
+    }
+
+    let mut deadline = now + FRAME_RATE;
+    loop {
+        if !next_triangle(&app_handle, None) {
+            return;
+        }
+        sleep_until(deadline).await;
+        deadline += FRAME_RATE;
+    }
+}
+
+#[tauri::command]
+async fn stop_live_view(app_handle: AppHandle) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.start_time = None;
+    // TODO make consts for these default values
+    gpu_state.min_threshold = 0.0;
+    gpu_state.max_threshold = 100.0;
+    gpu_state.auto_threshold_method = None;
+}
+
+/// The source's sample format, colour space and full scale, which is the range thresholds are
+/// given in for intensity metrics
+#[tauri::command]
+async fn get_sample_range(app_handle: AppHandle) -> SampleRange {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.source_format.into()
+}
+
+/// Set what the source's samples mean, saving it with the source's calibration. Thresholds and
+/// the display range are converted to keep applying to the same stored values, so their native
+/// units change along with `get_sample_range`.
+#[tauri::command]
+async fn set_color_space(app_handle: AppHandle, color_space: ColorSpace) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.set_color_space(color_space)?;
+    color_space.save(&calibration_dir().join(COLOR_SPACE_FILE))
+}
+
+/// Set the min threshold in the source's native units, e.g. 0-255 for linear 8 bit frames or
+/// 0-65535 for 16 bit ones, except for hue, saturation and sRGB sources which are 0-100
+#[tauri::command]
+async fn set_min_threshold(app_handle: AppHandle, new_min_threshold: f64) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.min_threshold = gpu_state
+        .source_format
+        .threshold_to_percent(gpu_state.threshold_metric, new_min_threshold);
+    // a manually entered threshold takes over from the automatic one
+    gpu_state.auto_threshold_method = None;
+}
+
+/// Set the max threshold in the same units as `set_min_threshold`
+#[tauri::command]
+async fn set_max_threshold(app_handle: AppHandle, new_max_threshold: f64) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.max_threshold = gpu_state
+        .source_format
+        .threshold_to_percent(gpu_state.threshold_metric, new_max_threshold);
+}
+
+/// Show `range` of the channels from black to white, in the source's native units, or the full
+/// range with `None`
+#[tauri::command]
+async fn set_display_range(app_handle: AppHandle, range: Option<[f64; 2]>) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let units_per_percent = gpu_state.source_format.units_per_percent();
+    let display_range = range.map_or([0.0, 100.0], |range| {
+        range.map(|native| (native / units_per_percent) as f32)
+    });
+    if display_range[0] >= display_range[1] {
+        return Err("display range min must be less than the max".to_string());
+    }
+    gpu_state.display_range = display_range;
+    Ok(())
+}
+
+/// Fit the display range to the darkest and brightest channels of the processed frame, returning
+/// it in the source's native units
+#[tauri::command]
+async fn auto_display_range(app_handle: AppHandle) -> Result<[f64; 2], String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let (low, high) = gpu_state
+        .render_processed()?
+        .pixels()
+        .flat_map(|pixel| &pixel.0[..3])
+        .fold((f32::MAX, f32::MIN), |(low, high), &c| {
+            (low.min(c), high.max(c))
+        });
+    // a flat frame keeps the full range rather than dividing by 0
+    if low < high {
+        gpu_state.display_range = [low * 100.0, high * 100.0];
+    }
+    let units_per_percent = gpu_state.source_format.units_per_percent();
+    Ok(gpu_state
+        .display_range
+        .map(|percent| percent as f64 * units_per_percent))
+}
+
+#[tauri::command]
+async fn set_threshold_metric(app_handle: AppHandle, new_threshold_metric: ThresholdMetric) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.threshold_metric = new_threshold_metric;
+}
+
+/// Compute a min threshold from the histogram of the processed frame and apply it. If
+/// `recompute_each_frame` is set, the threshold is recomputed whenever live view moves to a new
+/// frame and emitted as an `auto-threshold` event, until a min threshold is set manually.
+#[tauri::command]
+async fn auto_threshold(
+    app_handle: AppHandle,
+    method: AutoThresholdMethod,
+    recompute_each_frame: bool,
+) -> Result<f64, String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let histogram =
+        Histogram::from_image(&gpu_state.render_processed()?, gpu_state.threshold_metric);
+    gpu_state.min_threshold = method.compute(&histogram);
+    gpu_state.auto_threshold_method = recompute_each_frame.then_some(method);
+    Ok(gpu_state.native_thresholds()[0])
+}
+
+/// Set how many times per second a `histogram` event is emitted during live view, or 0 to stop
+/// them.
+#[tauri::command]
+async fn set_histogram_rate(app_handle: AppHandle, new_histogram_rate: f64) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.histogram.interval =
+        (new_histogram_rate > 0.0).then(|| Duration::from_secs_f64(1.0 / new_histogram_rate));
+}
+
+#[tauri::command]
+async fn set_histogram_per_channel(app_handle: AppHandle, new_histogram_per_channel: bool) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.histogram.per_channel = new_histogram_per_channel;
+}
+
+/// Add an ROI, returning its id
+#[tauri::command]
+async fn add_roi(app_handle: AppHandle, shape: RoiShape) -> u32 {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.rois.add(shape)
+}
+
+#[tauri::command]
+async fn update_roi(app_handle: AppHandle, roi_id: u32, shape: RoiShape) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.rois.update(roi_id, shape)
+}
+
+#[tauri::command]
+async fn remove_roi(app_handle: AppHandle, roi_id: u32) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.rois.remove(roi_id)
+}
+
+#[tauri::command]
+async fn get_rois(app_handle: AppHandle) -> Vec<Roi> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.rois.all().to_vec()
+}
+
+/// Compute the stats of every ROI over the processed frame. The same stats are emitted as a
+/// `roi-stats` event on every frame while there are any ROIs.
+#[tauri::command]
+async fn get_roi_stats(app_handle: AppHandle) -> Result<Vec<RoiStats>, String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    let units_per_percent = gpu_state.metric_units_per_percent();
+    Ok(gpu_state
+        .rois
+        .stats(
+            &gpu_state.render_processed()?,
+            gpu_state.threshold_band(),
+            gpu_state.spatial_calibration.as_ref(),
+        )
+        .into_iter()
+        .map(|stats| stats.in_units(units_per_percent))
+        .collect())
+}
+
+/// Get the samples of an ROI's stats recorded during the current or most recent live view.
+/// New samples are emitted as `roi-trace` events as they are recorded.
+#[tauri::command]
+async fn get_roi_trace(app_handle: AppHandle, roi_id: u32) -> Result<Vec<RoiTracePoint>, String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.rois.trace(roi_id)
+}
+
+#[tauri::command]
+async fn export_roi_trace(app_handle: AppHandle, roi_id: u32, path: PathBuf) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.rois.write_trace_csv(roi_id, &path)
+}
+
+/// Sample the threshold metric of the processed frame along a line, averaging `width` pixels
+/// perpendicular to it. The line is clamped to the frame and its width to the frame's diagonal. It
+/// stays drawn over the video until `clear_line_profile` is called.
+#[tauri::command]
+async fn line_profile(
+    app_handle: AppHandle,
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+    width: u32,
+) -> Result<LineProfile, String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let profile_line = ProfileLine::clamped(
+        [x0, y0],
+        [x1, y1],
+        width,
+        gpu_state.current_frame.dimensions(),
+    )?;
+    gpu_state.profile_line = Some(profile_line);
+    Ok(profile_line
+        .profile(
+            &gpu_state.render_processed()?,
+            gpu_state.threshold_band(),
+            gpu_state.spatial_calibration.as_ref(),
+        )
+        .in_units(gpu_state.metric_units_per_percent()))
+}
+
+#[tauri::command]
+async fn clear_line_profile(app_handle: AppHandle) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.profile_line = None;
+}
+
+/// Save the current frame as a PNG or TIFF, rendered through the full pipeline at the frame's
+/// resolution unless `options.unprocessed` is set
+#[tauri::command]
+async fn save_snapshot(
+    app_handle: AppHandle,
+    path: PathBuf,
+    options: Option<SnapshotOptions>,
+) -> Result<(), String> {
+    let options = options.unwrap_or_default();
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    let image = if options.unprocessed {
+        frame::to_image(&gpu_state.current_frame, gpu_state.source_format)
+    } else {
+        gpu_state.render_offscreen(options.overlays)?.into()
+    };
+    // no need to hold up rendering while encoding
+    drop(gpu_state);
+    snapshot::save_image(&image, &path)
+}
+
+/// Start recording every presented frame, or every new source frame, to an image sequence or
+/// through ffmpeg to a video. Frames the writer can't keep up with are dropped and counted. If a
+/// frame can't be captured, the recording stops and `recording-error` is emitted.
+#[tauri::command]
+async fn start_recording(app_handle: AppHandle, options: RecordingOptions) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    if gpu_state.recording.is_some() {
+        return Err("already recording".to_string());
+    }
+    let size = gpu_state.current_frame.dimensions();
+    gpu_state.recording = Some(Recording::start(options, size, FRAME_RATE, NUM_FRAMES)?);
+    Ok(())
+}
+
+/// Stop recording, waiting for the queued frames to be written, and write the sidecar JSON with
+/// each frame's timestamp and threshold settings
+#[tauri::command]
+async fn stop_recording(app_handle: AppHandle) -> Result<RecordingSummary, String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let recording = gpu_state_mutex.lock().unwrap().recording.take();
+    // rendering can carry on while the last frames are written
+    recording
+        .ok_or_else(|| "not recording".to_string())?
+        .finish()
+}
+
+/// Save how frames are currently processed and measured, to be loaded again or used in batch mode
+#[tauri::command]
+async fn save_processing_config(app_handle: AppHandle, path: PathBuf) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.processing_config().save(&path)
+}
+
+#[tauri::command]
+async fn load_processing_config(
+    app_handle: AppHandle,
+    path: PathBuf,
+) -> Result<ProcessingConfig, String> {
+    let config = ProcessingConfig::load(&path)?;
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.apply_processing_config(config.clone())?;
+    Ok(config)
+}
+
+/// Load a frame of the source at its full depth, or the default image if there is no frame index.
+/// Frames are read in `color_space`, or the default for their sample format if `None`.
+fn load_frame(
+    frame_idx: Option<u32>,
+    color_space: Option<ColorSpace>,
+) -> Result<(Frame, SourceFormat), String> {
+    let img_name = if let Some(frame_idx) = frame_idx {
+        format!("happy-tree-{}", frame_idx + 1)
+    } else {
+        "default".to_string()
+    };
+    frame::open(
+        &Path::new(SOURCE_DIR).join(format!("{}.png", img_name)),
+        color_space,
+    )
+}
+
+/// Render source frames `range` through the current processing offscreen and encode them as an
+/// animated GIF or APNG at the live view frame rate. `clip-progress` events are emitted while
+/// frames are rendered and then encoded.
+#[tauri::command]
+async fn export_clip(
+    app_handle: AppHandle,
+    path: PathBuf,
+    range: ClipRange,
+    format: ClipFormat,
+    options: Option<ClipOptions>,
+) -> Result<(), String> {
+    let options = options.unwrap_or_default().validated()?;
+    if range.start > range.end || range.end >= NUM_FRAMES {
+        return Err(format!(
+            "frame range must be within 0-{}, start to end",
+            NUM_FRAMES - 1
+        ));
+    }
+    let total = range.end - range.start + 1;
+    let emit_progress = |stage, done| {
+        app_handle
+            .emit("clip-progress", ClipProgress { stage, done, total })
+            .expect("should emit");
+    };
+
+    let frames = {
+        let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+        let gpu_state = gpu_state_mutex.lock().unwrap();
+        gpu_state.render_clip(range, |done| emit_progress(ClipStage::Rendering, done))?
+    };
+    // no need to hold up rendering while encoding
+    encode_clip(&frames, &path, format, &options, FRAME_RATE, |done| {
+        emit_progress(ClipStage::Encoding, done)
+    })
+}
+
+/// Stop a recording that can't carry on, finishing what it has so far without holding up rendering
+/// and emitting `recording-error` with why
+fn abort_recording(app_handle: &AppHandle, recording: Recording, error: String) {
+    let app_handle = app_handle.clone();
+    std::thread::spawn(move || {
+        let error = match recording.finish() {
+            Ok(_) => error,
+            Err(e) => format!("{}, then {}", error, e),
+        };
+        app_handle
+            .emit("recording-error", error)
+            .expect("should emit");
+    });
+}
+
+/// Finish a triggered recording without holding up rendering, emitting `triggered-recording` with
+/// its summary once it is written or `recording-error` if it couldn't be
+fn finish_triggered_recording(app_handle: &AppHandle, recording: Recording) {
+    let app_handle = app_handle.clone();
+    std::thread::spawn(move || match recording.finish() {
+        Ok(summary) => app_handle
+            .emit("triggered-recording", summary)
+            .expect("should emit"),
+        Err(e) => app_handle
+            .emit(
+                "recording-error",
+                format!("failed to finish triggered recording: {}", e),
+            )
+            .expect("should emit"),
+    });
+}
+
+/// Keep the last few seconds of frames in memory while live view runs, and whenever a trigger
+/// fires write them out, along with the frames up to `post_trigger_seconds` after it, to a new
+/// recording in the directory `recording.path`. `recording-triggered` is emitted as each one
+/// starts, and `recording-error` if one fails or a frame can't be captured, which stops buffering.
+#[tauri::command]
+async fn start_pre_trigger(
+    app_handle: AppHandle,
+    recording: RecordingOptions,
+    options: Option<PreTriggerOptions>,
+) -> Result<(), String> {
+    let pre_trigger = PreTrigger::new(recording, options.unwrap_or_default())?;
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    if let Some(recording) = gpu_state
+        .pre_trigger
+        .replace(pre_trigger)
+        .and_then(PreTrigger::finish)
+    {
+        finish_triggered_recording(&app_handle, recording);
+    }
+    Ok(())
+}
+
+/// Stop buffering, finishing any triggered recording in progress
+#[tauri::command]
+async fn stop_pre_trigger(app_handle: AppHandle) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    if let Some(recording) = gpu_state.pre_trigger.take().and_then(PreTrigger::finish) {
+        finish_triggered_recording(&app_handle, recording);
+    }
+}
+
+/// Write out the pre-trigger buffer and keep recording, or extend the recording if one was
+/// already triggered
+#[tauri::command]
+async fn trigger_recording(app_handle: AppHandle) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    let pre_trigger = gpu_state
+        .pre_trigger
+        .as_mut()
+        .ok_or_else(|| "pre-trigger buffering isn't on".to_string())?;
+    if pre_trigger.trigger(
+        TriggerReason::Manual,
+        gpu_state.current_frame.dimensions(),
+        FRAME_RATE,
+        NUM_FRAMES,
+    )? {
+        app_handle
+            .emit("recording-triggered", TriggerReason::Manual)
+            .expect("should emit");
+    }
+    Ok(())
+}
+
+/// Set the physical size of the source's pixels, or remove the calibration with `None`. The
+/// calibration is saved with the source.
+#[tauri::command]
+async fn set_spatial_calibration(
+    app_handle: AppHandle,
+    new_spatial_calibration: Option<SpatialCalibration>,
+) -> Result<(), String> {
+    let new_spatial_calibration = new_spatial_calibration
+        .map(SpatialCalibration::validated)
+        .transpose()?;
+    SpatialCalibration::save(
+        new_spatial_calibration.as_ref(),
+        &calibration_dir().join(SPATIAL_CALIBRATION_FILE),
+    )?;
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.spatial_calibration = new_spatial_calibration;
+    Ok(())
+}
+
+/// Calibrate from a line drawn over something of known `length`, in texture pixel coordinates of
+/// the video as shown. With a lens model these are undistorted, like everything that is measured.
+/// Anisotropic pixels keep their aspect ratio.
+#[tauri::command]
+async fn calibrate_from_line(
+    app_handle: AppHandle,
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+    length: f64,
+    unit: LengthUnit,
+) -> Result<SpatialCalibration, String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let calibration = SpatialCalibration::from_line(
+        [x0, y0],
+        [x1, y1],
+        length,
+        unit,
+        gpu_state.spatial_calibration.as_ref(),
+    )?;
+    SpatialCalibration::save(
+        Some(&calibration),
+        &calibration_dir().join(SPATIAL_CALIBRATION_FILE),
+    )?;
+    gpu_state.spatial_calibration = Some(calibration);
+    Ok(calibration)
+}
+
+#[tauri::command]
+async fn get_spatial_calibration(app_handle: AppHandle) -> Option<SpatialCalibration> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.spatial_calibration
+}
+
+/// Show or hide the scale bar, which is only drawn when there is a spatial calibration
+#[tauri::command]
+async fn set_scale_bar(app_handle: AppHandle, visible: bool) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.scale_bar = visible;
+}
+
+/// Turn on blob detection with the given options, or off with `None`. While it is on, the blobs
+/// in each frame are emitted as a `blobs` event.
+#[tauri::command]
+async fn set_blob_detection(
+    app_handle: AppHandle,
+    new_blob_detection: Option<BlobDetectionOptions>,
+) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.blob_detection = new_blob_detection;
+}
+
+/// Detect the blobs in the processed frame, using the default options if blob detection is off
+#[tauri::command]
+async fn get_blobs(app_handle: AppHandle) -> Result<Vec<Blob>, String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    let units_per_percent = gpu_state.metric_units_per_percent();
+    Ok(detect_blobs(
+        &gpu_state.render_processed()?,
+        gpu_state.threshold_band(),
+        &gpu_state.blob_detection.clone().unwrap_or_default(),
+        gpu_state.spatial_calibration.as_ref(),
+    )
+    .into_iter()
+    .map(|blob| blob.in_units(units_per_percent))
+    .collect())
+}
+
+/// Turn on tracking of the detected blobs with the given options, or off with `None`. While it is
+/// on, the active tracks are emitted as a `tracks` event on every new frame.
+#[tauri::command]
+async fn set_tracking(app_handle: AppHandle, new_tracking: Option<TrackingOptions>) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.tracking = new_tracking;
+}
+
+/// Get every track from the current or most recent live view, including finished ones
+#[tauri::command]
+async fn get_tracks(app_handle: AppHandle) -> Vec<Track> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.tracker.tracks().to_vec()
+}
+
+#[tauri::command]
+async fn export_tracks(
+    app_handle: AppHandle,
+    path: PathBuf,
+    format: TrackExportFormat,
+) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.tracker.export(&path, format)
+}
+
+#[tauri::command]
+async fn set_difference_mode(app_handle: AppHandle, new_difference_mode: DifferenceMode) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.difference_mode = new_difference_mode;
+}
+
+/// Set the motion score above which a `motion-detected` event is emitted, or `None` to stop
+/// detecting motion. The score is the mean absolute change in the threshold metric from the
+/// previous frame, in the same native units as the thresholds, and is also emitted for every frame
+/// as a `motion-score` event.
+#[tauri::command]
+async fn set_motion_level(app_handle: AppHandle, new_motion_level: Option<f32>) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.motion_level = new_motion_level;
+}
+
+/// Set the model used to estimate the background that is subtracted from each frame before
+/// thresholding, or `None` to turn background subtraction off
+#[tauri::command]
+async fn set_background_model(
+    app_handle: AppHandle,
+    new_background_model: Option<BackgroundModel>,
+) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state
+        .background
+        .set_model(&gpu_state.device, &gpu_state.queue, new_background_model);
+}
+
+/// Use the current frame as the background
+#[tauri::command]
+async fn capture_background(app_handle: AppHandle) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state.background.capture(
+        &gpu_state.device,
+        &gpu_state.queue,
+        &gpu_state.diffuse_texture,
+    );
+}
+
+#[tauri::command]
+async fn reset_background(app_handle: AppHandle) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state.background.reset(&gpu_state.queue);
+}
+
+/// Stop or resume updating the background from new frames
+#[tauri::command]
+async fn freeze_background(app_handle: AppHandle, frozen: bool) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.background.frozen = frozen;
+}
+
+#[tauri::command]
+async fn save_background(app_handle: AppHandle, path: PathBuf) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.background.save(
+        &gpu_state.device,
+        &gpu_state.queue,
+        &path,
+        gpu_state.source_format,
+    )
+}
+
+/// Load a saved background, which is frozen until `freeze_background` is called with `false`
+#[tauri::command]
+async fn load_background(app_handle: AppHandle, path: PathBuf) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state
+        .background
+        .load(&gpu_state.queue, &path, gpu_state.source_format.color_space)
+}
+
+/// Find hot and dead pixels from dark and evenly lit frames and start correcting them
+#[tauri::command]
+async fn detect_bad_pixels(
+    app_handle: AppHandle,
+    dark_paths: Vec<PathBuf>,
+    bright_paths: Vec<PathBuf>,
+    options: Option<BadPixelDetectionOptions>,
+) -> Result<BadPixelMap, String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    // not held while reading the frames
+    let color_space = gpu_state_mutex.lock().unwrap().source_format.color_space;
+    let map = BadPixelMap::detect(
+        &dark_paths,
+        &bright_paths,
+        color_space,
+        &options.unwrap_or_default(),
+    )?;
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.set_bad_pixel_map(Some(map.clone()))?;
+    Ok(map)
+}
+
+#[tauri::command]
+async fn get_bad_pixel_map(app_handle: AppHandle) -> Option<BadPixelMap> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.bad_pixels.map().cloned()
+}
+
+#[tauri::command]
+async fn save_bad_pixel_map(app_handle: AppHandle, path: PathBuf) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state
+        .bad_pixels
+        .map()
+        .ok_or_else(|| "no bad pixel map to save".to_string())?
+        .save(&path)
+}
+
+#[tauri::command]
+async fn load_bad_pixel_map(app_handle: AppHandle, path: PathBuf) -> Result<BadPixelMap, String> {
+    let map = BadPixelMap::load(&path)?;
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.set_bad_pixel_map(Some(map.clone()))?;
+    Ok(map)
+}
+
+/// Stop correcting bad pixels. Frames already on screen keep their corrections until the next
+/// frame.
+#[tauri::command]
+async fn clear_bad_pixel_map(app_handle: AppHandle) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state
+        .set_bad_pixel_map(None)
+        .expect("clearing the map can't fail");
+}
+
+/// Use the current raw frame as the dark or flat frame for flat field correction, saving it with
+/// the source's calibration
+#[tauri::command]
+async fn capture_calibration_frame(
+    app_handle: AppHandle,
+    frame: CalibrationFrame,
+) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state
+        .flat_field
+        .capture(&gpu_state.queue, frame, &gpu_state.current_frame)
+}
+
+/// Load the dark or flat frame from an image, saving a copy with the source's calibration
+#[tauri::command]
+async fn load_calibration_frame(
+    app_handle: AppHandle,
+    frame: CalibrationFrame,
+    path: PathBuf,
+) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state.flat_field.load(&gpu_state.queue, frame, &path)
+}
+
+/// Remove the dark or flat frame, including from the source's calibration
+#[tauri::command]
+async fn clear_calibration_frame(
+    app_handle: AppHandle,
+    frame: CalibrationFrame,
+) -> Result<(), String> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state.flat_field.clear(&gpu_state.queue, frame)
+}
+
+/// Turn flat field correction on or off without discarding the calibration frames
+#[tauri::command]
+async fn set_flat_field_enabled(app_handle: AppHandle, enabled: bool) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.flat_field.enabled = enabled;
+}
+
+#[tauri::command]
+async fn get_flat_field(app_handle: AppHandle) -> FlatFieldStatus {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.flat_field.status()
+}
+
+/// Undistort the video with a Brown–Conrady lens model, or stop with `None`. Overlays and
+/// measurements are in the coordinates of the undistorted frames, the same as the video, so a
+/// spatial calibration made before changing the lens model may need redoing.
+#[tauri::command]
+async fn set_lens_distortion(
+    app_handle: AppHandle,
+    new_lens_distortion: Option<LensDistortion>,
+) -> Result<(), String> {
+    let new_lens_distortion = new_lens_distortion
+        .map(LensDistortion::validated)
+        .transpose()?;
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state
+        .lens_undistortion
+        .set_distortion(&gpu_state.queue, new_lens_distortion);
+    Ok(())
+}
+
+/// Undistort the video with an OpenCV camera calibration file, in YAML or JSON
+#[tauri::command]
+async fn load_lens_distortion(
+    app_handle: AppHandle,
+    path: PathBuf,
+) -> Result<LensDistortion, String> {
+    let lens_distortion = LensDistortion::load(&path)?;
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state
+        .lens_undistortion
+        .set_distortion(&gpu_state.queue, Some(lens_distortion));
+    Ok(lens_distortion)
+}
 
-const NUM_FRAMES: u32 = 11;
-// current limit seems to be ~10ms
-const FRAME_RATE: Duration = Duration::from_millis(100);
+#[tauri::command]
+async fn get_lens_distortion(app_handle: AppHandle) -> Option<LensDistortion> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.lens_undistortion.distortion()
+}
 
-struct GpuState<'a> {
-    surface: wgpu::Surface<'a>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    diffuse_bind_group: wgpu::BindGroup,
-    diffuse_texture: wgpu::Texture,
-    texture_size: wgpu::Extent3d,
-    frame_idx: Option<u32>,
-    start_time: Option<Instant>,
-    min_threshold: u32,
-    max_threshold: u32,
-    threshold_buffer: wgpu::Buffer,
-    threshold_bind_group: wgpu::BindGroup,
+/// Set the filter used to smooth frames over time, or `None` to turn temporal filtering off
+#[tauri::command]
+async fn set_temporal_filter(app_handle: AppHandle, new_temporal_filter: Option<TemporalFilter>) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state
+        .temporal_filter
+        .set_filter(&gpu_state.device, new_temporal_filter);
 }
 
-// TODO
-// 1) don't use a new webview (Is this already done?)
-//  - create components around the video player that do not have transparent backgrounds
-//
-// IDEAS:
-//  - instead of looping inside the command, create another task that just sends the next frame idx
-//    periodically according to the frame rate
-//  ? make some resizable component in the FE, send the size and position down to rust, have that
-//    control where the video is rendered in the shader
+#[tauri::command]
+async fn reset_temporal_filter(app_handle: AppHandle) {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.temporal_filter.reset();
+}
 
-fn next_triangle(app_handle: &AppHandle, new_size: Option<PhysicalSize<u32>>) -> bool {
+/// Replace the chain of spatial filters run on each frame before thresholding, in order
+#[tauri::command]
+async fn set_filter_chain(
+    app_handle: AppHandle,
+    new_filter_chain: Vec<FilterStage>,
+) -> Result<(), String> {
     let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let mut gpu_state = gpu_state_mutex.lock().unwrap();
+    let gpu_state = &mut *gpu_state;
+    gpu_state
+        .filter_chain
+        .set_stages(&gpu_state.device, new_filter_chain)
+}
 
-    // TODO try removing these inner brackets
-    {
-        let mut gpu_state = gpu_state_mutex.lock().unwrap();
+#[tauri::command]
+async fn get_filter_chain(app_handle: AppHandle) -> Vec<FilterStage> {
+    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.filter_chain.stages().to_vec()
+}
 
-        // check and see if reconfig is needed
-        if let Some(new_size) = new_size {
-            gpu_state.config.width = if new_size.width > 0 {
-                new_size.width
-            } else {
-                1
-            };
-            gpu_state.config.height = if new_size.height > 0 {
-                new_size.height
-            } else {
-                1
-            };
-            gpu_state
-                .surface
-                .configure(&gpu_state.device, &gpu_state.config);
-        }
+#[tauri::command]
+async fn list_shaders() -> Result<Vec<String>, String> {
+    shaders::list_shaders()
+}
 
-        // update frame idx if necessary
-        let next_frame_idx = if let Some(start_time) = gpu_state.start_time {
-            let whole_periods_elapsed =
-                Instant::now().duration_since(start_time).as_millis() / FRAME_RATE.as_millis();
-            let next_frame_idx = (whole_periods_elapsed % (NUM_FRAMES as u128)) as u32;
-            // TODO remove debug
-            if let Some(frame_idx) = gpu_state.frame_idx {
-                if next_frame_idx != frame_idx && next_frame_idx > (frame_idx + 1) % NUM_FRAMES {
-                    println!("\n\n------------- FRAME(S) DROPPED -------------\n\n")
+/// Render with a shader from `shaders::SHADER_DIR` instead of the built-in one, or go back to the
+/// built-in one with `None`. The shader is reloaded whenever its file is saved, with any errors
+/// sent out as `shader-error` events while the last working version stays in use.
+#[tauri::command]
+async fn set_shader(app_handle: AppHandle, name: Option<String>) -> Result<(), ShaderError> {
+    let (unused_shader, result) = {
+        let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
+        let mut gpu_state = gpu_state_mutex.lock().unwrap();
+        match name {
+            Some(name) => {
+                let compiled = shaders::compile(&name)?;
+                let reload_handle = app_handle.clone();
+                let reload_name = name.clone();
+                let custom_shader = CustomShader::watch(name.clone(), move || {
+                    reload_shader(&reload_handle, &reload_name)
+                })?;
+                match gpu_state.use_shader(
+                    &format!("{}.wgsl", name),
+                    &compiled.source,
+                    compiled.params,
+                ) {
+                    Ok(()) => (gpu_state.custom_shader.replace(custom_shader), Ok(())),
+                    Err(message) => (Some(custom_shader), Err(ShaderError::new(&name, message))),
                 }
             }
-            Some(next_frame_idx)
-        } else {
-            None
-        };
-        // if on a new frame idx, update the image
-        if next_frame_idx != gpu_state.frame_idx {
-            gpu_state.frame_idx = next_frame_idx;
-            let img_name = if let Some(frame_idx) = next_frame_idx {
-                format!("happy-tree-{}", frame_idx + 1)
-            } else {
-                "default".to_string()
-            };
-            let diffuse_bytes =
-                fs::read(format!("./video-imgs/{}.png", img_name)).expect("should read");
-            let diffuse_image = image::load_from_memory(&diffuse_bytes).unwrap();
-            let diffuse_rgba = diffuse_image.to_rgba8();
-            gpu_state.queue.write_texture(
-                wgpu::ImageCopyTexture {
-                    texture: &gpu_state.diffuse_texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
-                    aspect: wgpu::TextureAspect::All,
-                },
-                &diffuse_rgba,
-                wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(4 * gpu_state.texture_size.width),
-                    rows_per_image: Some(gpu_state.texture_size.height),
-                },
-                gpu_state.texture_size,
-            );
-        }
-
-        // handle thresholding
-        gpu_state.queue.write_buffer(
-            &gpu_state.threshold_buffer,
-            0,
-            bytemuck::cast_slice(&[gpu_state.min_threshold, gpu_state.max_threshold]),
-        );
-
-        // render
-        let frame = gpu_state
-            .surface
-            .get_current_texture()
-            .expect("Failed to acquire next swap chain texture");
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = gpu_state
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-        {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
-            rpass.set_pipeline(&gpu_state.render_pipeline);
-            rpass.set_bind_group(0, &gpu_state.diffuse_bind_group, &[]);
-            rpass.set_bind_group(1, &gpu_state.threshold_bind_group, &[]);
-            rpass.set_vertex_buffer(0, gpu_state.vertex_buffer.slice(..));
-            rpass.set_index_buffer(gpu_state.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            rpass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+            None => {
+                gpu_state
+                    .use_shader("shader.wgsl", BUILTIN_SHADER, ShaderParams::default())
+                    .map_err(|message| ShaderError::new("shader", message))?;
+                (gpu_state.custom_shader.take(), Ok(()))
+            }
         }
+    };
+    // stop watching the old file, or the new one if it couldn't be used, without holding the
+    // lock, in case its watcher is waiting on it
+    drop(unused_shader);
+    result?;
 
-        gpu_state.queue.submit(Some(encoder.finish()));
-        frame.present();
-
-        next_frame_idx.is_some()
-    }
+    next_triangle(&app_handle, None);
+    Ok(())
 }
 
-#[tauri::command]
-async fn start_live_view(app_handle: AppHandle) {
-    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
-    let now: Instant;
+fn reload_shader(app_handle: &AppHandle, name: &str) {
     {
+        let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
         let mut gpu_state = gpu_state_mutex.lock().unwrap();
-        now = Instant::now();
-        gpu_state.start_time = Some(now);
-    }
-
-    let mut deadline = now + FRAME_RATE;
-    loop {
-        if !next_triangle(&app_handle, None) {
+        if gpu_state
+            .custom_shader
+            .as_ref()
+            .is_none_or(|custom_shader| custom_shader.name != name)
+        {
             return;
         }
-        sleep_until(deadline).await;
-        deadline += FRAME_RATE;
+        match shaders::compile(name) {
+            Ok(mut compiled) => {
+                compiled.params.keep_values_from(&gpu_state.shader_params);
+                match gpu_state.use_shader(
+                    &format!("{}.wgsl", name),
+                    &compiled.source,
+                    compiled.params,
+                ) {
+                    Ok(()) => app_handle
+                        .emit("shader-reloaded", name)
+                        .expect("should emit"),
+                    Err(message) => app_handle
+                        .emit("shader-error", ShaderError::new(name, message))
+                        .expect("should emit"),
+                }
+            }
+            Err(error) => {
+                app_handle.emit("shader-error", error).expect("should emit");
+            }
+        }
     }
+    next_triangle(app_handle, None);
 }
 
+/// The members of the current shader's params uniform, which is empty for the built-in shader
 #[tauri::command]
-async fn stop_live_view(app_handle: AppHandle) {
-    let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
-    let mut gpu_state = gpu_state_mutex.lock().unwrap();
-    gpu_state.start_time = None;
-    // TODO make consts for these default values
-    gpu_state.min_threshold = 0;
-    gpu_state.max_threshold = 100;
-}
-
-#[tauri::command]
-async fn set_min_threshold(app_handle: AppHandle, new_min_threshold: u32) {
+async fn get_shader_params(app_handle: AppHandle) -> Vec<ShaderParam> {
     let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
-    let mut gpu_state = gpu_state_mutex.lock().unwrap();
-    gpu_state.min_threshold = new_min_threshold;
+    let gpu_state = gpu_state_mutex.lock().unwrap();
+    gpu_state.shader_params.params().to_vec()
 }
 
+/// Set a member of the current shader's params uniform. Values outside the member's range are
+/// clamped.
 #[tauri::command]
-async fn set_max_threshold(app_handle: AppHandle, new_max_threshold: u32) {
+async fn set_shader_param(
+    app_handle: AppHandle,
+    name: String,
+    value: ShaderParamValue,
+) -> Result<(), String> {
     let gpu_state_mutex = app_handle.state::<Mutex<GpuState>>();
     let mut gpu_state = gpu_state_mutex.lock().unwrap();
-    gpu_state.max_threshold = new_max_threshold;
+    gpu_state.shader_params.set(&name, value)?;
+    gpu_state.queue.write_buffer(
+        &gpu_state.shader_params_buffer,
+        0,
+        gpu_state.shader_params.data(),
+    );
+    Ok(())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -254,7 +2980,8 @@ pub fn run() {
             let (device, queue) = block_on(adapter.request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::empty(),
+                    // lets deep frames be filtered where the device can
+                    required_features: adapter.features() & wgpu::Features::FLOAT32_FILTERABLE,
                     memory_hints: wgpu::MemoryHints::Performance,
                     // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
                     required_limits: wgpu::Limits::default().using_resolution(adapter.limits()),
@@ -263,196 +2990,9 @@ pub fn run() {
             ))
             .expect("Failed to create device");
 
-            // Load the shaders from disk
-            let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
-
-            // vertex buffer
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(VERTICES),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-
-            let vertex_buffer_layout = wgpu::VertexBufferLayout {
-                array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-                step_mode: wgpu::VertexStepMode::Vertex,
-                attributes: &[
-                    wgpu::VertexAttribute {
-                        offset: 0,
-                        shader_location: 0,
-                        format: wgpu::VertexFormat::Float32x3,
-                    },
-                    wgpu::VertexAttribute {
-                        offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                        shader_location: 1,
-                        format: wgpu::VertexFormat::Float32x2,
-                    },
-                ],
-            };
-
-            // index buffer
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(INDICES),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            // texture
-            let texture_bind_group_layout =
-                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            // This should match the filterable field of the
-                            // corresponding Texture entry above.
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-                });
-
-            let diffuse_bytes = fs::read("./video-imgs/default.png").expect("should read");
-            let diffuse_image = image::load_from_memory(&diffuse_bytes).unwrap();
-            let dimensions = diffuse_image.dimensions();
-
-            let texture_size = wgpu::Extent3d {
-                width: dimensions.0,
-                height: dimensions.1,
-                depth_or_array_layers: 1,
-            };
-            let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
-                size: texture_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                label: Some("diffuse_texture"),
-                view_formats: &[],
-            });
-
-            let diffuse_texture_view =
-                diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
-            let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            });
-
-            let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                    },
-                ],
-                label: Some("diffuse_bind_group"),
-            });
-
-            let diffuse_rgba = diffuse_image.to_rgba8();
-            queue.write_texture(
-                wgpu::ImageCopyTexture {
-                    texture: &diffuse_texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
-                    aspect: wgpu::TextureAspect::All,
-                },
-                &diffuse_rgba,
-                wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(4 * dimensions.0),
-                    rows_per_image: Some(dimensions.1),
-                },
-                texture_size,
-            );
-
-            // thresholds
-            let min_threshold: u32 = 0;
-            let max_threshold: u32 = 100;
-
-            let threshold_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Threshold Buffer"),
-                contents: bytemuck::cast_slice(&[min_threshold, max_threshold]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            });
-
-            let threshold_bind_group_layout =
-                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                    label: Some("threshold_bind_group_layout"),
-                });
-
-            let threshold_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &threshold_bind_group_layout,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: threshold_buffer.as_entire_binding(),
-                }],
-                label: Some("camera_bind_group"),
-            });
-
-            // etc.
-            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[&texture_bind_group_layout, &threshold_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
             let swapchain_capabilities = surface.get_capabilities(&adapter);
             let swapchain_format = swapchain_capabilities.formats[0];
 
-            let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                    buffers: &[vertex_buffer_layout],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                    targets: &[Some(swapchain_format.into())],
-                }),
-                primitive: wgpu::PrimitiveState::default(),
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
-                cache: None,
-            });
-
             let config = wgpu::SurfaceConfiguration {
                 usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
                 format: swapchain_format,
@@ -466,63 +3006,27 @@ pub fn run() {
 
             surface.configure(&device, &config);
 
-            // TODO could probably just call fn here instead of duping code
-
-            let frame = surface
-                .get_current_texture()
-                .expect("Failed to acquire next swap chain texture");
-            let view = frame
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor::default());
-            let mut encoder =
-                device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-            {
-                let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: None,
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                    timestamp_writes: None,
-                    occlusion_query_set: None,
+            let color_space = ColorSpace::load(&calibration_dir().join(COLOR_SPACE_FILE))
+                .unwrap_or_else(|e| {
+                    eprintln!("failed to load colour space: {}", e);
+                    None
                 });
-
-                rpass.set_pipeline(&render_pipeline);
-                rpass.set_bind_group(0, &diffuse_bind_group, &[]);
-                rpass.set_bind_group(1, &threshold_bind_group, &[]);
-                rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
-                rpass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                rpass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
-            }
-
-            queue.submit(Some(encoder.finish()));
-            frame.present();
-
-            let gpu_state = GpuState {
-                surface,
+            let default_frame = load_frame(None, color_space).expect("should read");
+            let mut gpu_state = GpuState::new(
                 device,
                 queue,
+                Some(surface),
                 config,
-                render_pipeline,
-                vertex_buffer,
-                index_buffer,
-                diffuse_bind_group,
-                diffuse_texture,
-                texture_size,
-                frame_idx: None,
-                start_time: None,
-                min_threshold,
-                max_threshold,
-                threshold_buffer,
-                threshold_bind_group,
-            };
+                default_frame,
+                calibration_dir(),
+            );
+            if let Err(e) = gpu_state.load_calibration(&calibration_dir()) {
+                eprintln!("{}", e);
+            }
 
             app.manage(Mutex::new(gpu_state));
+            // draw the default frame without waiting for a resize
+            next_triangle(app.app_handle(), None);
 
             Ok(())
         })
@@ -533,6 +3037,70 @@ pub fn run() {
             stop_live_view,
             set_min_threshold,
             set_max_threshold,
+            get_sample_range,
+            set_color_space,
+            set_display_range,
+            auto_display_range,
+            set_threshold_metric,
+            auto_threshold,
+            set_histogram_rate,
+            set_histogram_per_channel,
+            add_roi,
+            update_roi,
+            remove_roi,
+            get_rois,
+            get_roi_stats,
+            get_roi_trace,
+            export_roi_trace,
+            line_profile,
+            clear_line_profile,
+            save_snapshot,
+            start_recording,
+            stop_recording,
+            start_pre_trigger,
+            stop_pre_trigger,
+            trigger_recording,
+            export_clip,
+            save_processing_config,
+            load_processing_config,
+            set_spatial_calibration,
+            calibrate_from_line,
+            get_spatial_calibration,
+            set_scale_bar,
+            set_blob_detection,
+            get_blobs,
+            set_tracking,
+            get_tracks,
+            export_tracks,
+            set_difference_mode,
+            set_motion_level,
+            set_background_model,
+            capture_background,
+            reset_background,
+            freeze_background,
+            save_background,
+            load_background,
+            detect_bad_pixels,
+            get_bad_pixel_map,
+            save_bad_pixel_map,
+            load_bad_pixel_map,
+            clear_bad_pixel_map,
+            capture_calibration_frame,
+            load_calibration_frame,
+            clear_calibration_frame,
+            set_flat_field_enabled,
+            get_flat_field,
+            set_lens_distortion,
+            load_lens_distortion,
+            get_lens_distortion,
+            set_temporal_filter,
+            reset_temporal_filter,
+            set_filter_chain,
+            get_filter_chain,
+            list_shaders,
+            set_shader,
+            get_shader_params,
+            set_shader_param,
         ])
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
@@ -547,3 +3115,127 @@ pub fn run() {
             _ => (),
         });
 }
+
+/// Process a sequence of frames without a window, writing each processed frame and a CSV of
+/// statistics. `args` are the command line arguments after `--batch`.
+pub fn run_batch(args: &[String]) -> Result<(), String> {
+    let args = BatchArgs::parse(args)?;
+    let paths = batch::sequence_paths(&args.input)?;
+    let config = args
+        .config
+        .as_deref()
+        .map(ProcessingConfig::load)
+        .transpose()?
+        .unwrap_or_default();
+    let calibration = args.calibration.clone().unwrap_or_else(calibration_dir);
+    let color_space = ColorSpace::load(&calibration.join(COLOR_SPACE_FILE))?;
+    let first_frame = frame::open(&paths[0], color_space)?;
+    let color_space = Some(first_frame.1.color_space);
+    let frame_size = first_frame.0.dimensions();
+    let mut gpu_state = GpuState::new_headless(first_frame, calibration.clone())?;
+    if let Err(e) = gpu_state.load_calibration(&calibration) {
+        // the default calibrations are only used if they load
+        if args.calibration.is_some() {
+            return Err(e);
+        }
+        eprintln!("{}", e);
+    }
+    gpu_state.apply_processing_config(config)?;
+    fs::create_dir_all(&args.output)
+        .map_err(|e| format!("failed to create {}: {}", args.output.display(), e))?;
+    let mut stats_writer = StatsWriter::create(&args.output)?;
+
+    for (frame_idx, path) in paths.iter().enumerate() {
+        let (frame, _) = frame::open(path, color_space)?;
+        if frame.dimensions() != frame_size {
+            return Err(format!(
+                "{} is {}x{} but the frames before it are {}x{}",
+                path.display(),
+                frame.width(),
+                frame.height(),
+                frame_size.0,
+                frame_size.1
+            ));
+        }
+        gpu_state.set_frame(frame);
+        let mut encoder =
+            gpu_state
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Batch Encoder"),
+                });
+        gpu_state.temporal_filter.encode(
+            &gpu_state.queue,
+            &mut encoder,
+            &gpu_state.diffuse_texture,
+        );
+        gpu_state
+            .background
+            .encode(&gpu_state.queue, &mut encoder, &gpu_state.diffuse_texture);
+        gpu_state.queue.submit(Some(encoder.finish()));
+        let processed_frame = gpu_state.render_processed()?;
+        if let Some(method) = gpu_state.auto_threshold_method {
+            let histogram = Histogram::from_image(&processed_frame, gpu_state.threshold_metric);
+            gpu_state.min_threshold = method.compute(&histogram);
+            gpu_state.write_threshold_uniform();
+        }
+
+        // the same analysis live view does
+        let band = gpu_state.threshold_band();
+        let calibration = gpu_state.spatial_calibration.as_ref();
+        let whole_frame = Roi {
+            id: 0,
+            shape: RoiShape::Rectangle {
+                x: 0.0,
+                y: 0.0,
+                width: frame_size.0 as f32,
+                height: frame_size.1 as f32,
+            },
+        };
+        let units_per_percent = gpu_state.metric_units_per_percent();
+        let stats = |roi: &Roi| {
+            roi.stats(&processed_frame, band, calibration)
+                .in_units(units_per_percent)
+        };
+        let mut regions = vec![(None, stats(&whole_frame))];
+        regions.extend(
+            gpu_state
+                .rois
+                .all()
+                .iter()
+                .map(|roi| (Some(roi), stats(roi))),
+        );
+        let blobs = gpu_state
+            .blob_detection
+            .as_ref()
+            .map(|options| detect_blobs(&processed_frame, band, options, calibration));
+        stats_writer.write_frame(
+            frame_idx,
+            path,
+            gpu_state.native_thresholds(),
+            &regions,
+            blobs.as_deref(),
+        )?;
+
+        if args.overlays {
+            let mut overlay_lines = OverlayLines::default();
+            if let (Some(blobs), Some(options)) = (&blobs, &gpu_state.blob_detection) {
+                if options.draw_outlines {
+                    draw_blobs(blobs, &mut overlay_lines);
+                }
+            }
+            gpu_state.rois.draw(&mut overlay_lines);
+            if let Some(calibration) = &gpu_state.spatial_calibration {
+                calibration.draw_scale_bar(frame_size, &mut overlay_lines);
+            }
+            gpu_state
+                .overlay
+                .set_lines(&gpu_state.device, &gpu_state.queue, &overlay_lines);
+        }
+
+        let rendered = gpu_state.render_offscreen(args.overlays)?;
+        snapshot::save_image(&rendered.into(), &batch::output_path(&args.output, path))?;
+        eprintln!("{}/{} {}", frame_idx + 1, paths.len(), path.display());
+    }
+    stats_writer.finish()
+}
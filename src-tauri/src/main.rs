@@ -2,5 +2,32 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // process a sequence without opening a window
+    if args.first().is_some_and(|arg| arg == "--batch") {
+        #[cfg(windows)]
+        attach_parent_console();
+        if let Err(e) = tauri_v2_test_lib::run_batch(&args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     tauri_v2_test_lib::run()
 }
+
+/// Release builds don't get a console of their own on Windows, so batch mode writes its progress
+/// and errors to the one it was started from, if there is one
+#[cfg(windows)]
+fn attach_parent_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // fails harmlessly if there is no parent console or one is already attached
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
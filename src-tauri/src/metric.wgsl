@@ -0,0 +1,73 @@
+// Threshold metrics, shared by every shader that needs to know what is being thresholded
+
+// must match ThresholdMetric in threshold.rs
+const METRIC_LUMINANCE_709: u32 = 0u;
+const METRIC_LUMINANCE_601: u32 = 1u;
+const METRIC_RED: u32 = 2u;
+const METRIC_GREEN: u32 = 3u;
+const METRIC_BLUE: u32 = 4u;
+const METRIC_ALPHA: u32 = 5u;
+const METRIC_VALUE: u32 = 6u;
+const METRIC_SATURATION: u32 = 7u;
+const METRIC_HUE: u32 = 8u;
+
+// hue as a fraction of the colour wheel, 0 for greys
+fn hue(c: vec3<f32>) -> f32 {
+    let c_max = max(c.r, max(c.g, c.b));
+    let delta = c_max - min(c.r, min(c.g, c.b));
+    if (delta <= 0.0) {
+        return 0.0;
+    }
+    var h: f32;
+    if (c_max == c.r) {
+        h = (c.g - c.b) / delta;
+    } else if (c_max == c.g) {
+        h = (c.b - c.r) / delta + 2.0;
+    } else {
+        h = (c.r - c.g) / delta + 4.0;
+    }
+    return fract(h / 6.0 + 1.0);
+}
+
+fn saturation(c: vec3<f32>) -> f32 {
+    let c_max = max(c.r, max(c.g, c.b));
+    if (c_max <= 0.0) {
+        return 0.0;
+    }
+    return (c_max - min(c.r, min(c.g, c.b))) / c_max;
+}
+
+// the value being thresholded, scaled to 0-100
+fn metric_value(c: vec4<f32>, metric: u32) -> f32 {
+    var v: f32;
+    switch metric {
+        case METRIC_LUMINANCE_601: {
+            v = 0.299*c.r + 0.587*c.g + 0.114*c.b;
+        }
+        case METRIC_RED: {
+            v = c.r;
+        }
+        case METRIC_GREEN: {
+            v = c.g;
+        }
+        case METRIC_BLUE: {
+            v = c.b;
+        }
+        case METRIC_ALPHA: {
+            v = c.a;
+        }
+        case METRIC_VALUE: {
+            v = max(c.r, max(c.g, c.b));
+        }
+        case METRIC_SATURATION: {
+            v = saturation(c.rgb);
+        }
+        case METRIC_HUE: {
+            v = hue(c.rgb);
+        }
+        default: {
+            v = 0.2126*c.r + 0.7152*c.g + 0.0722*c.b;
+        }
+    }
+    return v * 100;
+}
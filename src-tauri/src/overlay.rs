@@ -0,0 +1,271 @@
+use wgpu::util::DeviceExt as _;
+
+/// A line list vertex, positioned in texture pixel coordinates
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct OverlayVertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+/// Layout of the placement uniform in `overlay.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct OverlayPlacement {
+    rect: [f32; 4],
+    texture_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+impl OverlayPlacement {
+    /// `top_left` and `bottom_right` are the clip space corners the texture is drawn to
+    pub fn new(top_left: [f32; 2], bottom_right: [f32; 2], texture_size: wgpu::Extent3d) -> Self {
+        Self {
+            rect: [top_left[0], top_left[1], bottom_right[0], bottom_right[1]],
+            texture_size: [texture_size.width as f32, texture_size.height as f32],
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// glyphs for `OverlayLines::text` are drawn as strokes in a box GLYPH_WIDTH wide and 1 tall, with
+// y increasing downwards and the baseline at 1
+const GLYPH_WIDTH: f32 = 0.5;
+const GLYPH_ADVANCE: f32 = 0.7;
+// seven segment digits, as the segments lit for 0-9 in the order top, top right, bottom right,
+// bottom, bottom left, top left, middle
+const SEGMENTS: [[[f32; 2]; 2]; 7] = [
+    [[0.0, 0.0], [GLYPH_WIDTH, 0.0]],
+    [[GLYPH_WIDTH, 0.0], [GLYPH_WIDTH, 0.5]],
+    [[GLYPH_WIDTH, 0.5], [GLYPH_WIDTH, 1.0]],
+    [[0.0, 1.0], [GLYPH_WIDTH, 1.0]],
+    [[0.0, 0.5], [0.0, 1.0]],
+    [[0.0, 0.0], [0.0, 0.5]],
+    [[0.0, 0.5], [GLYPH_WIDTH, 0.5]],
+];
+const DIGIT_SEGMENTS: [u8; 10] = [
+    0b0111111, 0b0000110, 0b1011011, 0b1001111, 0b1100110, 0b1101101, 0b1111101, 0b0000111,
+    0b1111111, 0b1101111,
+];
+
+/// Strokes of the characters `OverlayLines::text` supports other than digits
+fn glyph_strokes(c: char) -> &'static [[[f32; 2]; 2]] {
+    match c {
+        '.' => &[[[0.2, 0.9], [0.3, 0.9]], [[0.2, 1.0], [0.3, 1.0]]],
+        '-' => &[[[0.0, 0.5], [GLYPH_WIDTH, 0.5]]],
+        'm' => &[
+            [[0.0, 0.5], [0.0, 1.0]],
+            [[0.0, 0.5], [GLYPH_WIDTH, 0.5]],
+            [[0.25, 0.5], [0.25, 1.0]],
+            [[GLYPH_WIDTH, 0.5], [GLYPH_WIDTH, 1.0]],
+        ],
+        'µ' => &[
+            [[0.0, 0.5], [0.0, 1.25]],
+            [[0.0, 1.0], [GLYPH_WIDTH, 1.0]],
+            [[GLYPH_WIDTH, 0.5], [GLYPH_WIDTH, 1.0]],
+        ],
+        _ => &[],
+    }
+}
+
+/// Accumulates outlines to be drawn over the video for a single frame
+#[derive(Default)]
+pub struct OverlayLines {
+    vertices: Vec<OverlayVertex>,
+}
+
+impl OverlayLines {
+    pub fn line(&mut self, from: [f32; 2], to: [f32; 2], color: [f32; 4]) {
+        self.vertices.push(OverlayVertex {
+            position: from,
+            color,
+        });
+        self.vertices.push(OverlayVertex {
+            position: to,
+            color,
+        });
+    }
+
+    /// Draw a closed outline through `points`
+    pub fn outline(&mut self, points: &[[f32; 2]], color: [f32; 4]) {
+        for (i, &from) in points.iter().enumerate() {
+            self.line(from, points[(i + 1) % points.len()], color);
+        }
+    }
+
+    /// Draw an open line through `points`
+    pub fn polyline(&mut self, points: &[[f32; 2]], color: [f32; 4]) {
+        for pair in points.windows(2) {
+            self.line(pair[0], pair[1], color);
+        }
+    }
+
+    /// Draw a line of text with its top left corner at `position`. Only digits, `.`, `-`, `m`, `µ`
+    /// and spaces are supported, which is enough for labelling measurements.
+    pub fn text(&mut self, text: &str, position: [f32; 2], height: f32, color: [f32; 4]) {
+        let mut x = position[0];
+        for c in text.chars() {
+            let digit_strokes;
+            let strokes = match c.to_digit(10) {
+                Some(digit) => {
+                    digit_strokes = SEGMENTS
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| DIGIT_SEGMENTS[digit as usize] & (1 << i) != 0)
+                        .map(|(_, &segment)| segment)
+                        .collect::<Vec<_>>();
+                    digit_strokes.as_slice()
+                }
+                None => glyph_strokes(c),
+            };
+            for [from, to] in strokes {
+                self.line(
+                    [x + from[0] * height, position[1] + from[1] * height],
+                    [x + to[0] * height, position[1] + to[1] * height],
+                    color,
+                );
+            }
+            x += GLYPH_ADVANCE * height;
+        }
+    }
+
+    /// Width of `text` drawn by `text` at the given height
+    pub fn text_width(text: &str, height: f32) -> f32 {
+        let count = text.chars().count() as f32;
+        (count * GLYPH_ADVANCE - (GLYPH_ADVANCE - GLYPH_WIDTH)).max(0.0) * height
+    }
+}
+
+pub struct Overlay {
+    pipeline: wgpu::RenderPipeline,
+    placement_buffer: wgpu::Buffer,
+    placement_bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    num_vertices: u32,
+}
+
+impl Overlay {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        placement: OverlayPlacement,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("overlay.wgsl"));
+
+        let placement_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Placement Buffer"),
+            contents: bytemuck::bytes_of(&placement),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let placement_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("overlay_placement_bind_group_layout"),
+            });
+        let placement_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &placement_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: placement_buffer.as_entire_binding(),
+            }],
+            label: Some("overlay_placement_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("overlay_pipeline_layout"),
+            bind_group_layouts: &[&placement_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("overlay_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<OverlayVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let vertex_buffer = Self::create_vertex_buffer(device, 0);
+
+        Self {
+            pipeline,
+            placement_buffer,
+            placement_bind_group,
+            vertex_buffer,
+            num_vertices: 0,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, num_vertices: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Vertex Buffer"),
+            // leave room to grow so the buffer isn't recreated every time a line is added
+            size: (num_vertices.max(256).next_power_of_two() * std::mem::size_of::<OverlayVertex>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Move the overlay to match where the video is drawn, from the next submit on
+    pub fn set_placement(&self, queue: &wgpu::Queue, placement: OverlayPlacement) {
+        queue.write_buffer(&self.placement_buffer, 0, bytemuck::bytes_of(&placement));
+    }
+
+    /// Upload the lines to draw on the next call to `draw`
+    pub fn set_lines(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lines: &OverlayLines) {
+        let size = std::mem::size_of_val(lines.vertices.as_slice()) as wgpu::BufferAddress;
+        if size > self.vertex_buffer.size() {
+            self.vertex_buffer = Self::create_vertex_buffer(device, lines.vertices.len());
+        }
+        queue.write_buffer(
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&lines.vertices),
+        );
+        self.num_vertices = lines.vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        if self.num_vertices == 0 {
+            return;
+        }
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.placement_bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.draw(0..self.num_vertices, 0..1);
+    }
+}
@@ -0,0 +1,42 @@
+// Overlay shader for outlines drawn on top of the video, positioned in texture pixel coordinates
+
+// must match OverlayPlacement in overlay.rs
+struct Placement {
+    // left, top, right, bottom of the video quad in clip space
+    rect: vec4<f32>,
+    texture_size: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> placement: Placement;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = model.position / placement.texture_size;
+    out.clip_position = vec4<f32>(
+        mix(placement.rect.x, placement.rect.z, uv.x),
+        mix(placement.rect.y, placement.rect.w, uv.y),
+        0.0,
+        1.0,
+    );
+    out.color = model.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
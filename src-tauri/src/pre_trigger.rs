@@ -0,0 +1,321 @@
+use std::{collections::VecDeque, fs};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    frame::Frame,
+    recording::{BufferedFrame, Recording, RecordingOptions},
+    threshold::{metric_value, ThresholdBand},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TriggerReason {
+    /// `trigger_recording` was called
+    Manual,
+    /// The motion score went over the motion level
+    Motion,
+    /// The share of the frame inside the threshold band went over `area_percent`
+    ThresholdArea,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PreTriggerOptions {
+    /// How much to keep from before a trigger
+    pub pre_trigger_seconds: f64,
+    /// How long to keep recording after the last trigger
+    pub post_trigger_seconds: f64,
+    /// Memory the buffered frames can use, which can shorten the pre-trigger time for big frames
+    pub max_buffer_megabytes: f64,
+    /// Trigger whenever a `motion-detected` event is emitted
+    pub on_motion: bool,
+    /// Trigger when more than this percentage of the frame is inside the threshold band
+    pub area_percent: Option<f64>,
+}
+
+impl Default for PreTriggerOptions {
+    fn default() -> Self {
+        Self {
+            pre_trigger_seconds: 5.0,
+            post_trigger_seconds: 5.0,
+            max_buffer_megabytes: 512.0,
+            on_motion: false,
+            area_percent: None,
+        }
+    }
+}
+
+/// Keeps the last few seconds of frames while waiting for a trigger, then writes them along with
+/// the frames after it as a recording
+pub struct PreTrigger {
+    recording_options: RecordingOptions,
+    options: PreTriggerOptions,
+    buffer: VecDeque<BufferedFrame>,
+    buffer_bytes: usize,
+    // the recording in progress and when it stops
+    triggered: Option<(Recording, Instant)>,
+}
+
+impl PreTrigger {
+    /// Each triggered recording is written into the directory `recording_options.path` with the
+    /// rest of its options
+    pub fn new(
+        recording_options: RecordingOptions,
+        options: PreTriggerOptions,
+    ) -> Result<Self, String> {
+        let non_negative = |seconds: f64| seconds.is_finite() && seconds >= 0.0;
+        if !non_negative(options.pre_trigger_seconds) || !non_negative(options.post_trigger_seconds)
+        {
+            return Err("pre and post trigger times can't be negative".to_string());
+        }
+        if !(options.max_buffer_megabytes.is_finite() && options.max_buffer_megabytes > 0.0) {
+            return Err("max buffer size must be greater than 0".to_string());
+        }
+        fs::create_dir_all(&recording_options.path).map_err(|e| {
+            format!(
+                "failed to create {}: {}",
+                recording_options.path.display(),
+                e
+            )
+        })?;
+        Ok(Self {
+            recording_options,
+            options,
+            buffer: VecDeque::new(),
+            buffer_bytes: 0,
+            triggered: None,
+        })
+    }
+
+    pub fn recording_options(&self) -> &RecordingOptions {
+        &self.recording_options
+    }
+
+    pub fn options(&self) -> &PreTriggerOptions {
+        &self.options
+    }
+
+    pub fn note_source_frame(&mut self, frame_idx: u32) {
+        if let Some((recording, _)) = &mut self.triggered {
+            recording.note_source_frame(frame_idx);
+        }
+    }
+
+    /// Whether more than `area_percent` of `frame` is inside the threshold band
+    pub fn area_exceeded(&self, frame: &Frame, band: ThresholdBand) -> bool {
+        let Some(area_percent) = self.options.area_percent else {
+            return false;
+        };
+        let in_band = frame
+            .pixels()
+            .filter(|pixel| band.contains(metric_value(pixel.0, band.metric)))
+            .count();
+        let total = (frame.width() * frame.height()).max(1);
+        in_band as f64 * 100.0 / total as f64 > area_percent
+    }
+
+    /// Start a recording from the buffered frames, or keep the one in progress going for longer.
+    /// Returns whether a new recording was started.
+    pub fn trigger(
+        &mut self,
+        reason: TriggerReason,
+        size: (u32, u32),
+        frame_period: Duration,
+        num_source_frames: u32,
+    ) -> Result<bool, String> {
+        let stop_at = Instant::now() + Duration::from_secs_f64(self.options.post_trigger_seconds);
+        if let Some((_, recording_stop_at)) = &mut self.triggered {
+            *recording_stop_at = stop_at;
+            return Ok(false);
+        }
+
+        let name = format!(
+            "trigger-{}",
+            chrono::Local::now().format("%Y%m%d-%H%M%S-%3f")
+        );
+        let format = self.recording_options.format;
+        let path = self
+            .recording_options
+            .path
+            .join(match format.frame_extension() {
+                Some(_) => name,
+                None => format!("{}.{}", name, format.extension()),
+            });
+        let mut recording = Recording::start_with_backlog(
+            RecordingOptions {
+                path,
+                ..self.recording_options.clone()
+            },
+            size,
+            frame_period,
+            num_source_frames,
+            self.buffer.drain(..).collect(),
+        )?;
+        self.buffer_bytes = 0;
+        recording.set_trigger(reason);
+        self.triggered = Some((recording, stop_at));
+        Ok(true)
+    }
+
+    /// Add a frame to the triggered recording or the buffer. Once the post-trigger time is up,
+    /// the recording is returned to be finished.
+    pub fn push(&mut self, frame: BufferedFrame) -> Option<Recording> {
+        if let Some((recording, stop_at)) = &mut self.triggered {
+            if frame.captured_at < *stop_at {
+                recording.push(frame.image, frame.frame, frame.captured_at);
+                return None;
+            }
+            let (recording, _) = self.triggered.take().expect("should be triggered");
+            self.buffer(frame);
+            return Some(recording);
+        }
+        self.buffer(frame);
+        None
+    }
+
+    fn buffer(&mut self, frame: BufferedFrame) {
+        let newest = frame.captured_at;
+        self.buffer_bytes += frame.image.as_raw().len();
+        self.buffer.push_back(frame);
+        let max_age = Duration::from_secs_f64(self.options.pre_trigger_seconds);
+        let max_bytes = (self.options.max_buffer_megabytes * 1024.0 * 1024.0) as usize;
+        while let Some(oldest) = self.buffer.front() {
+            if self.buffer_bytes <= max_bytes
+                && newest.duration_since(oldest.captured_at) <= max_age
+            {
+                break;
+            }
+            self.buffer_bytes -= oldest.image.as_raw().len();
+            self.buffer.pop_front();
+        }
+    }
+
+    /// Stop buffering, returning the triggered recording in progress, if any
+    pub fn finish(self) -> Option<Recording> {
+        self.triggered.map(|(recording, _)| recording)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use image::RgbaImage;
+
+    use super::*;
+    use crate::{
+        recording::{RecordedFrame, RecordingFormat},
+        threshold::ThresholdMetric,
+    };
+
+    const SIZE: (u32, u32) = (8, 8);
+    // an 8x8 RGBA frame
+    const FRAME_BYTES: usize = 256;
+
+    fn dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pre-trigger-{}-{}", std::process::id(), name))
+    }
+
+    fn pre_trigger(name: &str, options: PreTriggerOptions) -> PreTrigger {
+        let recording_options = RecordingOptions {
+            path: dir(name),
+            format: RecordingFormat::Png,
+            source: Default::default(),
+            overlays: false,
+            ffmpeg: None,
+        };
+        PreTrigger::new(recording_options, options).unwrap()
+    }
+
+    fn frame(captured_at: Instant) -> BufferedFrame {
+        BufferedFrame {
+            image: RgbaImage::new(SIZE.0, SIZE.1),
+            frame: RecordedFrame {
+                frame_idx: None,
+                min_threshold: 0.0,
+                max_threshold: 255.0,
+                threshold_metric: ThresholdMetric::default(),
+            },
+            captured_at,
+        }
+    }
+
+    #[test]
+    fn oldest_frames_are_dropped_to_stay_within_the_memory_limit() {
+        let mut pre_trigger = pre_trigger(
+            "memory",
+            PreTriggerOptions {
+                max_buffer_megabytes: (4 * FRAME_BYTES) as f64 / (1024.0 * 1024.0),
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        for i in 0..6 {
+            assert!(pre_trigger
+                .push(frame(start + Duration::from_millis(i)))
+                .is_none());
+        }
+        assert_eq!(pre_trigger.buffer.len(), 4);
+        assert_eq!(pre_trigger.buffer_bytes, 4 * FRAME_BYTES);
+        assert_eq!(
+            pre_trigger.buffer[0].captured_at,
+            start + Duration::from_millis(2)
+        );
+        fs::remove_dir_all(dir("memory")).unwrap();
+    }
+
+    #[test]
+    fn frames_older_than_the_pre_trigger_time_are_dropped() {
+        let mut pre_trigger = pre_trigger(
+            "age",
+            PreTriggerOptions {
+                pre_trigger_seconds: 1.0,
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        for i in 0..4 {
+            pre_trigger.push(frame(start + Duration::from_millis(500 * i)));
+        }
+        // the frame exactly a second older than the newest is kept
+        assert_eq!(pre_trigger.buffer.len(), 3);
+        assert_eq!(
+            pre_trigger.buffer[0].captured_at,
+            start + Duration::from_millis(500)
+        );
+        assert_eq!(pre_trigger.buffer_bytes, 3 * FRAME_BYTES);
+        fs::remove_dir_all(dir("age")).unwrap();
+    }
+
+    #[test]
+    fn recording_is_handed_back_once_the_post_trigger_time_is_up() {
+        let mut pre_trigger = pre_trigger(
+            "post",
+            PreTriggerOptions {
+                post_trigger_seconds: 1.0,
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        pre_trigger.push(frame(start));
+        let started = pre_trigger
+            .trigger(TriggerReason::Manual, SIZE, Duration::from_millis(100), 11)
+            .unwrap();
+        assert!(started);
+        // the buffered frame went into the recording
+        assert!(pre_trigger.buffer.is_empty());
+        assert_eq!(pre_trigger.buffer_bytes, 0);
+
+        assert!(pre_trigger.push(frame(Instant::now())).is_none());
+        let recording = pre_trigger
+            .push(frame(Instant::now() + Duration::from_secs(2)))
+            .expect("post-trigger time should be up");
+        // and the frame after it is buffered for the next trigger
+        assert_eq!(pre_trigger.buffer.len(), 1);
+        assert!(pre_trigger.finish().is_none());
+        assert_eq!(recording.finish().unwrap().frames, 2);
+        fs::remove_dir_all(dir("post")).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+// Vertex shader and fragment shader bindings shared by shader.wgsl and user shaders, appended
+// to metric.wgsl. User shaders only need to define `fs_main`.
+
+// Vertex shader
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+// Fragment shader bindings
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_previous: texture_2d<f32>;
+
+// thresholds on the 0-100 metric scale
+struct Threshold {
+    min_max: vec2<f32>,
+    metric: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> threshold: Threshold;
+
+// must match DifferenceMode in processing.rs
+const DIFFERENCE_OFF: u32 = 0u;
+const DIFFERENCE_ABSOLUTE: u32 = 1u;
+const DIFFERENCE_SIGNED: u32 = 2u;
+
+struct Processing {
+    difference_mode: u32,
+    subtract_background: u32,
+    filtered: u32,
+    flat_field: u32,
+    flat_field_mean: vec4<f32>,
+    undistort: u32,
+    // show values as stored rather than as light, for raw sources
+    display_as_stored: u32,
+    // the part of the 0-100 range of the channels stretched over the output
+    display_range: vec2<f32>,
+};
+
+@group(2) @binding(0)
+var<uniform> processing: Processing;
+// linear Rgba32Float, which can't be filtered so it is loaded rather than sampled
+@group(2) @binding(1)
+var t_background: texture_2d<f32>;
+// the output of the filters, used in place of the diffuse texture when any are on
+@group(2) @binding(2)
+var t_filtered: texture_2d<f32>;
+// linear Rgba32Float dark and flat frames for flat field correction
+@group(2) @binding(3)
+var t_dark: texture_2d<f32>;
+@group(2) @binding(4)
+var t_flat: texture_2d<f32>;
+
+// Rg32Float texture coordinates in the distorted frame for each texel of the undistorted one
+@group(2) @binding(5)
+var t_remap: texture_2d<f32>;
+
+// must match MIN_GAIN in flat_field.rs
+const MIN_FLAT_FIELD_GAIN: f32 = 1e-4;
+
+// texel of an unfilterable texture at the given texture coordinates
+fn texel_coords(t: texture_2d<f32>, tex_coords: vec2<f32>) -> vec2<u32> {
+    let size = textureDimensions(t);
+    return min(vec2<u32>(tex_coords * vec2<f32>(size)), size - 1u);
+}
+
+// where to sample the frame for the given texture coordinates of the output, which is outside
+// 0-1 where undistorting pulls in pixels from beyond the edges of the frame
+fn undistort(tex_coords: vec2<f32>) -> vec2<f32> {
+    if (processing.undistort == 0u) {
+        return tex_coords;
+    }
+    return textureLoad(t_remap, texel_coords(t_remap, tex_coords), 0).xy;
+}
+
+// (raw - dark) / (flat - dark) * mean, or c unchanged if flat field correction is off
+fn flat_field_correct(c: vec4<f32>, tex_coords: vec2<f32>) -> vec4<f32> {
+    if (processing.flat_field == 0u) {
+        return c;
+    }
+    let coords = texel_coords(t_dark, tex_coords);
+    let dark = textureLoad(t_dark, coords, 0).rgb;
+    let gain = max(textureLoad(t_flat, coords, 0).rgb - dark, vec3<f32>(MIN_FLAT_FIELD_GAIN));
+    return vec4<f32>((c.rgb - dark) / gain * processing.flat_field_mean.rgb, c.a);
+}
+
+// the current and previous frames at the texture coordinates of the output, with everything up to
+// differencing and thresholding applied
+struct ProcessedSamples {
+    current: vec4<f32>,
+    previous: vec4<f32>,
+    // false where undistorting pulls in pixels from beyond the edges of the frame
+    inside: bool,
+};
+
+// sample the frames through the lens undistortion, the filters, flat field correction and
+// background subtraction. textureSample can only be called in uniform control flow, so call this
+// before branching.
+fn processed_samples(output_coords: vec2<f32>) -> ProcessedSamples {
+    let tex_coords = undistort(output_coords);
+    var tex_sample = textureSample(t_diffuse, s_diffuse, tex_coords);
+    if (processing.filtered != 0u) {
+        tex_sample = textureSample(t_filtered, s_diffuse, tex_coords);
+    }
+    var previous_sample = textureSample(t_previous, s_diffuse, tex_coords);
+    tex_sample = flat_field_correct(tex_sample, tex_coords);
+    previous_sample = flat_field_correct(previous_sample, tex_coords);
+    if (processing.subtract_background != 0u) {
+        // the background is learnt from raw frames, so it needs correcting too
+        let background = flat_field_correct(
+            textureLoad(t_background, texel_coords(t_background, tex_coords), 0),
+            tex_coords,
+        );
+        tex_sample = vec4<f32>(abs(tex_sample.rgb - background.rgb), tex_sample.a);
+        previous_sample = vec4<f32>(abs(previous_sample.rgb - background.rgb), previous_sample.a);
+    }
+    let inside = all(tex_coords >= vec2<f32>(0.0)) && all(tex_coords <= vec2<f32>(1.0));
+    return ProcessedSamples(tex_sample, previous_sample, inside);
+}
+
+// the sRGB transfer function, mapping encoded 0-1 values to linear light
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+// stretch the display range of c over 0-1, for frames that only use part of their range like
+// 12 bit data in 16 bits
+fn display_scale(c: vec4<f32>) -> vec4<f32> {
+    let low = processing.display_range.x / 100.0;
+    let high = processing.display_range.y / 100.0;
+    var rgb = clamp((c.rgb - low) / max(high - low, 1e-6), vec3<f32>(0.0), vec3<f32>(1.0));
+    if (processing.display_as_stored != 0u) {
+        // cancel out the encoding of the sRGB render target so the values land in it unchanged
+        rgb = srgb_to_linear(rgb);
+    }
+    return vec4<f32>(rgb, c.a);
+}
+
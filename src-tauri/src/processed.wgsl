@@ -0,0 +1,16 @@
+// Renders the frame the thresholds are applied to, appended to metric.wgsl and prelude.wgsl.
+// It is read back for the histogram, ROI stats, blob detection and the other analyses.
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let samples = processed_samples(in.tex_coords);
+    if (!samples.inside) {
+        return vec4<f32>(0.0);
+    }
+    if (processing.difference_mode == DIFFERENCE_ABSOLUTE) {
+        return vec4<f32>(abs(samples.current.rgb - samples.previous.rgb), samples.current.a);
+    }
+    // signed differences are shown with a colormap rather than thresholded, so the analyses get
+    // the current frame
+    return samples.current;
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    frame::Frame,
+    threshold::{metric_value, ThresholdMetric},
+};
+
+/// How `fs_main` combines the current frame with the previous one. The discriminants must match
+/// the `DIFFERENCE_*` constants in `prelude.wgsl`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DifferenceMode {
+    /// Show the current frame as-is
+    #[default]
+    Off = 0,
+    /// Show the per-channel absolute difference from the previous frame, which is then thresholded
+    /// like a normal frame
+    Absolute = 1,
+    /// Show the change in the threshold metric from the previous frame on a blue-white-red
+    /// colormap. Thresholds are not applied in this mode.
+    Signed = 2,
+}
+
+/// Layout of the processing uniform buffer bound at `@group(2) @binding(0)` in `prelude.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ProcessingUniform {
+    difference_mode: u32,
+    subtract_background: u32,
+    filtered: u32,
+    flat_field: u32,
+    flat_field_mean: [f32; 4],
+    undistort: u32,
+    display_as_stored: u32,
+    display_range: [f32; 2],
+}
+
+impl ProcessingUniform {
+    /// `flat_field_mean` is `None` when flat field correction is off. `display_range` is the part
+    /// of the 0-100 range stretched from black to white. `display_as_stored` shows values as they
+    /// are rather than as light, for raw sources drawn to sRGB targets.
+    pub fn new(
+        difference_mode: DifferenceMode,
+        subtract_background: bool,
+        filtered: bool,
+        flat_field_mean: Option<[f32; 4]>,
+        undistort: bool,
+        display_as_stored: bool,
+        display_range: [f32; 2],
+    ) -> Self {
+        Self {
+            difference_mode: difference_mode as u32,
+            subtract_background: subtract_background as u32,
+            filtered: filtered as u32,
+            flat_field: flat_field_mean.is_some() as u32,
+            flat_field_mean: flat_field_mean.unwrap_or([1.0; 4]),
+            undistort: undistort as u32,
+            display_as_stored: display_as_stored as u32,
+            display_range,
+        }
+    }
+}
+
+/// Payload of the `motion-score` and `motion-detected` events
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MotionScore {
+    pub frame_idx: Option<u32>,
+    /// `motion_score` in the source's native units, like the thresholds
+    pub score: f32,
+}
+
+/// Mean absolute change in the threshold metric between two frames, 0-100
+pub fn motion_score(frame: &Frame, previous_frame: &Frame, metric: ThresholdMetric) -> f32 {
+    if frame.dimensions() != previous_frame.dimensions() || frame.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = frame
+        .pixels()
+        .zip(previous_frame.pixels())
+        .map(|(pixel, previous_pixel)| {
+            let value = metric_value(pixel.0, metric);
+            let previous_value = metric_value(previous_pixel.0, metric);
+            (value - previous_value).abs() as f64
+        })
+        .sum();
+    (total / frame.pixels().len() as f64) as f32
+}
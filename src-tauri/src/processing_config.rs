@@ -0,0 +1,83 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    background::BackgroundModel,
+    bad_pixels::BadPixelMap,
+    blobs::BlobDetectionOptions,
+    distortion::LensDistortion,
+    filters::FilterStage,
+    processing::DifferenceMode,
+    roi::RoiShape,
+    spatial_calibration::SpatialCalibration,
+    temporal::TemporalFilter,
+    threshold::{AutoThresholdMethod, ThresholdMetric},
+};
+
+/// Everything that decides how frames are processed and measured, saved as JSON so the same
+/// analysis can be run again, e.g. by batch mode. The dark and flat frames of the flat-field
+/// correction stay with the rest of the source's calibration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProcessingConfig {
+    /// In the source's native units, like the threshold commands
+    pub min_threshold: f64,
+    /// `None` for the top of the source's range
+    pub max_threshold: Option<f64>,
+    pub threshold_metric: ThresholdMetric,
+    /// Recompute the min threshold for each frame with this method
+    pub auto_threshold_method: Option<AutoThresholdMethod>,
+    pub difference_mode: DifferenceMode,
+    pub background_model: Option<BackgroundModel>,
+    pub temporal_filter: Option<TemporalFilter>,
+    pub filter_chain: Vec<FilterStage>,
+    pub flat_field: bool,
+    pub bad_pixel_map: Option<BadPixelMap>,
+    pub lens_distortion: Option<LensDistortion>,
+    /// Replaces the source's own calibration if set
+    pub spatial_calibration: Option<SpatialCalibration>,
+    pub rois: Vec<RoiShape>,
+    pub blob_detection: Option<BlobDetectionOptions>,
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        Self {
+            min_threshold: 0.0,
+            max_threshold: None,
+            threshold_metric: ThresholdMetric::default(),
+            auto_threshold_method: None,
+            difference_mode: DifferenceMode::default(),
+            background_model: None,
+            temporal_filter: None,
+            filter_chain: Vec::new(),
+            flat_field: true,
+            bad_pixel_map: None,
+            lens_distortion: None,
+            spatial_calibration: None,
+            rois: Vec::new(),
+            blob_detection: None,
+        }
+    }
+}
+
+impl ProcessingConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file =
+            File::create(path).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,237 @@
+use serde::Serialize;
+
+use crate::{
+    frame::Frame,
+    overlay::OverlayLines,
+    spatial_calibration::SpatialCalibration,
+    threshold::{metric_value, ThresholdBand},
+};
+
+const PROFILE_LINE_COLOR: [f32; 4] = [0.0, 0.9, 1.0, 1.0];
+
+/// A line segment in texture pixel coordinates to take an intensity profile along
+#[derive(Copy, Clone, Debug)]
+pub struct ProfileLine {
+    pub start: [f32; 2],
+    pub end: [f32; 2],
+    /// Number of parallel lines, one pixel apart, that are averaged at each point
+    pub width: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineProfile {
+    /// Distance of each sample from the start of the line, in pixels
+    pub distance: Vec<f32>,
+    /// Distance of each sample from the start of the line in the units of the spatial
+    /// calibration, if there is one
+    pub physical_distance: Option<Vec<f64>>,
+    /// Threshold metric of the frame at each sample, in the source's native units like the
+    /// thresholds
+    pub raw: Vec<f32>,
+    /// Threshold metric of each sample after the min/max thresholds have been applied
+    pub thresholded: Vec<f32>,
+}
+
+impl LineProfile {
+    /// The profile with the metric values converted from the 0-100 scale by `units_per_percent`
+    pub fn in_units(mut self, units_per_percent: f64) -> Self {
+        for value in self.raw.iter_mut().chain(&mut self.thresholded) {
+            *value = (*value as f64 * units_per_percent) as f32;
+        }
+        self
+    }
+}
+
+impl ProfileLine {
+    /// A line from `start` to `end` on a `width` by `height` frame, with the endpoints clamped to
+    /// the frame and `line_width` to its diagonal so a bad request can't sample forever
+    pub fn clamped(
+        start: [f32; 2],
+        end: [f32; 2],
+        line_width: u32,
+        (width, height): (u32, u32),
+    ) -> Result<Self, String> {
+        if !start.iter().chain(&end).all(|c| c.is_finite()) {
+            return Err("line endpoints must be finite".to_string());
+        }
+        let clamp = |[x, y]: [f32; 2]| [x.clamp(0.0, width as f32), y.clamp(0.0, height as f32)];
+        let diagonal = (width as f32).hypot(height as f32).ceil() as u32;
+        Ok(Self {
+            start: clamp(start),
+            end: clamp(end),
+            width: line_width.clamp(1, diagonal.max(1)),
+        })
+    }
+
+    fn length(&self) -> f32 {
+        (self.end[0] - self.start[0]).hypot(self.end[1] - self.start[1])
+    }
+
+    /// Unit vectors along and perpendicular to the line
+    fn axes(&self) -> ([f32; 2], [f32; 2]) {
+        let length = self.length();
+        if length == 0.0 {
+            return ([1.0, 0.0], [0.0, 1.0]);
+        }
+        let along = [
+            (self.end[0] - self.start[0]) / length,
+            (self.end[1] - self.start[1]) / length,
+        ];
+        (along, [-along[1], along[0]])
+    }
+
+    /// Sample the frame once per pixel of length along the line
+    pub fn profile(
+        &self,
+        frame: &Frame,
+        band: ThresholdBand,
+        calibration: Option<&SpatialCalibration>,
+    ) -> LineProfile {
+        let length = self.length();
+        let (along, across) = self.axes();
+        let width = self.width.max(1);
+        let num_samples = length.ceil() as usize + 1;
+
+        let mut profile = LineProfile {
+            distance: Vec::with_capacity(num_samples),
+            physical_distance: None,
+            raw: Vec::with_capacity(num_samples),
+            thresholded: Vec::with_capacity(num_samples),
+        };
+        for i in 0..num_samples {
+            let distance = (i as f32).min(length);
+            let mut raw = 0.0;
+            let mut thresholded = 0.0;
+            for j in 0..width {
+                let offset = j as f32 - (width - 1) as f32 / 2.0;
+                let x = self.start[0] + along[0] * distance + across[0] * offset;
+                let y = self.start[1] + along[1] * distance + across[1] * offset;
+                let rgba = sample_bilinear(frame, x, y);
+                raw += metric_value(rgba, band.metric);
+                thresholded += metric_value(band.apply(rgba), band.metric);
+            }
+            profile.distance.push(distance);
+            profile.raw.push(raw / width as f32);
+            profile.thresholded.push(thresholded / width as f32);
+        }
+        if let Some(calibration) = calibration {
+            let unit_length = calibration.length(along[0], along[1]);
+            profile.physical_distance = Some(
+                profile
+                    .distance
+                    .iter()
+                    .map(|&distance| distance as f64 * unit_length)
+                    .collect(),
+            );
+        }
+        profile
+    }
+
+    pub fn draw(&self, lines: &mut OverlayLines) {
+        lines.line(self.start, self.end, PROFILE_LINE_COLOR);
+        if self.width > 1 {
+            // outline the band being averaged over
+            let (_, across) = self.axes();
+            let half_width = self.width as f32 / 2.0;
+            let offset = [across[0] * half_width, across[1] * half_width];
+            lines.outline(
+                &[
+                    [self.start[0] + offset[0], self.start[1] + offset[1]],
+                    [self.end[0] + offset[0], self.end[1] + offset[1]],
+                    [self.end[0] - offset[0], self.end[1] - offset[1]],
+                    [self.start[0] - offset[0], self.start[1] - offset[1]],
+                ],
+                PROFILE_LINE_COLOR,
+            );
+        }
+    }
+}
+
+/// Linearly interpolate the frame at a point in texture pixel coordinates, clamping to the edges
+/// the same way the diffuse sampler does
+pub fn sample_bilinear(frame: &Frame, x: f32, y: f32) -> [f32; 4] {
+    let max_x = frame.width() as i64 - 1;
+    let max_y = frame.height() as i64 - 1;
+    // pixel centers are at +0.5
+    let x = x - 0.5;
+    let y = y - 0.5;
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let fx = x - x0;
+    let fy = y - y0;
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).clamp(0, max_x) as u32;
+        let y = (y as i64).clamp(0, max_y) as u32;
+        frame.get_pixel(x, y).0
+    };
+    let top_left = texel(x0, y0);
+    let top_right = texel(x0 + 1.0, y0);
+    let bottom_left = texel(x0, y0 + 1.0);
+    let bottom_right = texel(x0 + 1.0, y0 + 1.0);
+    std::array::from_fn(|c| {
+        let top = top_left[c] * (1.0 - fx) + top_right[c] * fx;
+        let bottom = bottom_left[c] * (1.0 - fx) + bottom_right[c] * fx;
+        top * (1.0 - fy) + bottom * fy
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    /// A one row frame with the given gray levels
+    fn row(levels: &[f32]) -> Frame {
+        Frame::from_fn(levels.len() as u32, 1, |x, _| {
+            let v = levels[x as usize];
+            Rgba([v, v, v, 1.0])
+        })
+    }
+
+    fn gray_at(frame: &Frame, x: f32, y: f32) -> f32 {
+        sample_bilinear(frame, x, y)[0]
+    }
+
+    #[test]
+    fn non_finite_endpoints_are_rejected() {
+        for (start, end) in [
+            ([f32::NAN, 0.0], [1.0, 1.0]),
+            ([0.0, 0.0], [f32::INFINITY, 1.0]),
+        ] {
+            assert!(ProfileLine::clamped(start, end, 1, (10, 8)).is_err());
+        }
+    }
+
+    #[test]
+    fn endpoints_are_clamped_to_the_frame() {
+        let line = ProfileLine::clamped([-5.0, 3.0], [20.0, -1.0], 1, (10, 8)).unwrap();
+        assert_eq!(line.start, [0.0, 3.0]);
+        assert_eq!(line.end, [10.0, 0.0]);
+    }
+
+    #[test]
+    fn width_is_clamped_to_the_diagonal() {
+        let clamped_width = |line_width| {
+            ProfileLine::clamped([0.0, 0.0], [3.0, 4.0], line_width, (3, 4))
+                .unwrap()
+                .width
+        };
+        assert_eq!(clamped_width(100), 5);
+        assert_eq!(clamped_width(3), 3);
+        assert_eq!(clamped_width(0), 1);
+    }
+
+    #[test]
+    fn bilinear_samples_are_exact_at_pixel_centers_and_clamped_at_edges() {
+        let frame = row(&[0.2, 0.6]);
+        assert_eq!(gray_at(&frame, 0.5, 0.5), 0.2);
+        assert_eq!(gray_at(&frame, 1.5, 0.5), 0.6);
+        assert!((gray_at(&frame, 1.0, 0.5) - 0.4).abs() < 1e-6);
+        // past the outer pixel centers the edge pixels carry on
+        assert_eq!(gray_at(&frame, 0.0, 0.5), 0.2);
+        assert_eq!(gray_at(&frame, 2.0, 0.0), 0.6);
+        assert!((gray_at(&frame, 1.0, 1.0) - 0.4).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,220 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    mpsc, Arc,
+};
+
+// states of a readback buffer's mapping, shared with the map_async callback
+const NOT_MAPPED: u8 = 0;
+const MAPPED: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+struct Readback<T> {
+    buffer: wgpu::Buffer,
+    // the order the readback was started in and what it holds, while the buffer is in use
+    in_flight: Option<(u64, T)>,
+    map_requested: bool,
+    state: Arc<AtomicU8>,
+}
+
+/// A few staging buffers that results are copied into on the GPU and then mapped without waiting
+/// on it, so the render loop never stalls on a readback. Each readback carries a `T` describing
+/// what it holds. If every buffer is still waiting on its results, new readbacks are refused
+/// rather than blocking.
+pub struct Readbacks<T> {
+    readbacks: Vec<Readback<T>>,
+    next_sequence: u64,
+}
+
+impl<T> Readbacks<T> {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        count: usize,
+        size: wgpu::BufferAddress,
+    ) -> Self {
+        let readbacks = (0..count)
+            .map(|_| Readback {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(label),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                in_flight: None,
+                map_requested: false,
+                state: Arc::new(AtomicU8::new(NOT_MAPPED)),
+            })
+            .collect();
+        Self {
+            readbacks,
+            next_sequence: 0,
+        }
+    }
+
+    /// A free buffer to copy the results described by `tag` into, or `None` if they are all in
+    /// use. Call `after_submit` once the copy has been submitted.
+    pub fn start(&mut self, tag: T) -> Option<&wgpu::Buffer> {
+        let readback = self
+            .readbacks
+            .iter_mut()
+            .find(|readback| readback.in_flight.is_none())?;
+        readback.in_flight = Some((self.next_sequence, tag));
+        self.next_sequence += 1;
+        Some(&readback.buffer)
+    }
+
+    /// Whether any readbacks are still waiting on their results
+    pub fn is_busy(&self) -> bool {
+        self.readbacks
+            .iter()
+            .any(|readback| readback.in_flight.is_some())
+    }
+
+    /// Request mapping of any buffers whose copies were just submitted
+    pub fn after_submit(&mut self) {
+        for readback in self
+            .readbacks
+            .iter_mut()
+            .filter(|readback| readback.in_flight.is_some() && !readback.map_requested)
+        {
+            readback.map_requested = true;
+            let state = readback.state.clone();
+            readback
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    let mapped = if result.is_ok() { MAPPED } else { MAP_FAILED };
+                    state.store(mapped, Ordering::Release);
+                });
+        }
+    }
+
+    /// Collect the oldest readback once it has finished, without blocking. `read` is given its tag
+    /// and the contents of its buffer. Readbacks are collected in the order they were started.
+    pub fn poll<R>(
+        &mut self,
+        device: &wgpu::Device,
+        read: impl FnOnce(&T, &[u8]) -> R,
+    ) -> Option<(T, Result<R, String>)> {
+        device.poll(wgpu::Maintain::Poll);
+
+        let readback = self
+            .readbacks
+            .iter_mut()
+            .filter_map(|readback| {
+                let sequence = readback.in_flight.as_ref()?.0;
+                Some((sequence, readback))
+            })
+            .min_by_key(|(sequence, _)| *sequence)
+            .map(|(_, readback)| readback)?;
+        let result = match readback.state.load(Ordering::Acquire) {
+            NOT_MAPPED => return None,
+            MAPPED => {
+                let (_, tag) = readback.in_flight.as_ref().expect("should be in flight");
+                let result = read(tag, &readback.buffer.slice(..).get_mapped_range());
+                readback.buffer.unmap();
+                Ok(result)
+            }
+            _ => Err("failed to map a readback buffer".to_string()),
+        };
+        readback.state.store(NOT_MAPPED, Ordering::Release);
+        readback.map_requested = false;
+        let (_, tag) = readback.in_flight.take().expect("should be in flight");
+        Some((tag, result))
+    }
+}
+
+/// How a whole texture is laid out once copied into a buffer, with each row padded to the
+/// alignment copies need
+#[derive(Copy, Clone, Debug)]
+pub struct TextureLayout {
+    size: wgpu::Extent3d,
+    bytes_per_row: u32,
+    padded_bytes_per_row: u32,
+}
+
+impl TextureLayout {
+    pub fn of(texture: &wgpu::Texture) -> Self {
+        let size = texture.size();
+        let bytes_per_row = texture
+            .format()
+            .block_copy_size(None)
+            .expect("should be a color format")
+            * size.width;
+        Self {
+            size,
+            bytes_per_row,
+            padded_bytes_per_row: bytes_per_row
+                .next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+        }
+    }
+
+    pub fn buffer_size(&self) -> wgpu::BufferAddress {
+        (self.padded_bytes_per_row * self.size.height) as wgpu::BufferAddress
+    }
+
+    /// Encode a copy of all of `texture` into `buffer`, which should be `buffer_size` long
+    pub fn encode_copy(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        buffer: &wgpu::Buffer,
+    ) {
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.size.height),
+                },
+            },
+            self.size,
+        );
+    }
+
+    /// The texels of a copy made with `encode_copy`, without the padding at the end of each row
+    pub fn unpad(&self, data: &[u8]) -> Vec<u8> {
+        let mut texels = Vec::with_capacity((self.bytes_per_row * self.size.height) as usize);
+        for row in data.chunks(self.padded_bytes_per_row as usize) {
+            texels.extend_from_slice(&row[..self.bytes_per_row as usize]);
+        }
+        texels
+    }
+}
+
+/// Copy all of `texture` back from the GPU, in the layout of its format without any row padding.
+/// This waits on the GPU.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<Vec<u8>, String> {
+    let layout = TextureLayout::of(texture);
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Texture Readback Buffer"),
+        size: layout.buffer_size(),
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Texture Readback Encoder"),
+    });
+    layout.encode_copy(&mut encoder, texture, &readback_buffer);
+    queue.submit(Some(encoder.finish()));
+
+    let slice = readback_buffer.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("failed to read back a texture: {}", e))?;
+    let texels = layout.unpad(&slice.get_mapped_range());
+    readback_buffer.unmap();
+    Ok(texels)
+}
@@ -0,0 +1,425 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write as _},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+};
+
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+use crate::{pre_trigger::TriggerReason, snapshot::save_image, threshold::ThresholdMetric};
+
+// frames waiting to be written before new ones are dropped
+const MAX_QUEUED_FRAMES: usize = 16;
+const SIDECAR_FILE: &str = "recording.json";
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordingSource {
+    /// Every frame presented on screen, with all processing applied
+    #[default]
+    Rendered,
+    /// Every new frame from the source, as it was loaded
+    Source,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordingFormat {
+    /// Numbered PNGs in a directory
+    Png,
+    /// Numbered TIFFs in a directory
+    Tiff,
+    /// H.264 video, encoded by piping frames to ffmpeg
+    Mp4,
+    Mkv,
+}
+
+impl RecordingFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Png => "png",
+            RecordingFormat::Tiff => "tiff",
+            RecordingFormat::Mp4 => "mp4",
+            RecordingFormat::Mkv => "mkv",
+        }
+    }
+
+    /// Extension of the frames of image sequences, or `None` for videos
+    pub fn frame_extension(self) -> Option<&'static str> {
+        match self {
+            RecordingFormat::Png | RecordingFormat::Tiff => Some(self.extension()),
+            RecordingFormat::Mp4 | RecordingFormat::Mkv => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingOptions {
+    /// Directory to write an image sequence to, or the file to write a video to
+    pub path: PathBuf,
+    pub format: RecordingFormat,
+    #[serde(default)]
+    pub source: RecordingSource,
+    /// Draw the overlays into rendered frames
+    #[serde(default)]
+    pub overlays: bool,
+    /// ffmpeg executable to use for videos, found on the `PATH` by default
+    #[serde(default)]
+    pub ffmpeg: Option<PathBuf>,
+}
+
+impl RecordingOptions {
+    /// Whether to capture the frame about to be presented
+    pub fn wants_frame(&self, new_source_frame: bool) -> bool {
+        match self.source {
+            RecordingSource::Rendered => true,
+            RecordingSource::Source => new_source_frame,
+        }
+    }
+}
+
+/// Settings a frame was recorded with, kept for the sidecar
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedFrame {
+    /// Index of the source frame on screen, if live view was running
+    pub frame_idx: Option<u32>,
+    /// In the source's native units, like the threshold commands
+    pub min_threshold: f64,
+    pub max_threshold: f64,
+    pub threshold_metric: ThresholdMetric,
+}
+
+/// A frame captured before there was a recording to write it to
+pub struct BufferedFrame {
+    pub image: RgbaImage,
+    pub frame: RecordedFrame,
+    pub captured_at: Instant,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SidecarFrame {
+    /// File the frame was written to, for image sequences
+    file: Option<String>,
+    /// Time since recording started
+    elapsed_ms: f64,
+    #[serde(flatten)]
+    frame: RecordedFrame,
+}
+
+/// Contents of the sidecar JSON written next to a recording
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Sidecar<'a> {
+    options: &'a RecordingOptions,
+    /// Wall clock time recording started, RFC 3339
+    started_at: &'a str,
+    frame_rate: f64,
+    width: u32,
+    height: u32,
+    frames: &'a [SidecarFrame],
+    /// What started a triggered recording
+    trigger: Option<&'a SidecarTrigger>,
+    dropped_frames: u64,
+    skipped_source_frames: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SidecarTrigger {
+    reason: TriggerReason,
+    /// Time since recording started, i.e. how much was recorded before the trigger
+    elapsed_ms: f64,
+}
+
+/// Returned by `stop_recording`
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingSummary {
+    pub frames: u64,
+    /// Frames that were captured but couldn't be written because the writer fell behind
+    pub dropped_frames: u64,
+    /// Source frames that live view skipped over while recording, so were never captured
+    pub skipped_source_frames: u64,
+    pub sidecar_path: PathBuf,
+}
+
+enum Writer {
+    Sequence {
+        dir: PathBuf,
+        extension: &'static str,
+    },
+    Ffmpeg {
+        child: Child,
+        stdin: ChildStdin,
+    },
+}
+
+pub struct Recording {
+    options: RecordingOptions,
+    sender: SyncSender<(u64, RgbaImage)>,
+    writer: JoinHandle<Result<(), String>>,
+    frames: Vec<SidecarFrame>,
+    trigger: Option<SidecarTrigger>,
+    dropped_frames: u64,
+    skipped_source_frames: u64,
+    last_frame_idx: Option<u32>,
+    num_source_frames: u32,
+    start: Instant,
+    started_at: String,
+    frame_rate: f64,
+    size: (u32, u32),
+}
+
+impl Recording {
+    /// Start writing frames of the given size. `frame_period` sets the frame rate of videos and
+    /// `num_source_frames` is how many frames the source loops through, for counting skipped ones.
+    pub fn start(
+        options: RecordingOptions,
+        size: (u32, u32),
+        frame_period: Duration,
+        num_source_frames: u32,
+    ) -> Result<Self, String> {
+        Self::start_with_backlog(options, size, frame_period, num_source_frames, Vec::new())
+    }
+
+    /// Start a recording with frames that were captured before it, which are written first and
+    /// never dropped
+    pub fn start_with_backlog(
+        options: RecordingOptions,
+        size: (u32, u32),
+        frame_period: Duration,
+        num_source_frames: u32,
+        backlog: Vec<BufferedFrame>,
+    ) -> Result<Self, String> {
+        let frame_rate = 1.0 / frame_period.as_secs_f64();
+        let writer = match options.format.frame_extension() {
+            Some(extension) => {
+                fs::create_dir_all(&options.path)
+                    .map_err(|e| format!("failed to create {}: {}", options.path.display(), e))?;
+                Writer::Sequence {
+                    dir: options.path.clone(),
+                    extension,
+                }
+            }
+            None => spawn_ffmpeg(&options, size, frame_rate)?,
+        };
+
+        let start = backlog
+            .first()
+            .map_or_else(Instant::now, |frame| frame.captured_at);
+        let backlog: Vec<BufferedFrame> = backlog
+            .into_iter()
+            .filter(|frame| frame.image.dimensions() == size)
+            .collect();
+        let frames = backlog
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| SidecarFrame {
+                file: options
+                    .format
+                    .frame_extension()
+                    .map(|extension| frame_file_name(index as u64, extension)),
+                elapsed_ms: frame.captured_at.duration_since(start).as_secs_f64() * 1000.0,
+                frame: frame.frame,
+            })
+            .collect();
+        let backlog: Vec<RgbaImage> = backlog.into_iter().map(|frame| frame.image).collect();
+
+        let (sender, receiver) = mpsc::sync_channel::<(u64, RgbaImage)>(MAX_QUEUED_FRAMES);
+        let writer = thread::spawn(move || {
+            let frames = (0..).zip(backlog).chain(receiver);
+            match writer {
+                Writer::Sequence { dir, extension } => {
+                    for (index, frame) in frames {
+                        save_image(&frame.into(), &dir.join(frame_file_name(index, extension)))?;
+                    }
+                    Ok(())
+                }
+                Writer::Ffmpeg {
+                    mut child,
+                    mut stdin,
+                } => {
+                    for (_, frame) in frames {
+                        stdin
+                            .write_all(frame.as_raw())
+                            .map_err(|e| format!("failed to write to ffmpeg: {}", e))?;
+                    }
+                    // closing stdin lets ffmpeg finish the file
+                    drop(stdin);
+                    let status = child
+                        .wait()
+                        .map_err(|e| format!("failed to wait for ffmpeg: {}", e))?;
+                    if !status.success() {
+                        return Err(format!("ffmpeg exited with {}", status));
+                    }
+                    Ok(())
+                }
+            }
+        });
+
+        let since_start = chrono::Duration::from_std(start.elapsed())
+            .unwrap_or_else(|_| chrono::Duration::zero());
+        Ok(Self {
+            options,
+            sender,
+            writer,
+            frames,
+            trigger: None,
+            dropped_frames: 0,
+            skipped_source_frames: 0,
+            last_frame_idx: None,
+            num_source_frames,
+            start,
+            started_at: (chrono::Local::now() - since_start).to_rfc3339(),
+            frame_rate,
+            size,
+        })
+    }
+
+    pub fn options(&self) -> &RecordingOptions {
+        &self.options
+    }
+
+    /// Note in the sidecar that the recording was triggered now
+    pub fn set_trigger(&mut self, reason: TriggerReason) {
+        self.trigger = Some(SidecarTrigger {
+            reason,
+            elapsed_ms: self.start.elapsed().as_secs_f64() * 1000.0,
+        });
+    }
+
+    /// When the first frame of the recording was captured
+    pub fn start_time(&self) -> Instant {
+        self.start
+    }
+
+    /// Count a frame that was due but couldn't be captured
+    pub fn note_dropped_frame(&mut self) {
+        self.dropped_frames += 1;
+    }
+
+    /// Keep count of the source frames live view skips over
+    pub fn note_source_frame(&mut self, frame_idx: u32) {
+        if let Some(last_frame_idx) = self.last_frame_idx {
+            let step =
+                (frame_idx + self.num_source_frames - last_frame_idx) % self.num_source_frames;
+            self.skipped_source_frames += step.saturating_sub(1) as u64;
+        }
+        self.last_frame_idx = Some(frame_idx);
+    }
+
+    /// Queue a frame captured at `captured_at` to be written, dropping it if the writer has fallen
+    /// behind
+    pub fn push(&mut self, frame: RgbaImage, recorded_frame: RecordedFrame, captured_at: Instant) {
+        if frame.dimensions() != self.size {
+            self.dropped_frames += 1;
+            return;
+        }
+        let index = self.frames.len() as u64;
+        match self.sender.try_send((index, frame)) {
+            Ok(()) => {
+                self.frames.push(SidecarFrame {
+                    file: self
+                        .options
+                        .format
+                        .frame_extension()
+                        .map(|extension| frame_file_name(index, extension)),
+                    elapsed_ms: captured_at.duration_since(self.start).as_secs_f64() * 1000.0,
+                    frame: recorded_frame,
+                });
+            }
+            // a disconnected writer has failed, which `finish` reports
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped_frames += 1;
+            }
+        }
+    }
+
+    /// Wait for the queued frames to be written, then write the sidecar
+    pub fn finish(self) -> Result<RecordingSummary, String> {
+        drop(self.sender);
+        let written = self
+            .writer
+            .join()
+            .unwrap_or_else(|_| Err("recording writer panicked".to_string()));
+
+        let sidecar_path = match self.options.format.frame_extension() {
+            Some(_) => self.options.path.join(SIDECAR_FILE),
+            None => self.options.path.with_extension("json"),
+        };
+        write_sidecar(
+            &sidecar_path,
+            &Sidecar {
+                options: &self.options,
+                started_at: &self.started_at,
+                frame_rate: self.frame_rate,
+                width: self.size.0,
+                height: self.size.1,
+                frames: &self.frames,
+                trigger: self.trigger.as_ref(),
+                dropped_frames: self.dropped_frames,
+                skipped_source_frames: self.skipped_source_frames,
+            },
+        )?;
+        written?;
+
+        Ok(RecordingSummary {
+            frames: self.frames.len() as u64,
+            dropped_frames: self.dropped_frames,
+            skipped_source_frames: self.skipped_source_frames,
+            sidecar_path,
+        })
+    }
+}
+
+fn frame_file_name(index: u64, extension: &str) -> String {
+    format!("frame-{:06}.{}", index, extension)
+}
+
+fn spawn_ffmpeg(
+    options: &RecordingOptions,
+    size: (u32, u32),
+    frame_rate: f64,
+) -> Result<Writer, String> {
+    let ffmpeg = options
+        .ffmpeg
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("ffmpeg"));
+    let mut child = Command::new(&ffmpeg)
+        .args(["-y", "-loglevel", "error"])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .args(["-s", &format!("{}x{}", size.0, size.1)])
+        .args(["-framerate", &frame_rate.to_string()])
+        .args(["-i", "-", "-an", "-c:v", "libx264"])
+        // yuv420p is the most widely playable, but needs even dimensions
+        .args([
+            "-vf",
+            "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+            "-pix_fmt",
+            "yuv420p",
+        ])
+        .arg(&options.path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", ffmpeg.display(), e))?;
+    let stdin = child.stdin.take().expect("stdin should be piped");
+    Ok(Writer::Ffmpeg { child, stdin })
+}
+
+fn write_sidecar(path: &Path, sidecar: &Sidecar) -> Result<(), String> {
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, sidecar)?;
+        writer.flush()
+    };
+    write().map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
@@ -0,0 +1,498 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    frame::Frame,
+    overlay::OverlayLines,
+    spatial_calibration::SpatialCalibration,
+    threshold::{metric_value, ThresholdBand},
+};
+
+const ROI_OUTLINE_COLOR: [f32; 4] = [1.0, 0.85, 0.0, 1.0];
+const ELLIPSE_OUTLINE_SEGMENTS: usize = 64;
+// oldest points are dropped from a trace once it reaches this length
+const MAX_TRACE_LEN: usize = 10_000;
+
+/// Region of interest shapes, in texture pixel coordinates with the origin at the top left corner
+/// of the frame. A pixel is inside the ROI if its center is.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RoiShape {
+    Rectangle {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    Ellipse {
+        cx: f32,
+        cy: f32,
+        rx: f32,
+        ry: f32,
+    },
+    Polygon {
+        points: Vec<[f32; 2]>,
+    },
+}
+
+impl RoiShape {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        match self {
+            RoiShape::Rectangle {
+                x: left,
+                y: top,
+                width,
+                height,
+            } => {
+                let (x0, x1) = (left.min(left + width), left.max(left + width));
+                let (y0, y1) = (top.min(top + height), top.max(top + height));
+                x >= x0 && x < x1 && y >= y0 && y < y1
+            }
+            RoiShape::Ellipse { cx, cy, rx, ry } => {
+                if *rx <= 0.0 || *ry <= 0.0 {
+                    return false;
+                }
+                ((x - cx) / rx).powi(2) + ((y - cy) / ry).powi(2) <= 1.0
+            }
+            RoiShape::Polygon { points } => {
+                // even-odd rule
+                let mut inside = false;
+                for (i, &[x0, y0]) in points.iter().enumerate() {
+                    let [x1, y1] = points[(i + 1) % points.len()];
+                    if (y0 > y) != (y1 > y) && x < x0 + (y - y0) / (y1 - y0) * (x1 - x0) {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+
+    /// Bounding box as `(min_x, min_y, max_x, max_y)`
+    pub fn bounds(&self) -> (f32, f32, f32, f32) {
+        match self {
+            RoiShape::Rectangle {
+                x,
+                y,
+                width,
+                height,
+            } => (
+                x.min(x + width),
+                y.min(y + height),
+                x.max(x + width),
+                y.max(y + height),
+            ),
+            RoiShape::Ellipse { cx, cy, rx, ry } => (cx - rx, cy - ry, cx + rx, cy + ry),
+            RoiShape::Polygon { points } => points.iter().fold(
+                (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+                |(min_x, min_y, max_x, max_y), &[x, y]| {
+                    (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+                },
+            ),
+        }
+    }
+
+    /// Points of the closed outline of the shape
+    pub fn outline(&self) -> Vec<[f32; 2]> {
+        match self {
+            RoiShape::Rectangle {
+                x,
+                y,
+                width,
+                height,
+            } => vec![
+                [*x, *y],
+                [x + width, *y],
+                [x + width, y + height],
+                [*x, y + height],
+            ],
+            RoiShape::Ellipse { cx, cy, rx, ry } => (0..ELLIPSE_OUTLINE_SEGMENTS)
+                .map(|i| {
+                    let angle = i as f32 / ELLIPSE_OUTLINE_SEGMENTS as f32 * std::f32::consts::TAU;
+                    [cx + rx * angle.cos(), cy + ry * angle.sin()]
+                })
+                .collect(),
+            RoiShape::Polygon { points } => points.clone(),
+        }
+    }
+
+    /// Call `f` with the coordinates of every frame pixel inside the shape
+    pub fn for_each_pixel(&self, width: u32, height: u32, mut f: impl FnMut(u32, u32)) {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        let x_range =
+            (min_x.floor().max(0.0) as u32)..(max_x.ceil().clamp(0.0, width as f32) as u32);
+        let y_range =
+            (min_y.floor().max(0.0) as u32)..(max_y.ceil().clamp(0.0, height as f32) as u32);
+        for y in y_range {
+            for x in x_range.clone() {
+                if self.contains(x as f32 + 0.5, y as f32 + 0.5) {
+                    f(x, y);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Roi {
+    pub id: u32,
+    pub shape: RoiShape,
+}
+
+/// Statistics of the threshold metric over the pixels inside an ROI. They are computed on the 0-100
+/// metric scale and reported in the source's native units, like the thresholds.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoiStats {
+    pub roi_id: u32,
+    /// Number of pixels inside the ROI
+    pub area: u64,
+    /// Area in square units of the spatial calibration, if there is one
+    pub physical_area: Option<f64>,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Sample standard deviation
+    pub std_dev: f64,
+    /// Sum of the metric over the ROI, i.e. `mean * area`
+    pub integrated_density: f64,
+    /// Percentage of pixels falling between the min and max thresholds
+    pub percent_in_band: f64,
+}
+
+impl RoiStats {
+    /// The stats with the metric values converted from the 0-100 scale by `units_per_percent`
+    pub fn in_units(self, units_per_percent: f64) -> Self {
+        Self {
+            mean: self.mean * units_per_percent,
+            min: self.min * units_per_percent,
+            max: self.max * units_per_percent,
+            std_dev: self.std_dev * units_per_percent,
+            integrated_density: self.integrated_density * units_per_percent,
+            ..self
+        }
+    }
+}
+
+impl Roi {
+    pub fn stats(
+        &self,
+        frame: &Frame,
+        band: ThresholdBand,
+        calibration: Option<&SpatialCalibration>,
+    ) -> RoiStats {
+        let mut area = 0u64;
+        let mut sum = 0.0f64;
+        let mut sum_sq = 0.0f64;
+        let mut min = f64::MAX;
+        let mut max = f64::MIN;
+        let mut in_band = 0u64;
+        self.shape
+            .for_each_pixel(frame.width(), frame.height(), |x, y| {
+                let value = metric_value(frame.get_pixel(x, y).0, band.metric);
+                if band.contains(value) {
+                    in_band += 1;
+                }
+                let value = value as f64;
+                area += 1;
+                sum += value;
+                sum_sq += value * value;
+                min = min.min(value);
+                max = max.max(value);
+            });
+
+        if area == 0 {
+            return RoiStats {
+                roi_id: self.id,
+                ..Default::default()
+            };
+        }
+        let mean = sum / area as f64;
+        let variance = if area > 1 {
+            ((sum_sq - sum * mean) / (area - 1) as f64).max(0.0)
+        } else {
+            0.0
+        };
+        RoiStats {
+            roi_id: self.id,
+            area,
+            physical_area: calibration.map(|calibration| calibration.area(area)),
+            mean,
+            min,
+            max,
+            std_dev: variance.sqrt(),
+            integrated_density: sum,
+            percent_in_band: in_band as f64 / area as f64 * 100.0,
+        }
+    }
+}
+
+/// A single sample of an ROI's intensity over time
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoiTracePoint {
+    pub frame_idx: u32,
+    /// Time since live view was started
+    pub elapsed_ms: f64,
+    #[serde(flatten)]
+    pub stats: RoiStats,
+}
+
+#[derive(Default)]
+pub struct Rois {
+    rois: Vec<Roi>,
+    next_id: u32,
+    traces: HashMap<u32, VecDeque<RoiTracePoint>>,
+}
+
+impl Rois {
+    pub fn add(&mut self, shape: RoiShape) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.rois.push(Roi { id, shape });
+        id
+    }
+
+    pub fn update(&mut self, id: u32, shape: RoiShape) -> Result<(), String> {
+        let roi = self
+            .rois
+            .iter_mut()
+            .find(|roi| roi.id == id)
+            .ok_or_else(|| format!("no ROI with id {}", id))?;
+        roi.shape = shape;
+        // samples of the old shape would be meaningless in the same trace
+        self.traces.remove(&id);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: u32) -> Result<(), String> {
+        let idx = self
+            .rois
+            .iter()
+            .position(|roi| roi.id == id)
+            .ok_or_else(|| format!("no ROI with id {}", id))?;
+        self.rois.remove(idx);
+        self.traces.remove(&id);
+        Ok(())
+    }
+
+    pub fn all(&self) -> &[Roi] {
+        &self.rois
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rois.is_empty()
+    }
+
+    pub fn stats(
+        &self,
+        frame: &Frame,
+        band: ThresholdBand,
+        calibration: Option<&SpatialCalibration>,
+    ) -> Vec<RoiStats> {
+        self.rois
+            .iter()
+            .map(|roi| roi.stats(frame, band, calibration))
+            .collect()
+    }
+
+    /// Append one sample per ROI to the traces, returning the new points
+    pub fn record(
+        &mut self,
+        frame_idx: u32,
+        elapsed_ms: f64,
+        stats: Vec<RoiStats>,
+    ) -> Vec<RoiTracePoint> {
+        stats
+            .into_iter()
+            .map(|stats| {
+                let point = RoiTracePoint {
+                    frame_idx,
+                    elapsed_ms,
+                    stats,
+                };
+                let trace = self.traces.entry(point.stats.roi_id).or_default();
+                if trace.len() == MAX_TRACE_LEN {
+                    trace.pop_front();
+                }
+                trace.push_back(point.clone());
+                point
+            })
+            .collect()
+    }
+
+    pub fn clear_traces(&mut self) {
+        self.traces.clear();
+    }
+
+    pub fn trace(&self, id: u32) -> Result<Vec<RoiTracePoint>, String> {
+        if !self.rois.iter().any(|roi| roi.id == id) {
+            return Err(format!("no ROI with id {}", id));
+        }
+        Ok(self
+            .traces
+            .get(&id)
+            .map(|trace| trace.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    pub fn write_trace_csv(&self, id: u32, path: &Path) -> Result<(), String> {
+        let trace = self.trace(id)?;
+        let write = || -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(path)?);
+            writeln!(
+                writer,
+                "frame_idx,elapsed_ms,area,physical_area,mean,min,max,std_dev,integrated_density,percent_in_band"
+            )?;
+            for point in trace {
+                let stats = point.stats;
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    point.frame_idx,
+                    point.elapsed_ms,
+                    stats.area,
+                    // left empty without a spatial calibration
+                    stats
+                        .physical_area
+                        .map_or(String::new(), |area| area.to_string()),
+                    stats.mean,
+                    stats.min,
+                    stats.max,
+                    stats.std_dev,
+                    stats.integrated_density,
+                    stats.percent_in_band
+                )?;
+            }
+            writer.flush()
+        };
+        write().map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn draw(&self, lines: &mut OverlayLines) {
+        for roi in &self.rois {
+            lines.outline(&roi.shape.outline(), ROI_OUTLINE_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::{spatial_calibration::LengthUnit, threshold::ThresholdMetric};
+
+    const BAND: ThresholdBand = ThresholdBand {
+        min: 45.0,
+        max: 101.0,
+        metric: ThresholdMetric::Value,
+    };
+
+    /// A gray frame with each pixel a tenth of the digit at its place in `rows`
+    fn levels(rows: &[&str]) -> Frame {
+        Frame::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            let digit = rows[y as usize].as_bytes()[x as usize] - b'0';
+            let v = digit as f32 / 10.0;
+            Rgba([v, v, v, 1.0])
+        })
+    }
+
+    fn rectangle(x: f32, y: f32, width: f32, height: f32) -> RoiShape {
+        RoiShape::Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn pixels(shape: &RoiShape, width: u32, height: u32) -> Vec<(u32, u32)> {
+        let mut pixels = Vec::new();
+        shape.for_each_pixel(width, height, |x, y| pixels.push((x, y)));
+        pixels
+    }
+
+    #[test]
+    fn self_intersecting_polygons_use_the_even_odd_rule() {
+        // a five-pointed star drawn in one stroke, which crosses itself around the middle
+        let points = (0..5)
+            .map(|i| {
+                let angle = (i * 2 % 5) as f32 / 5.0 * std::f32::consts::TAU;
+                [10.0 * angle.sin(), -10.0 * angle.cos()]
+            })
+            .collect();
+        let star = RoiShape::Polygon { points };
+        assert!(star.contains(0.0, -8.0));
+        assert!(!star.contains(0.0, 0.0));
+        assert!(!star.contains(0.0, -11.0));
+    }
+
+    #[test]
+    fn rectangles_with_negative_sizes_are_normalised() {
+        let flipped = rectangle(4.0, 3.0, -4.0, -3.0);
+        assert_eq!(flipped.bounds(), (0.0, 0.0, 4.0, 3.0));
+        assert!(flipped.contains(1.0, 1.0));
+        assert!(!flipped.contains(4.0, 1.0));
+        assert_eq!(
+            pixels(&flipped, 8, 8),
+            pixels(&rectangle(0.0, 0.0, 4.0, 3.0), 8, 8)
+        );
+    }
+
+    #[test]
+    fn degenerate_ellipses_contain_nothing() {
+        let line = RoiShape::Ellipse {
+            cx: 2.5,
+            cy: 2.5,
+            rx: 2.0,
+            ry: 0.0,
+        };
+        assert!(!line.contains(2.5, 2.5));
+        assert!(pixels(&line, 5, 5).is_empty());
+    }
+
+    #[test]
+    fn stats_use_the_sample_standard_deviation() {
+        let roi = Roi {
+            id: 3,
+            shape: rectangle(0.0, 0.0, 8.0, 1.0),
+        };
+        let stats = roi.stats(&levels(&["24445579"]), BAND, None);
+        assert_eq!(stats.roi_id, 3);
+        assert_eq!(stats.area, 8);
+        assert!((stats.mean - 50.0).abs() < 1e-4);
+        assert!((stats.min - 20.0).abs() < 1e-4);
+        assert!((stats.max - 90.0).abs() < 1e-4);
+        // the squared deviations sum to 3200, over 7 degrees of freedom
+        assert!((stats.std_dev - (3200.0f64 / 7.0).sqrt()).abs() < 1e-3);
+        assert!((stats.integrated_density - 400.0).abs() < 1e-3);
+        assert_eq!(stats.percent_in_band, 50.0);
+    }
+
+    #[test]
+    fn empty_rois_have_default_stats() {
+        let roi = Roi {
+            id: 1,
+            shape: rectangle(10.0, 10.0, 2.0, 2.0),
+        };
+        let calibration = SpatialCalibration {
+            unit: LengthUnit::Micrometers,
+            pixel_width: 2.0,
+            pixel_height: 2.0,
+        };
+        let stats = roi.stats(&levels(&["55", "55"]), BAND, Some(&calibration));
+        assert_eq!(stats.roi_id, 1);
+        assert_eq!(stats.area, 0);
+        assert_eq!(stats.physical_area, None);
+        assert_eq!([stats.mean, stats.min, stats.max, stats.std_dev], [0.0; 4]);
+        assert_eq!(stats.percent_in_band, 0.0);
+    }
+}
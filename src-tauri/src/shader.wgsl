@@ -1,42 +1,47 @@
-// Vertex shader
+// Built-in fragment shader, appended to metric.wgsl and prelude.wgsl
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
-};
-
-@vertex
-fn vs_main(
-    model: VertexInput,
-) -> VertexOutput {
-    var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    out.clip_position = vec4<f32>(model.position, 1.0);
-    return out;
+// blue for negative, white for 0, red for positive, with d in -1 to 1
+fn diverging_colormap(d: f32) -> vec3<f32> {
+    let t = clamp(d, -1.0, 1.0);
+    if (t < 0.0) {
+        return mix(vec3<f32>(1.0, 1.0, 1.0), vec3<f32>(0.23, 0.3, 0.75), -t);
+    }
+    return mix(vec3<f32>(1.0, 1.0, 1.0), vec3<f32>(0.7, 0.02, 0.15), t);
 }
 
-// Fragment shader
-
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0) @binding(1)
-var s_diffuse: sampler;
-@group(1) @binding(0)
-var<uniform> min_max_threshold: vec2<u32>;
-
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var tex_sample = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let lum = (0.2126*tex_sample.r + 0.7152*tex_sample.g + 0.0722*tex_sample.b) * 100;
-    if (lum <= f32(min_max_threshold.x)) {
+    let samples = processed_samples(in.tex_coords);
+    if (!samples.inside) {
+        return vec4<f32>(0.0);
+    }
+    var tex_sample = samples.current;
+    if (processing.difference_mode == DIFFERENCE_SIGNED) {
+        let d = metric_value(tex_sample, threshold.metric)
+            - metric_value(samples.previous, threshold.metric);
+        return vec4<f32>(diverging_colormap(d / 100.0), tex_sample.a);
+    } else if (processing.difference_mode == DIFFERENCE_ABSOLUTE) {
+        tex_sample = vec4<f32>(abs(tex_sample.rgb - samples.previous.rgb), tex_sample.a);
+    }
+
+    let value = metric_value(tex_sample, threshold.metric);
+    let min_threshold = threshold.min_max.x;
+    let max_threshold = threshold.min_max.y;
+    if (threshold.metric == METRIC_HUE) {
+        // hue wraps around, so a min above the max selects the band through 0
+        var in_band: bool;
+        if (min_threshold <= max_threshold) {
+            in_band = value >= min_threshold && value <= max_threshold;
+        } else {
+            in_band = value >= min_threshold || value <= max_threshold;
+        }
+        if (!in_band) {
+            tex_sample = vec4<f32>(0.0, 0.0, 0.0, tex_sample.a);
+        }
+    } else if (value <= min_threshold) {
         tex_sample = vec4<f32>(0.0, 0.0, 0.0, tex_sample.a);
-    } else if (lum >= f32(min_max_threshold.y)) {
+    } else if (value >= max_threshold) {
         tex_sample = vec4<f32>(1.0, 1.0, 1.0, tex_sample.a);
     }
-    return tex_sample;
+    return display_scale(tex_sample);
 }
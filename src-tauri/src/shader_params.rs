@@ -0,0 +1,389 @@
+use serde::{Deserialize, Serialize};
+
+/// Bind group a user shader can declare its params uniform in, at binding 0
+pub const SHADER_PARAMS_GROUP: u32 = 3;
+/// Uniform buffers are bound in multiples of this
+const UNIFORM_ALIGNMENT: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ShaderParamType {
+    F32,
+    I32,
+    U32,
+}
+
+/// A scalar or vector member of a user shader's params uniform.
+///
+/// Ranges come from comments on or directly above the member, e.g.
+///
+/// ```wgsl
+/// struct Params {
+///     // @range(0, 1) @step(0.01)
+///     strength: f32,
+///     tint: vec3<f32>, // @default(1, 0.5, 0.2)
+/// };
+/// ```
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShaderParam {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub param_type: ShaderParamType,
+    /// 1 for scalars, otherwise the number of vector components
+    pub components: u32,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    pub step: Option<f32>,
+    pub value: Vec<f32>,
+    #[serde(skip)]
+    offset: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ShaderParamValue {
+    Scalar(f32),
+    Vector(Vec<f32>),
+}
+
+/// The params of the current shader, along with the contents of their uniform buffer
+#[derive(Clone, Debug, Default)]
+pub struct ShaderParams {
+    params: Vec<ShaderParam>,
+    data: Vec<u8>,
+}
+
+impl ShaderParams {
+    /// Find the uniform at `@group(SHADER_PARAMS_GROUP) @binding(0)` in `module`, if there is one,
+    /// and list its members. `source` is what `module` was parsed from, for reading the comments.
+    pub fn reflect(module: &naga::Module, source: &str) -> Result<Self, String> {
+        let Some((_, global)) = module.global_variables.iter().find(|(_, global)| {
+            global
+                .binding
+                .as_ref()
+                .is_some_and(|binding| binding.group == SHADER_PARAMS_GROUP && binding.binding == 0)
+        }) else {
+            return Ok(Self::default());
+        };
+        if global.space != naga::AddressSpace::Uniform {
+            return Err(format!(
+                "@group({}) @binding(0) must be a uniform",
+                SHADER_PARAMS_GROUP
+            ));
+        }
+        let naga::TypeInner::Struct { members, span } = &module.types[global.ty].inner else {
+            return Err(format!(
+                "@group({}) @binding(0) must be a struct",
+                SHADER_PARAMS_GROUP
+            ));
+        };
+        // the span stops at the last member's type, so carry on to the closing brace to take in
+        // any comment after it
+        let struct_source = module
+            .types
+            .get_span(global.ty)
+            .to_range()
+            .and_then(|range| {
+                let end = source
+                    .get(range.end..)?
+                    .find('}')
+                    .map_or(source.len(), |brace| range.end + brace);
+                source.get(range.start..end)
+            })
+            .unwrap_or("");
+
+        let mut params = Vec::new();
+        for member in members {
+            let Some(name) = &member.name else {
+                continue;
+            };
+            let (scalar, components) = match module.types[member.ty].inner {
+                naga::TypeInner::Scalar(scalar) => (scalar, 1),
+                naga::TypeInner::Vector { size, scalar } => (scalar, size as u32),
+                // matrices, arrays and nested structs keep their place in the buffer but aren't
+                // exposed as controls
+                _ => continue,
+            };
+            let param_type = match (scalar.kind, scalar.width) {
+                (naga::ScalarKind::Float, 4) => ShaderParamType::F32,
+                (naga::ScalarKind::Sint, 4) => ShaderParamType::I32,
+                (naga::ScalarKind::Uint, 4) => ShaderParamType::U32,
+                _ => continue,
+            };
+
+            let comments = member_comments(struct_source, name);
+            let first = |key| annotation(&comments, key).and_then(|values| values.first().copied());
+            let min = first("range");
+            let max = annotation(&comments, "range").and_then(|values| values.get(1).copied());
+            let mut value = annotation(&comments, "default").unwrap_or_default();
+            value.resize(components as usize, value.last().copied().unwrap_or(0.0));
+
+            let mut param = ShaderParam {
+                name: name.clone(),
+                param_type,
+                components,
+                min,
+                max,
+                step: first("step"),
+                value,
+                offset: member.offset,
+            };
+            param.value = param.clamp(&param.value);
+            params.push(param);
+        }
+
+        let mut shader_params = Self {
+            params,
+            data: vec![0; (*span as usize).next_multiple_of(UNIFORM_ALIGNMENT)],
+        };
+        for i in 0..shader_params.params.len() {
+            shader_params.write(i);
+        }
+        Ok(shader_params)
+    }
+
+    pub fn params(&self) -> &[ShaderParam] {
+        &self.params
+    }
+
+    /// Contents of the params uniform buffer, empty if the shader doesn't have one
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Carry over the values of params that are still there with the same type, so reloading a
+    /// shader doesn't reset its controls
+    pub fn keep_values_from(&mut self, previous: &ShaderParams) {
+        for i in 0..self.params.len() {
+            let param = &self.params[i];
+            if let Some(previous_param) = previous.params.iter().find(|previous_param| {
+                previous_param.name == param.name
+                    && previous_param.param_type == param.param_type
+                    && previous_param.components == param.components
+            }) {
+                self.params[i].value = param.clamp(&previous_param.value);
+                self.write(i);
+            }
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: ShaderParamValue) -> Result<(), String> {
+        let i = self
+            .params
+            .iter()
+            .position(|param| param.name == name)
+            .ok_or_else(|| format!("no shader param named {}", name))?;
+        let value = match value {
+            ShaderParamValue::Scalar(value) => vec![value],
+            ShaderParamValue::Vector(value) => value,
+        };
+        let param = &self.params[i];
+        if value.len() != param.components as usize {
+            return Err(format!(
+                "{} has {} components but got {}",
+                name,
+                param.components,
+                value.len()
+            ));
+        }
+        self.params[i].value = param.clamp(&value);
+        self.write(i);
+        Ok(())
+    }
+
+    fn write(&mut self, i: usize) {
+        let param = &self.params[i];
+        for (component, &value) in param.value.iter().enumerate() {
+            let bytes = match param.param_type {
+                ShaderParamType::F32 => value.to_le_bytes(),
+                ShaderParamType::I32 => (value.round() as i32).to_le_bytes(),
+                ShaderParamType::U32 => (value.round() as u32).to_le_bytes(),
+            };
+            let offset = param.offset as usize + 4 * component;
+            self.data[offset..offset + 4].copy_from_slice(&bytes);
+        }
+    }
+}
+
+impl ShaderParam {
+    fn clamp(&self, value: &[f32]) -> Vec<f32> {
+        value
+            .iter()
+            .map(|&v| {
+                let v = self.min.map_or(v, |min| v.max(min));
+                self.max.map_or(v, |max| v.min(max))
+            })
+            .collect()
+    }
+}
+
+/// The comment on the same line as a struct member's declaration, plus any comment lines
+/// directly above it
+fn member_comments(struct_source: &str, name: &str) -> String {
+    let lines: Vec<&str> = struct_source.lines().collect();
+    let Some(line_idx) = lines.iter().position(|line| {
+        skip_attributes(line)
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.trim_start().starts_with(':'))
+    }) else {
+        return String::new();
+    };
+
+    let mut comments: Vec<&str> = lines[..line_idx]
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .take_while(|line| line.starts_with("//"))
+        .collect();
+    comments.reverse();
+    if let Some((_, trailing)) = lines[line_idx].split_once("//") {
+        comments.push(trailing);
+    }
+    comments.join(" ")
+}
+
+/// `line` from the start of the member's name, past any attributes like `@align(16)`
+fn skip_attributes(line: &str) -> &str {
+    let mut rest = line.trim_start();
+    while let Some(attribute) = rest.strip_prefix('@') {
+        let name_end = attribute
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(attribute.len());
+        rest = &attribute[name_end..];
+        if rest.starts_with('(') {
+            rest = rest.find(')').map_or("", |end| &rest[end + 1..]);
+        }
+        rest = rest.trim_start();
+    }
+    rest
+}
+
+/// The comma separated numbers in `@key(...)`, if `comments` has it
+fn annotation(comments: &str, key: &str) -> Option<Vec<f32>> {
+    let start = comments.find(&format!("@{}(", key))? + key.len() + 2;
+    let end = start + comments[start..].find(')')?;
+    comments[start..end]
+        .split(',')
+        .map(|value| value.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reflect(source: &str) -> Result<ShaderParams, String> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|e| e.message().to_string())?;
+        ShaderParams::reflect(&module, source)
+    }
+
+    const SOURCE: &str = "
+struct Params {
+    // @range(0, 1) @step(0.01)
+    strength: f32,
+    tint: vec3<f32>, // @default(1, 0.5, 0.2)
+    // spread out over
+    // two lines @range(-4, 4)
+    @align(16) offset: vec2<i32>,
+    transform: mat2x2<f32>,
+    count: u32, // @default(7) @range(0, 5)
+};
+
+@group(3) @binding(0)
+var<uniform> params: Params;
+";
+
+    fn param<'a>(params: &'a ShaderParams, name: &str) -> &'a ShaderParam {
+        params
+            .params()
+            .iter()
+            .find(|param| param.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn members_are_reflected_with_their_annotations() {
+        let params = reflect(SOURCE).unwrap();
+        let names: Vec<&str> = params.params().iter().map(|p| p.name.as_str()).collect();
+        // the matrix keeps its place in the buffer but isn't a control
+        assert_eq!(names, ["strength", "tint", "offset", "count"]);
+
+        let strength = param(&params, "strength");
+        assert_eq!(strength.param_type, ShaderParamType::F32);
+        assert_eq!(strength.components, 1);
+        assert_eq!((strength.min, strength.max), (Some(0.0), Some(1.0)));
+        assert_eq!(strength.step, Some(0.01));
+        assert_eq!(strength.value, [0.0]);
+
+        let tint = param(&params, "tint");
+        assert_eq!(tint.components, 3);
+        assert_eq!(tint.value, [1.0, 0.5, 0.2]);
+        assert_eq!((tint.min, tint.max), (None, None));
+
+        let offset = param(&params, "offset");
+        assert_eq!(offset.param_type, ShaderParamType::I32);
+        assert_eq!((offset.min, offset.max), (Some(-4.0), Some(4.0)));
+        assert_eq!(offset.offset, 32);
+
+        // defaults are clamped to the range
+        let count = param(&params, "count");
+        assert_eq!(count.param_type, ShaderParamType::U32);
+        assert_eq!(count.value, [5.0]);
+    }
+
+    #[test]
+    fn buffer_holds_the_values_at_their_offsets() {
+        let mut params = reflect(SOURCE).unwrap();
+        // padded out to the uniform alignment
+        assert_eq!(params.data().len() % UNIFORM_ALIGNMENT, 0);
+        let tint_offset = param(&params, "tint").offset as usize;
+        assert_eq!(
+            &params.data()[tint_offset..tint_offset + 12],
+            bytemuck::cast_slice::<f32, u8>(&[1.0, 0.5, 0.2])
+        );
+
+        params
+            .set("offset", ShaderParamValue::Vector(vec![-2.6, 9.0]))
+            .unwrap();
+        assert_eq!(param(&params, "offset").value, [-2.6, 4.0]);
+        assert_eq!(
+            &params.data()[32..40],
+            bytemuck::cast_slice::<i32, u8>(&[-3, 4])
+        );
+
+        assert!(params.set("tint", ShaderParamValue::Scalar(1.0)).is_err());
+        assert!(params
+            .set("missing", ShaderParamValue::Scalar(1.0))
+            .is_err());
+    }
+
+    #[test]
+    fn values_survive_a_reload_when_the_param_is_unchanged() {
+        let mut previous = reflect(SOURCE).unwrap();
+        previous
+            .set("strength", ShaderParamValue::Scalar(0.75))
+            .unwrap();
+        previous
+            .set("tint", ShaderParamValue::Vector(vec![0.1, 0.2, 0.3]))
+            .unwrap();
+
+        // tint is now a vec4, so it starts again from its default
+        let reloaded_source = SOURCE.replace("tint: vec3<f32>", "tint: vec4<f32>");
+        let mut reloaded = reflect(&reloaded_source).unwrap();
+        reloaded.keep_values_from(&previous);
+        assert_eq!(param(&reloaded, "strength").value, [0.75]);
+        assert_eq!(param(&reloaded, "tint").value, [1.0, 0.5, 0.2, 0.2]);
+    }
+
+    #[test]
+    fn shaders_without_params_have_an_empty_buffer() {
+        let params =
+            reflect("@fragment fn fs() -> @location(0) vec4<f32> { return vec4(1.0); }").unwrap();
+        assert!(params.params().is_empty());
+        assert!(params.data().is_empty());
+
+        let error = reflect("@group(3) @binding(0) var<storage> params: array<f32>;").unwrap_err();
+        assert!(error.contains("must be a uniform"), "{}", error);
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use serde::Serialize;
+
+use crate::shader_params::{ShaderParams, SHADER_PARAMS_GROUP};
+
+/// Where user shaders are loaded from. Each `.wgsl` file is a shader, named by its file stem.
+pub const SHADER_DIR: &str = "./shaders";
+
+/// Everything a fragment shader is appended to. User shaders get the same bindings and helpers
+/// as the built-in `shader.wgsl`.
+pub const PRELUDE: &str = concat!(include_str!("metric.wgsl"), include_str!("prelude.wgsl"));
+
+/// Payload of the `shader-error` event, and the error returned by `set_shader`
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShaderError {
+    pub name: String,
+    pub message: String,
+    /// 1-based position in the user's file, if the error can be pinned to one
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl ShaderError {
+    pub fn new(name: &str, message: String) -> Self {
+        Self {
+            name: name.to_string(),
+            message,
+            line: None,
+            column: None,
+        }
+    }
+
+    fn at(mut self, location: Option<naga::SourceLocation>) -> Self {
+        // errors in the prelude itself have no position in the user's file
+        let prelude_lines = PRELUDE.lines().count() as u32;
+        if let Some(location) = location.filter(|location| location.line_number > prelude_lines) {
+            self.line = Some(location.line_number - prelude_lines);
+            self.column = Some(location.line_position);
+        }
+        self
+    }
+}
+
+pub fn shader_path(name: &str) -> PathBuf {
+    Path::new(SHADER_DIR).join(format!("{}.wgsl", name))
+}
+
+/// Names of the shaders in `SHADER_DIR`, sorted
+pub fn list_shaders() -> Result<Vec<String>, String> {
+    let entries = match fs::read_dir(SHADER_DIR) {
+        Ok(entries) => entries,
+        // no directory just means no user shaders
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("failed to read {}: {}", SHADER_DIR, e)),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "wgsl")
+        })
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// A user shader that has passed validation
+pub struct CompiledShader {
+    /// Full source ready for `create_shader_module`
+    pub source: String,
+    pub params: ShaderParams,
+}
+
+/// Read a user shader, check it with naga and reflect its params
+pub fn compile(name: &str) -> Result<CompiledShader, ShaderError> {
+    let path = shader_path(name);
+    let user_source = fs::read_to_string(&path)
+        .map_err(|e| ShaderError::new(name, format!("failed to read {}: {}", path.display(), e)))?;
+    let source = format!("{}{}", PRELUDE, user_source);
+
+    let module = naga::front::wgsl::parse_str(&source)
+        .map_err(|e| ShaderError::new(name, e.message().to_string()).at(e.location(&source)))?;
+    Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| {
+            // the top level validation error only says which function is invalid, so include
+            // the reasons underneath it
+            let mut message = e.as_inner().to_string();
+            let mut cause = std::error::Error::source(e.as_inner());
+            while let Some(error) = cause {
+                message = format!("{}: {}", message, error);
+                cause = error.source();
+            }
+            ShaderError::new(name, message).at(e.location(&source))
+        })?;
+
+    if !module.entry_points.iter().any(|entry_point| {
+        entry_point.name == "fs_main" && entry_point.stage == naga::ShaderStage::Fragment
+    }) {
+        return Err(ShaderError::new(
+            name,
+            "missing a `@fragment fn fs_main`".to_string(),
+        ));
+    }
+
+    // the pipeline layout is fixed, so only the bindings from the prelude and the params uniform
+    // can be used
+    let mut available_bindings =
+        bindings(&naga::front::wgsl::parse_str(PRELUDE).expect("prelude should parse"));
+    available_bindings.insert((SHADER_PARAMS_GROUP, 0));
+    if let Some(binding) = bindings(&module).difference(&available_bindings).next() {
+        return Err(ShaderError::new(
+            name,
+            format!(
+                "@group({}) @binding({}) is not available to user shaders",
+                binding.0, binding.1
+            ),
+        ));
+    }
+
+    let params = ShaderParams::reflect(&module, &source)
+        .map_err(|message| ShaderError::new(name, message))?;
+    Ok(CompiledShader { source, params })
+}
+
+/// A user shader that is currently in use
+pub struct CustomShader {
+    pub name: String,
+    // stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl CustomShader {
+    /// Start watching the shader's file, calling `on_change` from the watcher's thread whenever it
+    /// is written
+    pub fn watch(name: String, on_change: impl Fn() + Send + 'static) -> Result<Self, ShaderError> {
+        let file_name = shader_path(&name)
+            .file_name()
+            .map(|file_name| file_name.to_owned());
+        let watch_error = |e: notify::Error| {
+            ShaderError::new(&name, format!("failed to watch {}: {}", SHADER_DIR, e))
+        };
+        let mut watcher =
+            notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+                let Ok(event) = result else {
+                    return;
+                };
+                if (event.kind.is_modify() || event.kind.is_create())
+                    && event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == file_name.as_deref())
+                {
+                    on_change();
+                }
+            })
+            .map_err(watch_error)?;
+        // watch the directory rather than the file, since some editors save by replacing it
+        watcher
+            .watch(Path::new(SHADER_DIR), RecursiveMode::NonRecursive)
+            .map_err(watch_error)?;
+        Ok(Self {
+            name,
+            _watcher: watcher,
+        })
+    }
+}
+
+fn bindings(module: &naga::Module) -> HashSet<(u32, u32)> {
+    module
+        .global_variables
+        .iter()
+        .filter_map(|(_, global)| global.binding.as_ref())
+        .map(|binding| (binding.group, binding.binding))
+        .collect()
+}
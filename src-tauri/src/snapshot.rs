@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use serde::Deserialize;
+
+use crate::readback;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SnapshotOptions {
+    /// Draw the ROIs, blobs, tracks, etc. over the frame like on screen
+    pub overlays: bool,
+    /// Save the source frame as it was loaded instead of rendering it, at its full depth
+    pub unprocessed: bool,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self {
+            overlays: true,
+            unprocessed: false,
+        }
+    }
+}
+
+/// Copy a render target back from the GPU, swapping the channels of BGRA formats. This waits on
+/// the GPU.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<RgbaImage, String> {
+    let texels = readback::read_texture(device, queue, texture)?;
+    rgba_image(texture.format(), texture.size(), texels)
+}
+
+/// An image from the texels of a render target without any row padding, swapping the channels of
+/// BGRA formats
+pub fn rgba_image(
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    mut texels: Vec<u8>,
+) -> Result<RgbaImage, String> {
+    let bgra = match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        format => return Err(format!("can't read back {:?} textures", format)),
+    };
+    if bgra {
+        for pixel in texels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    Ok(RgbaImage::from_raw(size.width, size.height, texels).expect("should be the right size"))
+}
+
+/// Write an image as a PNG or TIFF, going by the extension of `path`. Float images can only be
+/// written as TIFFs.
+pub fn save_image(image: &DynamicImage, path: &Path) -> Result<(), String> {
+    let format = ImageFormat::from_path(path)
+        .ok()
+        .filter(|format| matches!(format, ImageFormat::Png | ImageFormat::Tiff))
+        .ok_or_else(|| format!("{} should end in .png or .tiff", path.display()))?;
+    image
+        .save_with_format(path, format)
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
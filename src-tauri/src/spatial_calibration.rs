@@ -0,0 +1,261 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{blobs::Blob, overlay::OverlayLines};
+
+const SCALE_BAR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+// fraction of the frame width the scale bar aims for, before rounding to a nice length
+const SCALE_BAR_TARGET_WIDTH: f64 = 0.2;
+// fraction of the frame height used for the margin, bar thickness and label height
+const SCALE_BAR_MARGIN: f32 = 0.04;
+const SCALE_BAR_THICKNESS: f32 = 0.008;
+const SCALE_BAR_LABEL_HEIGHT: f32 = 0.04;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LengthUnit {
+    Micrometers,
+    Millimeters,
+}
+
+impl LengthUnit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            LengthUnit::Micrometers => "µm",
+            LengthUnit::Millimeters => "mm",
+        }
+    }
+}
+
+/// Physical size of the pixels of a source, saved as JSON with the rest of its calibration
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpatialCalibration {
+    pub unit: LengthUnit,
+    /// Size of a pixel along x, in `unit`
+    pub pixel_width: f64,
+    /// Size of a pixel along y, in `unit`. The same as `pixel_width` unless the pixels aren't
+    /// square.
+    pub pixel_height: f64,
+}
+
+/// Blob measurements in the units of the spatial calibration
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PhysicalBlob {
+    pub centroid: [f64; 2],
+    pub area: f64,
+    pub perimeter: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl SpatialCalibration {
+    /// Calibrate from a line in texture pixel coordinates that is `length` long. The pixel aspect
+    /// ratio of `previous` is kept, if there is one.
+    pub fn from_line(
+        start: [f32; 2],
+        end: [f32; 2],
+        length: f64,
+        unit: LengthUnit,
+        previous: Option<&SpatialCalibration>,
+    ) -> Result<Self, String> {
+        let aspect = previous.map_or(1.0, |previous| previous.pixel_height / previous.pixel_width);
+        let dx = (end[0] - start[0]) as f64;
+        let dy = (end[1] - start[1]) as f64;
+        // length in units of pixel widths
+        let pixels = dx.hypot(dy * aspect);
+        if pixels == 0.0 {
+            return Err("calibration line has no length".to_string());
+        }
+        let pixel_width = length / pixels;
+        Self {
+            unit,
+            pixel_width,
+            pixel_height: pixel_width * aspect,
+        }
+        .validated()
+    }
+
+    pub fn validated(self) -> Result<Self, String> {
+        let positive = |size: f64| size.is_finite() && size > 0.0;
+        if !positive(self.pixel_width) || !positive(self.pixel_height) {
+            return Err("pixel sizes must be greater than 0".to_string());
+        }
+        Ok(self)
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file =
+            File::open(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let calibration: Self = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        calibration.validated().map(Some)
+    }
+
+    /// Save `calibration` to `path`, or remove any saved calibration with `None`
+    pub fn save(calibration: Option<&Self>, path: &Path) -> Result<(), String> {
+        let Some(calibration) = calibration else {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(format!("failed to remove {}: {}", path.display(), e))
+                }
+                _ => Ok(()),
+            };
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        }
+        let file =
+            File::create(path).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), calibration)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    /// Physical length of an offset in pixels
+    pub fn length(&self, dx: f32, dy: f32) -> f64 {
+        (dx as f64 * self.pixel_width).hypot(dy as f64 * self.pixel_height)
+    }
+
+    pub fn area(&self, pixels: u64) -> f64 {
+        pixels as f64 * self.pixel_width * self.pixel_height
+    }
+
+    pub fn point(&self, point: [f32; 2]) -> [f64; 2] {
+        [
+            point[0] as f64 * self.pixel_width,
+            point[1] as f64 * self.pixel_height,
+        ]
+    }
+
+    pub fn blob(&self, blob: &Blob) -> PhysicalBlob {
+        // measure the outline itself, since its diagonal steps aren't √2 long in both units if the
+        // pixels aren't square
+        let perimeter = if blob.outline.len() > 1 {
+            (0..blob.outline.len())
+                .map(|i| {
+                    let [x0, y0] = blob.outline[i];
+                    let [x1, y1] = blob.outline[(i + 1) % blob.outline.len()];
+                    self.length(x1 - x0, y1 - y0)
+                })
+                .sum()
+        } else {
+            0.0
+        };
+        PhysicalBlob {
+            centroid: self.point(blob.centroid),
+            area: self.area(blob.area),
+            perimeter,
+            width: blob.bounding_box.width as f64 * self.pixel_width,
+            height: blob.bounding_box.height as f64 * self.pixel_height,
+        }
+    }
+
+    /// Draw a labelled scale bar in the bottom right corner of a frame of the given size
+    pub fn draw_scale_bar(&self, frame_size: (u32, u32), lines: &mut OverlayLines) {
+        let (width, height) = (frame_size.0 as f32, frame_size.1 as f32);
+        let length = nice_length(width as f64 * self.pixel_width * SCALE_BAR_TARGET_WIDTH);
+        let bar_width = (length / self.pixel_width) as f32;
+        let margin = height * SCALE_BAR_MARGIN;
+        let right = width - margin;
+        let left = right - bar_width;
+        let bottom = height - margin;
+        let thickness = (height * SCALE_BAR_THICKNESS).max(1.0);
+
+        // fill the bar with one line per pixel of thickness
+        let mut y = bottom;
+        while y > bottom - thickness {
+            lines.line([left, y], [right, y], SCALE_BAR_COLOR);
+            y -= 1.0;
+        }
+
+        let label = scale_bar_label(length, self.unit);
+        let label_height = height * SCALE_BAR_LABEL_HEIGHT;
+        let label_width = OverlayLines::text_width(&label, label_height);
+        lines.text(
+            &label,
+            [
+                (left + right - label_width) / 2.0,
+                bottom - thickness - margin / 2.0 - label_height,
+            ],
+            label_height,
+            SCALE_BAR_COLOR,
+        );
+    }
+}
+
+/// The largest 1, 2 or 5 times a power of 10 that is at most `max`
+fn nice_length(max: f64) -> f64 {
+    let magnitude = 10f64.powf(max.log10().floor());
+    [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|step| step * magnitude)
+        .find(|&length| length <= max)
+        .unwrap_or(magnitude)
+}
+
+/// Label for a scale bar `length` long in `unit`, in millimetres from 1 mm up and micrometres
+/// below it so the number stays short
+fn scale_bar_label(length: f64, unit: LengthUnit) -> String {
+    let (length, unit) = match unit {
+        LengthUnit::Micrometers if length >= 1000.0 => (length / 1000.0, LengthUnit::Millimeters),
+        LengthUnit::Millimeters if length < 1.0 => (length * 1000.0, LengthUnit::Micrometers),
+        _ => (length, unit),
+    };
+    format!("{} {}", format_length(length), unit.symbol())
+}
+
+fn format_length(length: f64) -> String {
+    // nice lengths only have one significant digit, so this is exact up to float error
+    let decimals = (-length.log10().floor()).max(0.0) as usize;
+    format!("{:.*}", decimals, length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nice_lengths_are_1_2_or_5_times_a_power_of_10() {
+        assert_eq!(nice_length(1.0), 1.0);
+        assert_eq!(nice_length(1.9), 1.0);
+        assert_eq!(nice_length(2.0), 2.0);
+        assert_eq!(nice_length(4.99), 2.0);
+        assert_eq!(nice_length(7.0), 5.0);
+        assert_eq!(nice_length(99.0), 50.0);
+        assert_eq!(nice_length(1000.0), 1000.0);
+        assert_eq!(nice_length(0.3), 0.2);
+        assert!((nice_length(0.0007) - 0.0005).abs() < 1e-12);
+    }
+
+    #[test]
+    fn lengths_are_formatted_without_trailing_zeros() {
+        assert_eq!(format_length(500.0), "500");
+        assert_eq!(format_length(5.0), "5");
+        assert_eq!(format_length(0.5), "0.5");
+        assert_eq!(format_length(0.02), "0.02");
+        assert_eq!(format_length(0.1 + 0.2 - 0.1), "0.2");
+    }
+
+    #[test]
+    fn scale_bar_labels_switch_between_micrometers_and_millimeters() {
+        use LengthUnit::*;
+        assert_eq!(scale_bar_label(500.0, Micrometers), "500 µm");
+        assert_eq!(scale_bar_label(1000.0, Micrometers), "1 mm");
+        assert_eq!(scale_bar_label(5000.0, Micrometers), "5 mm");
+        assert_eq!(scale_bar_label(2.0, Millimeters), "2 mm");
+        assert_eq!(scale_bar_label(0.5, Millimeters), "500 µm");
+        assert_eq!(scale_bar_label(0.2, Millimeters), "200 µm");
+        assert_eq!(scale_bar_label(0.002, Millimeters), "2 µm");
+        assert_eq!(scale_bar_label(0.5, Micrometers), "0.5 µm");
+    }
+}
@@ -0,0 +1,381 @@
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    frame,
+    frame_history::{FrameHistory, MAX_HISTORY_FRAMES},
+};
+
+// must match the MODE_* constants in temporal.wgsl
+const MODE_ROLLING_MEAN: u32 = 0;
+const MODE_EXPONENTIAL_SMOOTHING: u32 = 1;
+const MODE_ROLLING_MAX: u32 = 2;
+const MODE_ROLLING_MIN: u32 = 3;
+
+const WORKGROUP_SIZE: u32 = 16;
+
+// exponential smoothing is warmed up until the frames before carry less than this much weight
+const SMOOTHING_WARM_UP_WEIGHT: f32 = 0.01;
+// and for no more than this many frames, for alphas near 0 that would take forever
+const MAX_SMOOTHING_WARM_UP_FRAMES: u32 = 4 * MAX_HISTORY_FRAMES;
+
+/// Ways of combining each new frame with the ones before it to cut down on noise. The windowed
+/// filters are limited to `MAX_HISTORY_FRAMES` frames.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TemporalFilter {
+    /// Mean of the last `frames` frames
+    RollingMean { frames: u32 },
+    /// Exponential moving average, where each new frame is given a weight of `alpha`, 0-1
+    ExponentialSmoothing { alpha: f32 },
+    /// Per-channel maximum over the last `frames` frames
+    RollingMax { frames: u32 },
+    /// Per-channel minimum over the last `frames` frames
+    RollingMin { frames: u32 },
+}
+
+impl TemporalFilter {
+    /// How many frames the filter needs to see before a given one for its output there to be the
+    /// same as in live view, or close enough for exponential smoothing, whose weights never quite
+    /// reach 0
+    pub fn warm_up_frames(self) -> u32 {
+        match self {
+            TemporalFilter::RollingMean { frames }
+            | TemporalFilter::RollingMax { frames }
+            | TemporalFilter::RollingMin { frames } => frames.clamp(1, MAX_HISTORY_FRAMES) - 1,
+            TemporalFilter::ExponentialSmoothing { alpha } => {
+                let kept = 1.0 - alpha.clamp(0.0, 1.0);
+                if kept <= SMOOTHING_WARM_UP_WEIGHT {
+                    0
+                } else if kept >= 1.0 {
+                    MAX_SMOOTHING_WARM_UP_FRAMES
+                } else {
+                    let frames = (SMOOTHING_WARM_UP_WEIGHT.ln() / kept.ln()).ceil() as u32;
+                    frames.min(MAX_SMOOTHING_WARM_UP_FRAMES)
+                }
+            }
+        }
+    }
+}
+
+/// Layout of the params uniform in `temporal.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TemporalParams {
+    mode: u32,
+    alpha: f32,
+    history_len: u32,
+    _padding: u32,
+}
+
+/// Runs a temporal filter over each new frame on the GPU.
+///
+/// The filtered frames are accumulated in a pair of textures in the `frame::intermediate_format`
+/// of the frames that take turns being read from and written to, so exponential smoothing can build on the previous result. The latest
+/// result is copied into a fixed output texture for the passes that come after.
+pub struct GpuTemporalFilter {
+    pipeline: wgpu::ComputePipeline,
+    // bind group `i` reads accumulator `1 - i` and writes accumulator `i`
+    bind_groups: [wgpu::BindGroup; 2],
+    params_buffer: wgpu::Buffer,
+    accumulators: [wgpu::Texture; 2],
+    output_texture: wgpu::Texture,
+    // which accumulator holds the latest result
+    current: usize,
+    history: FrameHistory,
+    texture_size: wgpu::Extent3d,
+    workgroups: (u32, u32),
+    /// `None` if temporal filtering is off
+    filter: Option<TemporalFilter>,
+    // whether the accumulators hold anything yet
+    initialized: bool,
+}
+
+impl GpuTemporalFilter {
+    pub fn new(
+        device: &wgpu::Device,
+        diffuse_texture: &wgpu::Texture,
+        texture_size: wgpu::Extent3d,
+    ) -> Self {
+        let format = frame::intermediate_format(diffuse_texture.format());
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("temporal.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                frame::with_output_format(include_str!("temporal.wgsl"), format).into(),
+            ),
+        });
+
+        let accumulators = ["temporal_accumulator_0", "temporal_accumulator_1"].map(|label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                size: texture_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                label: Some(label),
+                view_formats: &[],
+            })
+        });
+        let accumulator_views = accumulators
+            .each_ref()
+            .map(|accumulator| accumulator.create_view(&wgpu::TextureViewDescriptor::default()));
+        let output_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            label: Some("temporal_output_texture"),
+            view_formats: &[],
+        });
+        let history = FrameHistory::new(
+            device,
+            texture_size,
+            diffuse_texture.format(),
+            "temporal_history_texture",
+        );
+        let diffuse_texture_view =
+            diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Temporal Params Buffer"),
+            contents: bytemuck::bytes_of(&TemporalParams {
+                mode: MODE_ROLLING_MEAN,
+                alpha: 1.0,
+                history_len: 0,
+                _padding: 0,
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                frame::frame_binding_layout_entry(0, wgpu::TextureViewDimension::D2),
+                frame::frame_binding_layout_entry(1, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("temporal_bind_group_layout"),
+        });
+        let bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&accumulator_views[1 - i]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&accumulator_views[i]),
+                    },
+                ],
+                label: Some("temporal_bind_group"),
+            })
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("temporal_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout, history.bind_group_layout()],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("temporal_pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_groups,
+            params_buffer,
+            accumulators,
+            output_texture,
+            current: 0,
+            history,
+            texture_size,
+            workgroups: (
+                texture_size.width.div_ceil(WORKGROUP_SIZE),
+                texture_size.height.div_ceil(WORKGROUP_SIZE),
+            ),
+            filter: None,
+            initialized: false,
+        }
+    }
+
+    /// The latest filtered frame
+    pub fn output(&self) -> &wgpu::Texture {
+        &self.output_texture
+    }
+
+    /// Whether frames should be taken from the filter output rather than the diffuse texture.
+    /// Until the first frame has been filtered there is nothing to show.
+    pub fn enabled(&self) -> bool {
+        self.filter.is_some() && self.initialized
+    }
+
+    pub fn filter(&self) -> Option<TemporalFilter> {
+        self.filter
+    }
+
+    /// Switch to a new filter, or turn temporal filtering off with `None`. The filter starts over
+    /// unless it is unchanged. Frames are only kept for the rolling filters.
+    pub fn set_filter(&mut self, device: &wgpu::Device, filter: Option<TemporalFilter>) {
+        if filter != self.filter {
+            self.filter = filter;
+            let window = match filter {
+                Some(
+                    TemporalFilter::RollingMean { frames }
+                    | TemporalFilter::RollingMax { frames }
+                    | TemporalFilter::RollingMin { frames },
+                ) => Some(frames),
+                Some(TemporalFilter::ExponentialSmoothing { .. }) | None => None,
+            };
+            self.history.set_window(device, window);
+            self.reset();
+        }
+    }
+
+    /// Encode copying the latest filtered frame into `texture`, which must be the size and format
+    /// of the output. Copying into the output itself puts back the latest result after something
+    /// else has been copied there.
+    pub fn encode_copy_output(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        encoder.copy_texture_to_texture(
+            self.accumulators[self.current].as_image_copy(),
+            texture.as_image_copy(),
+            self.texture_size,
+        );
+    }
+
+    /// Forget all previous frames so the filter starts over from the next one
+    pub fn reset(&mut self) {
+        self.initialized = false;
+        self.history.clear();
+    }
+
+    /// Encode a filter update with a new frame, which must already have been written to the
+    /// diffuse texture. Does nothing if filtering is off.
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        diffuse_texture: &wgpu::Texture,
+    ) {
+        let Some(filter) = self.filter else {
+            return;
+        };
+        let params = match filter {
+            TemporalFilter::ExponentialSmoothing { alpha } => TemporalParams {
+                mode: MODE_EXPONENTIAL_SMOOTHING,
+                // start from the first frame as-is
+                alpha: if self.initialized {
+                    alpha.clamp(0.0, 1.0)
+                } else {
+                    1.0
+                },
+                history_len: 0,
+                _padding: 0,
+            },
+            TemporalFilter::RollingMean { .. }
+            | TemporalFilter::RollingMax { .. }
+            | TemporalFilter::RollingMin { .. } => {
+                self.history.push(encoder, diffuse_texture);
+                TemporalParams {
+                    mode: match filter {
+                        TemporalFilter::RollingMax { .. } => MODE_ROLLING_MAX,
+                        TemporalFilter::RollingMin { .. } => MODE_ROLLING_MIN,
+                        _ => MODE_ROLLING_MEAN,
+                    },
+                    alpha: 0.0,
+                    history_len: self.history.len(),
+                    _padding: 0,
+                }
+            }
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let next = 1 - self.current;
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("temporal_pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &self.bind_groups[next], &[]);
+            cpass.set_bind_group(1, self.history.bind_group(), &[]);
+            cpass.dispatch_workgroups(self.workgroups.0, self.workgroups.1, 1);
+        }
+        self.current = next;
+        self.initialized = true;
+
+        self.encode_copy_output(encoder, &self.output_texture);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_filters_warm_up_over_the_rest_of_their_window() {
+        assert_eq!(
+            TemporalFilter::RollingMean { frames: 5 }.warm_up_frames(),
+            4
+        );
+        assert_eq!(TemporalFilter::RollingMax { frames: 0 }.warm_up_frames(), 0);
+        assert_eq!(
+            TemporalFilter::RollingMin { frames: 100 }.warm_up_frames(),
+            MAX_HISTORY_FRAMES - 1
+        );
+    }
+
+    #[test]
+    fn smoothing_warms_up_until_earlier_frames_barely_count() {
+        let frames = TemporalFilter::ExponentialSmoothing { alpha: 0.5 }.warm_up_frames();
+        // 0.5^7 is just under 1%
+        assert_eq!(frames, 7);
+        assert_eq!(
+            TemporalFilter::ExponentialSmoothing { alpha: 1.0 }.warm_up_frames(),
+            0
+        );
+        assert_eq!(
+            TemporalFilter::ExponentialSmoothing { alpha: 0.0 }.warm_up_frames(),
+            MAX_SMOOTHING_WARM_UP_FRAMES
+        );
+    }
+}
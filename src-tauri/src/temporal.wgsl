@@ -0,0 +1,62 @@
+// Temporal filter update
+
+// must match the MODE_* constants in temporal.rs
+const MODE_ROLLING_MEAN: u32 = 0u;
+const MODE_EXPONENTIAL_SMOOTHING: u32 = 1u;
+const MODE_ROLLING_MAX: u32 = 2u;
+const MODE_ROLLING_MIN: u32 = 3u;
+
+// must match TemporalParams in temporal.rs
+struct Params {
+    mode: u32,
+    alpha: f32,
+    history_len: u32,
+};
+
+@group(0) @binding(0)
+var t_frame: texture_2d<f32>;
+// the output of the last update
+@group(0) @binding(1)
+var t_accumulated: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+// OUTPUT_FORMAT is filled in with the accumulator format by temporal.rs
+@group(0) @binding(3)
+var t_output: texture_storage_2d<OUTPUT_FORMAT, write>;
+// the most recent frames, in no particular order, bound by FrameHistory
+@group(1) @binding(0)
+var t_history: texture_2d_array<f32>;
+
+@compute @workgroup_size(16, 16)
+fn cs_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(t_frame);
+    if (global_id.x >= size.x || global_id.y >= size.y) {
+        return;
+    }
+    let coords = global_id.xy;
+
+    var filtered: vec4<f32>;
+    if (params.mode == MODE_EXPONENTIAL_SMOOTHING) {
+        filtered = mix(
+            textureLoad(t_accumulated, coords, 0),
+            textureLoad(t_frame, coords, 0),
+            params.alpha,
+        );
+    } else {
+        filtered = textureLoad(t_history, coords, 0u, 0);
+        for (var i = 1u; i < params.history_len; i++) {
+            let texel = textureLoad(t_history, coords, i, 0);
+            if (params.mode == MODE_ROLLING_MAX) {
+                filtered = max(filtered, texel);
+            } else if (params.mode == MODE_ROLLING_MIN) {
+                filtered = min(filtered, texel);
+            } else {
+                filtered += texel;
+            }
+        }
+        if (params.mode == MODE_ROLLING_MEAN) {
+            filtered /= f32(params.history_len);
+        }
+    }
+    textureStore(t_output, coords, filtered);
+}
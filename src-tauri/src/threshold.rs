@@ -0,0 +1,407 @@
+use serde::{Deserialize, Serialize};
+
+use crate::histogram::Histogram;
+
+/// The per-pixel value that the min/max thresholds are compared against.
+///
+/// Every metric is scaled to 0-100 so the same threshold inputs work regardless of which one is
+/// selected. Metrics are computed from the frame as decoded for the source's `ColorSpace`, so
+/// for sRGB sources they measure linear light rather than the stored codes.
+///
+/// The discriminants are what `fs_main` switches on, so keep them in sync with the `METRIC_*`
+/// constants in `metric.wgsl`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ThresholdMetric {
+    /// Rec.709 luminance
+    #[default]
+    Luminance709 = 0,
+    /// Rec.601 luma
+    Luminance601 = 1,
+    Red = 2,
+    Green = 3,
+    Blue = 4,
+    Alpha = 5,
+    /// HSV value, i.e. the max of the RGB channels
+    Value = 6,
+    /// HSV saturation
+    Saturation = 7,
+    /// HSV hue as a percentage of the colour wheel. Since hue wraps around, pixels inside the
+    /// min/max band keep their colour and everything else is blacked out. A min greater than the
+    /// max selects a band that wraps through red, e.g. 90-10.
+    Hue = 8,
+}
+
+impl ThresholdMetric {
+    /// Whether the metric is proportional to the samples, rather than a ratio like hue and
+    /// saturation, so it can be given in the source's native units
+    pub fn is_intensity(self) -> bool {
+        !matches!(self, ThresholdMetric::Saturation | ThresholdMetric::Hue)
+    }
+}
+
+/// Layout of the threshold uniform buffer bound at `@group(1) @binding(0)` in `prelude.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ThresholdUniform {
+    min_max: [f32; 2],
+    metric: u32,
+    _padding: u32,
+}
+
+impl ThresholdUniform {
+    pub fn new(min_threshold: f32, max_threshold: f32, metric: ThresholdMetric) -> Self {
+        Self {
+            min_max: [min_threshold, max_threshold],
+            metric: metric as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// Reference implementation of `metric_value` in `metric.wgsl`, for work done on the CPU copy of
+/// the frame. `rgba` should be a pixel of a `Frame`, which is decoded the same way the GPU sees it.
+pub fn metric_value(rgba: [f32; 4], metric: ThresholdMetric) -> f32 {
+    let [r, g, b, a] = rgba;
+    let c_max = r.max(g).max(b);
+    let delta = c_max - r.min(g).min(b);
+    let v = match metric {
+        ThresholdMetric::Luminance709 => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+        ThresholdMetric::Luminance601 => 0.299 * r + 0.587 * g + 0.114 * b,
+        ThresholdMetric::Red => r,
+        ThresholdMetric::Green => g,
+        ThresholdMetric::Blue => b,
+        ThresholdMetric::Alpha => a,
+        ThresholdMetric::Value => c_max,
+        ThresholdMetric::Saturation => {
+            if c_max <= 0.0 {
+                0.0
+            } else {
+                delta / c_max
+            }
+        }
+        ThresholdMetric::Hue => {
+            if delta <= 0.0 {
+                0.0
+            } else {
+                let h = if c_max == r {
+                    (g - b) / delta
+                } else if c_max == g {
+                    (b - r) / delta + 2.0
+                } else {
+                    (r - g) / delta + 4.0
+                };
+                (h / 6.0 + 1.0).fract()
+            }
+        }
+    };
+    v * 100.0
+}
+
+/// The sRGB transfer function, mapping an encoded 0-1 value to linear light
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse of `srgb_to_linear`
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Methods for picking a threshold from the histogram of the current frame.
+///
+/// These follow the definitions used by ImageJ's auto threshold, so results should be comparable.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AutoThresholdMethod {
+    Otsu,
+    Triangle,
+    Huang,
+    Mean,
+    /// Pick the threshold so that the given percentage of pixels falls at or below it
+    Percentile(f32),
+}
+
+impl AutoThresholdMethod {
+    /// Compute a min threshold on the 0-100 metric scale
+    pub fn compute(&self, histogram: &Histogram) -> f32 {
+        let bins = histogram.bins();
+        if histogram.total() == 0 {
+            return 0.0;
+        }
+        let bin = match self {
+            AutoThresholdMethod::Otsu => otsu(bins),
+            AutoThresholdMethod::Triangle => triangle(bins),
+            AutoThresholdMethod::Huang => huang(bins),
+            AutoThresholdMethod::Mean => histogram.mean_bin().floor() as usize,
+            AutoThresholdMethod::Percentile(percentile) => {
+                let target =
+                    histogram.total() as f64 * (percentile.clamp(0.0, 100.0) as f64 / 100.0);
+                let mut cumulative = 0u64;
+                bins.iter()
+                    .position(|&count| {
+                        cumulative += count as u64;
+                        cumulative as f64 >= target
+                    })
+                    .unwrap_or(bins.len() - 1)
+            }
+        };
+        Histogram::bin_upper_edge(bin)
+    }
+}
+
+fn otsu(bins: &[u32]) -> usize {
+    let total: f64 = bins.iter().map(|&count| count as f64).sum();
+    let sum_all: f64 = bins
+        .iter()
+        .enumerate()
+        .map(|(i, &count)| i as f64 * count as f64)
+        .sum();
+
+    let mut best_bin = 0;
+    let mut best_variance = 0.0;
+    let mut weight_background = 0.0;
+    let mut sum_background = 0.0;
+    for (i, &count) in bins.iter().enumerate() {
+        weight_background += count as f64;
+        if weight_background == 0.0 {
+            continue;
+        }
+        let weight_foreground = total - weight_background;
+        if weight_foreground == 0.0 {
+            break;
+        }
+        sum_background += i as f64 * count as f64;
+        let mean_background = sum_background / weight_background;
+        let mean_foreground = (sum_all - sum_background) / weight_foreground;
+        let variance =
+            weight_background * weight_foreground * (mean_background - mean_foreground).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_bin = i;
+        }
+    }
+    best_bin
+}
+
+fn triangle(bins: &[u32]) -> usize {
+    let first = bins.iter().position(|&count| count > 0).unwrap_or(0);
+    let last = bins.iter().rposition(|&count| count > 0).unwrap_or(0);
+    let (peak, &peak_count) = bins
+        .iter()
+        .enumerate()
+        .max_by_key(|&(_, count)| count)
+        .unwrap();
+
+    // draw the line from the peak to whichever end of the histogram is further away
+    let low = first.saturating_sub(1);
+    let high = (last + 1).min(bins.len() - 1);
+    let far = if peak - low > high - peak { low } else { high };
+    if far == peak {
+        return peak;
+    }
+
+    // distance from each bin to the line, up to a constant factor
+    let span = far as f64 - peak as f64;
+    let distance =
+        |i: usize| (peak_count as f64 * (far as f64 - i as f64) - span * bins[i] as f64).abs();
+    (far.min(peak)..=far.max(peak))
+        .max_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+        .unwrap_or(peak)
+}
+
+fn huang(bins: &[u32]) -> usize {
+    let first = bins.iter().position(|&count| count > 0).unwrap_or(0);
+    let last = bins.iter().rposition(|&count| count > 0).unwrap_or(0);
+    if first == last {
+        return first;
+    }
+
+    // cumulative counts and first moments
+    let mut s = vec![0.0f64; last + 1];
+    let mut w = vec![0.0f64; last + 1];
+    s[first] = bins[first] as f64;
+    w[first] = first as f64 * bins[first] as f64;
+    for i in first + 1..=last {
+        s[i] = s[i - 1] + bins[i] as f64;
+        w[i] = w[i - 1] + i as f64 * bins[i] as f64;
+    }
+
+    // Shannon entropy of the fuzzy membership for each distance from a class mean
+    let c = (last - first) as f64;
+    let mut entropy_of_distance = vec![0.0f64; last - first + 1];
+    for (i, entropy) in entropy_of_distance.iter_mut().enumerate().skip(1) {
+        let mu = 1.0 / (1.0 + i as f64 / c);
+        *entropy = -mu * mu.ln() - (1.0 - mu) * (1.0 - mu).ln();
+    }
+
+    let class_entropy = |range: std::ops::RangeInclusive<usize>, mean: f64| {
+        let mean = mean.round() as i64;
+        range
+            .map(|i| {
+                entropy_of_distance[(i as i64 - mean).unsigned_abs() as usize] * bins[i] as f64
+            })
+            .sum::<f64>()
+    };
+
+    let mut best_bin = first;
+    let mut min_entropy = f64::MAX;
+    for t in first..last {
+        let entropy = class_entropy(first..=t, w[t] / s[t])
+            + class_entropy(t + 1..=last, (w[last] - w[t]) / (s[last] - s[t]));
+        if entropy < min_entropy {
+            min_entropy = entropy;
+            best_bin = t;
+        }
+    }
+    best_bin
+}
+
+/// The current thresholds, for deciding on the CPU which pixels `fs_main` leaves untouched
+#[derive(Copy, Clone, Debug)]
+pub struct ThresholdBand {
+    pub min: f32,
+    pub max: f32,
+    pub metric: ThresholdMetric,
+}
+
+impl ThresholdBand {
+    /// Whether a pixel with the given metric value is shown as-is rather than being thresholded
+    /// to black or white
+    pub fn contains(&self, value: f32) -> bool {
+        if self.metric == ThresholdMetric::Hue {
+            if self.min <= self.max {
+                value >= self.min && value <= self.max
+            } else {
+                value >= self.min || value <= self.max
+            }
+        } else {
+            value > self.min && value < self.max
+        }
+    }
+
+    /// Reference implementation of the thresholding in `fs_main`
+    pub fn apply(&self, rgba: [f32; 4]) -> [f32; 4] {
+        let value = metric_value(rgba, self.metric);
+        if self.metric == ThresholdMetric::Hue {
+            if self.contains(value) {
+                rgba
+            } else {
+                [0.0, 0.0, 0.0, rgba[3]]
+            }
+        } else if value <= self.min {
+            [0.0, 0.0, 0.0, rgba[3]]
+        } else if value >= self.max {
+            [1.0, 1.0, 1.0, rgba[3]]
+        } else {
+            rgba
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::{frame::Frame, histogram::HISTOGRAM_BINS};
+
+    /// A histogram with `count` gray pixels in the middle of each given bin
+    fn histogram(counts: &[(usize, u32)]) -> Histogram {
+        let values: Vec<f32> = counts
+            .iter()
+            .flat_map(|&(bin, count)| {
+                let value = (bin as f32 + 0.5) / HISTOGRAM_BINS as f32;
+                std::iter::repeat_n(value, count as usize)
+            })
+            .collect();
+        let frame = Frame::from_fn(values.len() as u32, 1, |x, _| {
+            let v = values[x as usize];
+            Rgba([v, v, v, 1.0])
+        });
+        Histogram::from_image(&frame, ThresholdMetric::Value)
+    }
+
+    #[test]
+    fn empty_histograms_threshold_at_zero() {
+        let empty = Histogram::from_image(&Frame::new(0, 0), ThresholdMetric::Value);
+        for method in [
+            AutoThresholdMethod::Otsu,
+            AutoThresholdMethod::Triangle,
+            AutoThresholdMethod::Huang,
+            AutoThresholdMethod::Mean,
+        ] {
+            assert_eq!(method.compute(&empty), 0.0);
+        }
+    }
+
+    #[test]
+    fn otsu_and_huang_split_two_peaks_at_the_first() {
+        let bimodal = histogram(&[(50, 100), (200, 100)]);
+        // every split between the peaks separates them equally well, so the lowest one is kept
+        assert_eq!(otsu(bimodal.bins()), 50);
+        assert_eq!(huang(bimodal.bins()), 50);
+        assert_eq!(
+            AutoThresholdMethod::Otsu.compute(&bimodal),
+            Histogram::bin_upper_edge(50)
+        );
+    }
+
+    #[test]
+    fn otsu_balances_unequal_peaks() {
+        // the split that maximises the between class variance puts the sparse bin 43 in with the
+        // upper peak
+        let bins = histogram(&[(40, 30), (41, 50), (42, 30), (43, 5), (44, 30), (45, 50)]);
+        assert_eq!(otsu(bins.bins()), 42);
+    }
+
+    #[test]
+    fn triangle_thresholds_just_past_the_peak_towards_the_long_tail() {
+        // a tall peak with a flat tail to the right is furthest below the line at the first tail
+        // bin
+        let mut counts = vec![(10, 100)];
+        counts.extend((11..=200).map(|bin| (bin, 1)));
+        assert_eq!(triangle(histogram(&counts).bins()), 11);
+
+        // and mirrored, with the tail to the left
+        let mut counts: Vec<_> = (55..=244).map(|bin| (bin, 1)).collect();
+        counts.push((245, 100));
+        assert_eq!(triangle(histogram(&counts).bins()), 244);
+    }
+
+    #[test]
+    fn mean_thresholds_at_the_mean_bin() {
+        let bins = histogram(&[(50, 100), (200, 100)]);
+        assert_eq!(
+            AutoThresholdMethod::Mean.compute(&bins),
+            Histogram::bin_upper_edge(125)
+        );
+        let bins = histogram(&[(10, 3), (20, 1)]);
+        // a mean of 12.5 rounds down
+        assert_eq!(
+            AutoThresholdMethod::Mean.compute(&bins),
+            Histogram::bin_upper_edge(12)
+        );
+    }
+
+    #[test]
+    fn percentile_counts_pixels_at_or_below_the_threshold() {
+        let bins = histogram(&[(50, 100), (200, 100)]);
+        assert_eq!(
+            AutoThresholdMethod::Percentile(50.0).compute(&bins),
+            Histogram::bin_upper_edge(50)
+        );
+        assert_eq!(
+            AutoThresholdMethod::Percentile(51.0).compute(&bins),
+            Histogram::bin_upper_edge(200)
+        );
+    }
+}
@@ -0,0 +1,491 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{blobs::Blob, overlay::OverlayLines};
+
+const TRACK_TAIL_COLOR: [f32; 4] = [0.2, 1.0, 0.3, 1.0];
+// finished tracks beyond this many are dropped, oldest first
+const MAX_FINISHED_TRACKS: usize = 10_000;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkingMethod {
+    /// Greedily link the closest track and blob pairs first
+    #[default]
+    NearestNeighbour,
+    /// Find the assignment that minimises the total distance
+    Hungarian,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrackingOptions {
+    pub method: LinkingMethod,
+    /// Furthest a blob can be from a track's predicted position and still be linked to it, in
+    /// pixels
+    pub max_distance: f32,
+    /// Number of frames a track can go without a blob before it is finished
+    pub max_missed_frames: u32,
+    /// Number of points in the tail drawn behind each track
+    pub tail_length: usize,
+    pub draw_tails: bool,
+}
+
+impl Default for TrackingOptions {
+    fn default() -> Self {
+        Self {
+            method: LinkingMethod::default(),
+            max_distance: 20.0,
+            max_missed_frames: 2,
+            tail_length: 20,
+            draw_tails: true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TrackExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackPoint {
+    pub frame_idx: u32,
+    pub elapsed_ms: f64,
+    pub centroid: [f32; 2],
+    pub area: u64,
+    /// Mean of the threshold metric over the blob, in the source's native units
+    pub mean_intensity: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Track {
+    pub id: u32,
+    pub points: Vec<TrackPoint>,
+    /// Velocity over the last two points, in pixels per second
+    pub velocity: [f32; 2],
+    /// Time between the first and last points
+    pub lifetime_ms: f64,
+    /// Whether the track can still be extended
+    pub active: bool,
+    #[serde(skip)]
+    missed_frames: u32,
+}
+
+/// Summary of an active track, emitted in the `tracks` event
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackState {
+    pub id: u32,
+    pub centroid: [f32; 2],
+    pub velocity: [f32; 2],
+    pub lifetime_ms: f64,
+    pub num_points: usize,
+}
+
+impl Track {
+    fn last(&self) -> &TrackPoint {
+        self.points.last().unwrap()
+    }
+
+    fn predicted_position(&self, elapsed_ms: f64) -> [f32; 2] {
+        let last = self.last();
+        let dt = ((elapsed_ms - last.elapsed_ms) / 1000.0) as f32;
+        [
+            last.centroid[0] + self.velocity[0] * dt,
+            last.centroid[1] + self.velocity[1] * dt,
+        ]
+    }
+
+    fn push(&mut self, point: TrackPoint) {
+        if let Some(last) = self.points.last() {
+            let dt = ((point.elapsed_ms - last.elapsed_ms) / 1000.0) as f32;
+            if dt > 0.0 {
+                self.velocity = [
+                    (point.centroid[0] - last.centroid[0]) / dt,
+                    (point.centroid[1] - last.centroid[1]) / dt,
+                ];
+            }
+        }
+        self.lifetime_ms = point.elapsed_ms - self.points[0].elapsed_ms;
+        self.points.push(point);
+        self.missed_frames = 0;
+    }
+
+    fn state(&self) -> TrackState {
+        TrackState {
+            id: self.id,
+            centroid: self.last().centroid,
+            velocity: self.velocity,
+            lifetime_ms: self.lifetime_ms,
+            num_points: self.points.len(),
+        }
+    }
+}
+
+/// Links the blobs in consecutive frames into tracks
+#[derive(Default)]
+pub struct Tracker {
+    tracks: Vec<Track>,
+    next_id: u32,
+}
+
+impl Tracker {
+    pub fn reset(&mut self) {
+        self.tracks.clear();
+        self.next_id = 0;
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Link the blobs of a new frame to the active tracks, starting new tracks for any blobs left
+    /// over. Returns the state of every track that is still active.
+    pub fn update(
+        &mut self,
+        options: &TrackingOptions,
+        frame_idx: u32,
+        elapsed_ms: f64,
+        blobs: &[Blob],
+    ) -> Vec<TrackState> {
+        let active: Vec<usize> = (0..self.tracks.len())
+            .filter(|&i| self.tracks[i].active)
+            .collect();
+
+        let distances: Vec<Vec<f32>> = active
+            .iter()
+            .map(|&i| {
+                let predicted = self.tracks[i].predicted_position(elapsed_ms);
+                blobs
+                    .iter()
+                    .map(|blob| {
+                        (blob.centroid[0] - predicted[0]).hypot(blob.centroid[1] - predicted[1])
+                    })
+                    .collect()
+            })
+            .collect();
+        let links = match options.method {
+            LinkingMethod::NearestNeighbour => {
+                link_nearest_neighbour(&distances, options.max_distance)
+            }
+            LinkingMethod::Hungarian => link_hungarian(&distances, options.max_distance),
+        };
+
+        let mut blob_linked = vec![false; blobs.len()];
+        for (active_idx, &track_idx) in active.iter().enumerate() {
+            let track = &mut self.tracks[track_idx];
+            match links[active_idx] {
+                Some(blob_idx) => {
+                    blob_linked[blob_idx] = true;
+                    track.push(track_point(frame_idx, elapsed_ms, &blobs[blob_idx]));
+                }
+                None => {
+                    track.missed_frames += 1;
+                    if track.missed_frames > options.max_missed_frames {
+                        track.active = false;
+                    }
+                }
+            }
+        }
+
+        for (blob, _) in blobs.iter().zip(blob_linked).filter(|(_, linked)| !linked) {
+            self.tracks.push(Track {
+                id: self.next_id,
+                points: vec![track_point(frame_idx, elapsed_ms, blob)],
+                velocity: [0.0, 0.0],
+                lifetime_ms: 0.0,
+                active: true,
+                missed_frames: 0,
+            });
+            self.next_id += 1;
+        }
+
+        let num_finished = self.tracks.iter().filter(|track| !track.active).count();
+        if num_finished > MAX_FINISHED_TRACKS {
+            let mut to_drop = num_finished - MAX_FINISHED_TRACKS;
+            self.tracks.retain(|track| {
+                let drop = !track.active && to_drop > 0;
+                if drop {
+                    to_drop -= 1;
+                }
+                !drop
+            });
+        }
+
+        self.tracks
+            .iter()
+            .filter(|track| track.active)
+            .map(Track::state)
+            .collect()
+    }
+
+    pub fn draw(&self, options: &TrackingOptions, lines: &mut OverlayLines) {
+        if !options.draw_tails {
+            return;
+        }
+        for track in self.tracks.iter().filter(|track| track.active) {
+            let tail_start = track.points.len().saturating_sub(options.tail_length);
+            let tail: Vec<[f32; 2]> = track.points[tail_start..]
+                .iter()
+                .map(|point| point.centroid)
+                .collect();
+            lines.polyline(&tail, TRACK_TAIL_COLOR);
+        }
+    }
+
+    pub fn export(&self, path: &Path, format: TrackExportFormat) -> Result<(), String> {
+        let write = || -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(path)?);
+            match format {
+                TrackExportFormat::Csv => {
+                    writeln!(
+                        writer,
+                        "track_id,frame_idx,elapsed_ms,x,y,area,mean_intensity"
+                    )?;
+                    for track in &self.tracks {
+                        for point in &track.points {
+                            writeln!(
+                                writer,
+                                "{},{},{},{},{},{},{}",
+                                track.id,
+                                point.frame_idx,
+                                point.elapsed_ms,
+                                point.centroid[0],
+                                point.centroid[1],
+                                point.area,
+                                point.mean_intensity
+                            )?;
+                        }
+                    }
+                }
+                TrackExportFormat::Json => serde_json::to_writer_pretty(&mut writer, &self.tracks)?,
+            }
+            writer.flush()
+        };
+        write().map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+fn track_point(frame_idx: u32, elapsed_ms: f64, blob: &Blob) -> TrackPoint {
+    TrackPoint {
+        frame_idx,
+        elapsed_ms,
+        centroid: blob.centroid,
+        area: blob.area,
+        mean_intensity: blob.mean_intensity,
+    }
+}
+
+/// For each track (row of `distances`), the blob it is linked to, if any
+fn link_nearest_neighbour(distances: &[Vec<f32>], max_distance: f32) -> Vec<Option<usize>> {
+    let mut pairs: Vec<(usize, usize, f32)> = distances
+        .iter()
+        .enumerate()
+        .flat_map(|(track, row)| {
+            row.iter()
+                .enumerate()
+                .map(move |(blob, &distance)| (track, blob, distance))
+        })
+        .filter(|&(_, _, distance)| distance <= max_distance)
+        .collect();
+    pairs.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let num_blobs = distances.first().map_or(0, Vec::len);
+    let mut links = vec![None; distances.len()];
+    let mut blob_taken = vec![false; num_blobs];
+    for (track, blob, _) in pairs {
+        if links[track].is_none() && !blob_taken[blob] {
+            links[track] = Some(blob);
+            blob_taken[blob] = true;
+        }
+    }
+    links
+}
+
+/// For each track (row of `distances`), the blob it is linked to, if any, minimising the total
+/// distance over all links within `max_distance`
+fn link_hungarian(distances: &[Vec<f32>], max_distance: f32) -> Vec<Option<usize>> {
+    let num_tracks = distances.len();
+    let num_blobs = distances.first().map_or(0, Vec::len);
+    let n = num_tracks.max(num_blobs);
+    if n == 0 {
+        return vec![None; num_tracks];
+    }
+
+    // pad to a square matrix. Pairs outside the gate cost more than any set of gated links could,
+    // so they are only used when there's nothing else left.
+    let gated_cost = max_distance as f64 * (n + 1) as f64 + 1.0;
+    let cost = |track: usize, blob: usize| -> f64 {
+        match distances.get(track).and_then(|row| row.get(blob)) {
+            Some(&distance) if distance <= max_distance => distance as f64,
+            Some(_) => gated_cost,
+            // dummy row or column
+            None => 0.0,
+        }
+    };
+
+    // Kuhn-Munkres with potentials, 1-indexed with column 0 as the sentinel
+    let mut u = vec![0.0f64; n + 1];
+    let mut v = vec![0.0f64; n + 1];
+    let mut row_of_col = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+    for row in 1..=n {
+        row_of_col[0] = row;
+        let mut col0 = 0;
+        let mut min_v = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[col0] = true;
+            let row0 = row_of_col[col0];
+            let mut delta = f64::INFINITY;
+            let mut col1 = 0;
+            for col in 1..=n {
+                if used[col] {
+                    continue;
+                }
+                let reduced = cost(row0 - 1, col - 1) - u[row0] - v[col];
+                if reduced < min_v[col] {
+                    min_v[col] = reduced;
+                    way[col] = col0;
+                }
+                if min_v[col] < delta {
+                    delta = min_v[col];
+                    col1 = col;
+                }
+            }
+            for col in 0..=n {
+                if used[col] {
+                    u[row_of_col[col]] += delta;
+                    v[col] -= delta;
+                } else {
+                    min_v[col] -= delta;
+                }
+            }
+            col0 = col1;
+            if row_of_col[col0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let col1 = way[col0];
+            row_of_col[col0] = row_of_col[col1];
+            col0 = col1;
+            if col0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut links = vec![None; num_tracks];
+    for (col, &row) in row_of_col.iter().enumerate().skip(1) {
+        let (track, blob) = (row - 1, col - 1);
+        if track < num_tracks && blob < num_blobs && distances[track][blob] <= max_distance {
+            links[track] = Some(blob);
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blobs::BoundingBox;
+
+    fn blob(x: f32, y: f32) -> Blob {
+        Blob {
+            label: 0,
+            area: 1,
+            centroid: [x, y],
+            bounding_box: BoundingBox {
+                x: x as u32,
+                y: y as u32,
+                width: 1,
+                height: 1,
+            },
+            mean_intensity: 100.0,
+            perimeter: 0.0,
+            circularity: 1.0,
+            physical: None,
+            outline: vec![[x, y]],
+        }
+    }
+
+    fn last_x(tracker: &Tracker, id: u32) -> f32 {
+        let track = tracker
+            .tracks()
+            .iter()
+            .find(|track| track.id == id)
+            .unwrap();
+        track.last().centroid[0]
+    }
+
+    /// Two tracks at x = 0 and 4 whose blobs next turn up at x = 3 and 9. The closest pair is the
+    /// second track and the blob at 3, which leaves the first track with the blob at 9 for a total
+    /// of 1 + 9, whereas crossing over costs 3 + 5.
+    fn crossing(method: LinkingMethod, max_distance: f32) -> Tracker {
+        let options = TrackingOptions {
+            method,
+            max_distance,
+            max_missed_frames: 0,
+            ..Default::default()
+        };
+        let mut tracker = Tracker::default();
+        tracker.update(&options, 0, 0.0, &[blob(0.0, 0.0), blob(4.0, 0.0)]);
+        tracker.update(&options, 1, 100.0, &[blob(3.0, 0.0), blob(9.0, 0.0)]);
+        tracker
+    }
+
+    #[test]
+    fn nearest_neighbour_links_the_closest_pair_first() {
+        let tracker = crossing(LinkingMethod::NearestNeighbour, 20.0);
+        assert_eq!(tracker.tracks().len(), 2);
+        assert_eq!(last_x(&tracker, 0), 9.0);
+        assert_eq!(last_x(&tracker, 1), 3.0);
+    }
+
+    #[test]
+    fn hungarian_minimises_the_total_distance() {
+        let tracker = crossing(LinkingMethod::Hungarian, 20.0);
+        assert_eq!(tracker.tracks().len(), 2);
+        assert_eq!(last_x(&tracker, 0), 3.0);
+        assert_eq!(last_x(&tracker, 1), 9.0);
+    }
+
+    #[test]
+    fn blobs_past_the_max_distance_start_new_tracks() {
+        for method in [LinkingMethod::NearestNeighbour, LinkingMethod::Hungarian] {
+            // only the second track and the blob at 3 are within 4 of each other
+            let tracker = crossing(method, 4.0);
+            let tracks = tracker.tracks();
+            assert_eq!(tracks.len(), 3, "{:?}", method);
+            assert!(!tracks[0].active);
+            assert_eq!(tracks[0].points.len(), 1);
+            assert_eq!(last_x(&tracker, 1), 3.0);
+            assert_eq!(last_x(&tracker, 2), 9.0);
+            assert_eq!(tracks[2].points[0].frame_idx, 1);
+        }
+    }
+
+    #[test]
+    fn gated_pairs_are_never_linked() {
+        let distances = vec![vec![1.0, 30.0], vec![2.0, 40.0]];
+        assert_eq!(link_nearest_neighbour(&distances, 20.0), [Some(0), None]);
+        assert_eq!(link_hungarian(&distances, 20.0), [Some(0), None]);
+        // more blobs than tracks and the other way round
+        assert_eq!(link_hungarian(&[vec![5.0, 1.0, 3.0]], 20.0), [Some(1)]);
+        assert_eq!(
+            link_hungarian(&[vec![5.0], vec![1.0], vec![3.0]], 20.0),
+            [None, Some(0), None]
+        );
+    }
+}
@@ -1,7 +1,30 @@
-import { useState } from "react";
+import { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import FilterChain from "./FilterChain";
+import Histogram from "./Histogram";
+import ShaderParams from "./ShaderParams";
 import "./App.css";
 
+const BACKGROUND_MODELS = {
+  off: null,
+  runningAverage: { type: "runningAverage", alpha: 0.05 },
+  median: { type: "median", frames: 15 },
+};
+
+const TEMPORAL_FILTERS = {
+  off: null,
+  rollingMean: { type: "rollingMean", frames: 8 },
+  exponentialSmoothing: { type: "exponentialSmoothing", alpha: 0.2 },
+  rollingMax: { type: "rollingMax", frames: 8 },
+  rollingMin: { type: "rollingMin", frames: 8 },
+};
+
+function formatShaderError(error) {
+  const location = error.line ? ` (line ${error.line}, column ${error.column})` : "";
+  return `${error.name}${location}: ${error.message}`;
+}
+
 async function startLiveView() {
   await invoke("start_live_view");
 }
@@ -15,6 +38,113 @@ function App() {
   const [greetMsg, setGreetMsg] = useState("");
   const [name, setName] = useState("");
   const [liveViewRunning, setLiveViewRunning] = useState(false);
+  const [minThreshold, setMinThreshold] = useState("");
+  // full scale of the source in its native units, e.g. 65535 for 16 bit frames
+  const [sampleMax, setSampleMax] = useState(100);
+  const [colorSpace, setColorSpace] = useState("srgb");
+  const [backgroundModel, setBackgroundModel] = useState("off");
+  const [displayMin, setDisplayMin] = useState("");
+  const [displayMax, setDisplayMax] = useState("");
+  const [autoThresholdMethod, setAutoThresholdMethod] = useState("otsu");
+  const [autoThresholdLive, setAutoThresholdLive] = useState(false);
+  const [shaders, setShaders] = useState([]);
+  const [shaderError, setShaderError] = useState("");
+  const [recordingError, setRecordingError] = useState("");
+  const [pixelSize, setPixelSize] = useState("");
+  const [pixelUnit, setPixelUnit] = useState("micrometers");
+  // remounts the params controls whenever the shader changes
+  const [shaderVersion, setShaderVersion] = useState(0);
+
+  useEffect(() => {
+    invoke("set_histogram_rate", { newHistogramRate: 10 });
+  }, []);
+
+  useEffect(() => {
+    invoke("list_shaders").then(setShaders);
+    const unlistenError = listen("shader-error", (event) => setShaderError(formatShaderError(event.payload)));
+    const unlistenReloaded = listen("shader-reloaded", () => {
+      setShaderError("");
+      setShaderVersion((version) => version + 1);
+    });
+    return () => {
+      unlistenError.then((f) => f());
+      unlistenReloaded.then((f) => f());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen("recording-error", (event) => setRecordingError(event.payload));
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, []);
+
+  function refreshSampleRange() {
+    invoke("get_sample_range").then((range) => {
+      setSampleMax(range.max);
+      setColorSpace(range.colorSpace);
+    });
+  }
+
+  useEffect(refreshSampleRange, []);
+
+  useEffect(() => {
+    invoke("get_spatial_calibration").then((calibration) => {
+      if (calibration) {
+        setPixelSize(String(calibration.pixelWidth));
+        setPixelUnit(calibration.unit);
+      }
+    });
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen("auto-threshold", (event) => setMinThreshold(String(event.payload)));
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, []);
+
+  async function autoThreshold() {
+    const threshold = await invoke("auto_threshold", {
+      method: autoThresholdMethod,
+      recomputeEachFrame: autoThresholdLive,
+    });
+    setMinThreshold(String(threshold));
+  }
+
+  function setDisplayRange(min, max) {
+    setDisplayMin(min);
+    setDisplayMax(max);
+    const range = [min === "" ? 0 : parseFloat(min), max === "" ? sampleMax : parseFloat(max)];
+    if (min === "" && max === "") {
+      invoke("set_display_range", { range: null });
+    } else if (range[0] < range[1]) {
+      invoke("set_display_range", { range });
+    }
+  }
+
+  async function autoDisplayRange() {
+    const [min, max] = await invoke("auto_display_range");
+    setDisplayMin(String(min));
+    setDisplayMax(String(max));
+  }
+
+  async function setShader(name) {
+    try {
+      await invoke("set_shader", { name: name || null });
+      setShaderError("");
+      setShaderVersion((version) => version + 1);
+    } catch (e) {
+      setShaderError(formatShaderError(e));
+    }
+  }
+
+  function setSpatialCalibration(size, unit) {
+    const pixelWidth = parseFloat(size);
+    invoke("set_spatial_calibration", {
+      newSpatialCalibration: pixelWidth > 0 ? { unit, pixelWidth, pixelHeight: pixelWidth } : null,
+    });
+  }
 
   async function greet() {
     // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
@@ -39,13 +169,22 @@ function App() {
       <div className="row" style={{height: "300px"}}>
       </div>
 
+      <div class="row">
+        <Histogram />
+      </div>
+
       <button onClick={onLiveViewClick}>{liveViewBtnText}</button>
+      {recordingError && <pre>{recordingError}</pre>}
 
         <div class="row">
           <h2>Min:</h2>
           <input
             id="min-video-threshold"
-            onChange={(e) => invoke("set_min_threshold", { newMinThreshold: parseInt(e.currentTarget.value)})}
+            value={minThreshold}
+            onChange={(e) => {
+              setMinThreshold(e.currentTarget.value);
+              invoke("set_min_threshold", { newMinThreshold: parseFloat(e.currentTarget.value)});
+            }}
             placeholder="0"
           />
         </div>
@@ -53,10 +192,188 @@ function App() {
           <h2>Max:</h2>
           <input
             id="max-video-threshold"
-            onChange={(e) => invoke("set_max_threshold", { newMaxThreshold: parseInt(e.currentTarget.value)})}
-            placeholder="100"
+            onChange={(e) => invoke("set_max_threshold", { newMaxThreshold: parseFloat(e.currentTarget.value)})}
+            placeholder={String(sampleMax)}
           />
         </div>
+        <div class="row">
+          <h2>Colour Space:</h2>
+          <select
+            id="color-space"
+            value={colorSpace}
+            onChange={(e) => {
+              setColorSpace(e.currentTarget.value);
+              // the thresholds and display range are converted, so their units change with it
+              invoke("set_color_space", { colorSpace: e.currentTarget.value }).finally(refreshSampleRange);
+            }}
+          >
+            <option value="srgb">sRGB</option>
+            <option value="linear">Linear</option>
+            <option value="raw">Raw Sensor Data</option>
+          </select>
+        </div>
+        <div class="row">
+          <h2>Display:</h2>
+          <input
+            id="display-min"
+            value={displayMin}
+            onChange={(e) => setDisplayRange(e.currentTarget.value, displayMax)}
+            placeholder="0"
+          />
+          <input
+            id="display-max"
+            value={displayMax}
+            onChange={(e) => setDisplayRange(displayMin, e.currentTarget.value)}
+            placeholder={String(sampleMax)}
+          />
+          <button onClick={autoDisplayRange}>Auto</button>
+        </div>
+        <div class="row">
+          <h2>Metric:</h2>
+          <select
+            id="threshold-metric"
+            onChange={(e) => invoke("set_threshold_metric", { newThresholdMetric: e.currentTarget.value })}
+          >
+            <option value="luminance709">Luminance (Rec.709)</option>
+            <option value="luminance601">Luminance (Rec.601)</option>
+            <option value="red">Red</option>
+            <option value="green">Green</option>
+            <option value="blue">Blue</option>
+            <option value="alpha">Alpha</option>
+            <option value="value">HSV Value</option>
+            <option value="saturation">Saturation</option>
+            <option value="hue">Hue Range</option>
+          </select>
+        </div>
+        <div class="row">
+          <select
+            id="auto-threshold-method"
+            value={autoThresholdMethod}
+            onChange={(e) => setAutoThresholdMethod(e.currentTarget.value)}
+          >
+            <option value="otsu">Otsu</option>
+            <option value="triangle">Triangle</option>
+            <option value="huang">Huang</option>
+            <option value="mean">Mean</option>
+          </select>
+          <label>
+            <input
+              type="checkbox"
+              checked={autoThresholdLive}
+              onChange={(e) => setAutoThresholdLive(e.currentTarget.checked)}
+            />
+            Every frame
+          </label>
+          <button onClick={autoThreshold}>Auto Threshold</button>
+        </div>
+        <div class="row">
+          <h2>Difference:</h2>
+          <select
+            id="difference-mode"
+            onChange={(e) => invoke("set_difference_mode", { newDifferenceMode: e.currentTarget.value })}
+          >
+            <option value="off">Off</option>
+            <option value="absolute">Absolute</option>
+            <option value="signed">Signed</option>
+          </select>
+        </div>
+        <div class="row">
+          <h2>Background:</h2>
+          <select
+            id="background-model"
+            value={backgroundModel}
+            onChange={(e) => {
+              setBackgroundModel(e.currentTarget.value);
+              invoke("set_background_model", { newBackgroundModel: BACKGROUND_MODELS[e.currentTarget.value] });
+            }}
+          >
+            <option value="off">Off</option>
+            <option value="runningAverage">Running Average</option>
+            <option value="median">Median</option>
+          </select>
+          <button
+            onClick={async () => {
+              await invoke("capture_background");
+              // capturing turns subtraction on with the default running average
+              if (backgroundModel === "off") setBackgroundModel("runningAverage");
+            }}
+          >
+            Capture
+          </button>
+          <button onClick={() => invoke("reset_background")}>Reset</button>
+        </div>
+        <div class="row">
+          <h2>Flat Field:</h2>
+          <label>
+            <input
+              type="checkbox"
+              defaultChecked
+              onChange={(e) => invoke("set_flat_field_enabled", { enabled: e.currentTarget.checked })}
+            />
+            On
+          </label>
+          <button onClick={() => invoke("capture_calibration_frame", { frame: "dark" })}>Capture Dark</button>
+          <button onClick={() => invoke("capture_calibration_frame", { frame: "flat" })}>Capture Flat</button>
+          <button onClick={() => invoke("clear_calibration_frame", { frame: "dark" })}>Clear Dark</button>
+          <button onClick={() => invoke("clear_calibration_frame", { frame: "flat" })}>Clear Flat</button>
+        </div>
+        <div class="row">
+          <h2>Temporal:</h2>
+          <select
+            id="temporal-filter"
+            onChange={(e) => invoke("set_temporal_filter", { newTemporalFilter: TEMPORAL_FILTERS[e.currentTarget.value] })}
+          >
+            <option value="off">Off</option>
+            <option value="rollingMean">Rolling Mean</option>
+            <option value="exponentialSmoothing">Exponential Smoothing</option>
+            <option value="rollingMax">Rolling Max</option>
+            <option value="rollingMin">Rolling Min</option>
+          </select>
+          <button onClick={() => invoke("reset_temporal_filter")}>Reset</button>
+        </div>
+        <div class="row">
+          <h2>Pixel Size:</h2>
+          <input
+            id="pixel-size"
+            value={pixelSize}
+            onChange={(e) => {
+              setPixelSize(e.currentTarget.value);
+              setSpatialCalibration(e.currentTarget.value, pixelUnit);
+            }}
+            placeholder="uncalibrated"
+          />
+          <select
+            value={pixelUnit}
+            onChange={(e) => {
+              setPixelUnit(e.currentTarget.value);
+              setSpatialCalibration(pixelSize, e.currentTarget.value);
+            }}
+          >
+            <option value="micrometers">µm</option>
+            <option value="millimeters">mm</option>
+          </select>
+          <label>
+            <input
+              type="checkbox"
+              defaultChecked
+              onChange={(e) => invoke("set_scale_bar", { visible: e.currentTarget.checked })}
+            />
+            Scale bar
+          </label>
+        </div>
+        <div class="row">
+          <h2>Shader:</h2>
+          <select id="shader" onChange={(e) => setShader(e.currentTarget.value)}>
+            <option value="">Built-in</option>
+            {shaders.map((name) => (
+              <option key={name} value={name}>{name}</option>
+            ))}
+          </select>
+        </div>
+        {shaderError && <pre>{shaderError}</pre>}
+        <ShaderParams key={shaderVersion} />
+        <h2>Filters:</h2>
+        <FilterChain />
     </div>
   );
 }
@@ -0,0 +1,78 @@
+import { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+
+const STAGE_DEFAULTS = {
+  gaussianBlur: { type: "gaussianBlur", sigma: 1.5 },
+  unsharpMask: { type: "unsharpMask", sigma: 1.5, amount: 1 },
+  median: { type: "median", size: 3 },
+  sobel: { type: "sobel" },
+  laplacian: { type: "laplacian" },
+  open: { type: "open", radius: 1 },
+  close: { type: "close", radius: 1 },
+};
+
+function FilterChain() {
+  const [stages, setStages] = useState([]);
+  const [newStageType, setNewStageType] = useState("gaussianBlur");
+  const [error, setError] = useState("");
+
+  useEffect(() => {
+    invoke("get_filter_chain").then(setStages);
+  }, []);
+
+  async function updateStages(newStages) {
+    try {
+      await invoke("set_filter_chain", { newFilterChain: newStages });
+      setStages(newStages);
+      setError("");
+    } catch (e) {
+      setError(String(e));
+    }
+  }
+
+  function moveStage(i, offset) {
+    const newStages = [...stages];
+    [newStages[i], newStages[i + offset]] = [newStages[i + offset], newStages[i]];
+    updateStages(newStages);
+  }
+
+  function setParam(i, key, value) {
+    updateStages(stages.map((stage, j) => (j === i ? { ...stage, [key]: parseFloat(value) } : stage)));
+  }
+
+  return (
+    <div>
+      {stages.map((stage, i) => (
+        <div class="row" key={i}>
+          <span>{stage.type}</span>
+          {Object.entries(stage)
+            .filter(([key]) => key !== "type")
+            .map(([key, value]) => (
+              <label key={key}>
+                {key}
+                <input type="number" step="0.1" value={value} onChange={(e) => setParam(i, key, e.currentTarget.value)} />
+              </label>
+            ))}
+          <button disabled={i === 0} onClick={() => moveStage(i, -1)}>Up</button>
+          <button disabled={i === stages.length - 1} onClick={() => moveStage(i, 1)}>Down</button>
+          <button onClick={() => updateStages(stages.filter((_, j) => j !== i))}>Remove</button>
+        </div>
+      ))}
+      <div class="row">
+        <select value={newStageType} onChange={(e) => setNewStageType(e.currentTarget.value)}>
+          <option value="gaussianBlur">Gaussian Blur</option>
+          <option value="unsharpMask">Unsharp Mask</option>
+          <option value="median">Median</option>
+          <option value="sobel">Sobel</option>
+          <option value="laplacian">Laplacian</option>
+          <option value="open">Open</option>
+          <option value="close">Close</option>
+        </select>
+        <button onClick={() => updateStages([...stages, STAGE_DEFAULTS[newStageType]])}>Add Filter</button>
+      </div>
+      {error && <p>{error}</p>}
+    </div>
+  );
+}
+
+export default FilterChain;
@@ -0,0 +1,44 @@
+import { useEffect, useRef } from "react";
+import { listen } from "@tauri-apps/api/event";
+
+const CHANNEL_COLORS = { values: "#0f0f0f", red: "#d62728", green: "#2ca02c", blue: "#1f77b4" };
+
+function Histogram({ width = 256, height = 120 }) {
+  const canvasRef = useRef(null);
+
+  useEffect(() => {
+    const unlisten = listen("histogram", (event) => {
+      const ctx = canvasRef.current?.getContext("2d");
+      if (!ctx) {
+        return;
+      }
+      ctx.clearRect(0, 0, width, height);
+      for (const [channel, color] of Object.entries(CHANNEL_COLORS)) {
+        const bins = event.payload[channel];
+        if (!bins) {
+          continue;
+        }
+        const max = Math.max(...bins, 1);
+        ctx.strokeStyle = color;
+        ctx.beginPath();
+        bins.forEach((count, i) => {
+          const x = (i / bins.length) * width;
+          const y = height - (count / max) * height;
+          if (i === 0) {
+            ctx.moveTo(x, y);
+          } else {
+            ctx.lineTo(x, y);
+          }
+        });
+        ctx.stroke();
+      }
+    });
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, [width, height]);
+
+  return <canvas ref={canvasRef} width={width} height={height} style={{ background: "#f6f6f6" }} />;
+}
+
+export default Histogram;
@@ -0,0 +1,49 @@
+import { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+
+function ShaderParams() {
+  const [params, setParams] = useState([]);
+  const [error, setError] = useState("");
+
+  useEffect(() => {
+    invoke("get_shader_params").then(setParams);
+  }, []);
+
+  async function setComponent(param, component, value) {
+    const newValue = param.value.map((v, i) => (i === component ? parseFloat(value) : v));
+    try {
+      await invoke("set_shader_param", {
+        name: param.name,
+        value: param.components === 1 ? newValue[0] : newValue,
+      });
+      setParams(params.map((p) => (p.name === param.name ? { ...p, value: newValue } : p)));
+      setError("");
+    } catch (e) {
+      setError(String(e));
+    }
+  }
+
+  return (
+    <div>
+      {params.map((param) => (
+        <div class="row" key={param.name}>
+          <span>{param.name}</span>
+          {param.value.map((value, i) => (
+            <input
+              key={i}
+              type={param.min !== null && param.max !== null ? "range" : "number"}
+              min={param.min ?? undefined}
+              max={param.max ?? undefined}
+              step={param.step ?? (param.type === "f32" ? 0.01 : 1)}
+              value={value}
+              onChange={(e) => setComponent(param, i, e.currentTarget.value)}
+            />
+          ))}
+        </div>
+      ))}
+      {error && <p>{error}</p>}
+    </div>
+  );
+}
+
+export default ShaderParams;